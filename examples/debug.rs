@@ -0,0 +1,49 @@
+//! Runs an execution environment under the console debugger, reading
+//! commands from stdin.
+//!
+//! ```text
+//! cargo run --example debug -- <ee.wasm> [block data file]
+//! ```
+
+use ewasm::{Console, Execute, RootRuntime};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: debug <ee.wasm> [block data file]");
+        process::exit(2);
+    }
+
+    let code = fs::read(&args[0]).expect("EE to be readable");
+    let data = match args.get(1) {
+        Some(path) => fs::read(path).expect("block data to be readable"),
+        None => Vec::new(),
+    };
+
+    let stdin = io::stdin();
+    let console = Console::new(stdin.lock(), io::stdout());
+
+    let mut runtime = RootRuntime::builder(&code, &data, [0u8; 32])
+        .debugger(console)
+        .backtraces(true)
+        .logger(|message| println!("{}", message))
+        .build()
+        .expect("code to be a valid root");
+
+    match runtime.execute() {
+        Ok(post_root) => {
+            let hex: String = post_root.iter().map(|b| format!("{:02x}", b)).collect();
+            println!("post state root: {}", hex);
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Splits the block data into 64 byte chunks, and stores the second half of
+//! each chunk in the buffer under the first half. The post-state root is the
+//! value stored under the lowest key, xor the pre-state root.
+#![cfg_attr(target_arch = "wasm32", no_std)]
+
+use ewasm_sdk::{block_data_copy, block_data_size, buffer, ee_main, pre_state_root};
+
+const FRAME: u32 = 0;
+
+#[ee_main]
+fn main() -> [u8; 32] {
+    let mut key = [0; 32];
+    let mut value = [0; 32];
+
+    for chunk in 0..block_data_size() / 64 {
+        let offset = chunk as u32 * 64;
+
+        block_data_copy(&mut key, offset);
+        block_data_copy(&mut value, offset + 32);
+        buffer::set(FRAME, &key, &value);
+    }
+
+    let mut lowest = [[0; 32]];
+    if buffer::keys(FRAME, &mut lowest) == 0 {
+        return pre_state_root();
+    }
+
+    let mut root = buffer::get(FRAME, &lowest[0]).unwrap_or_default();
+    for (byte, pre) in root.iter_mut().zip(pre_state_root().iter()) {
+        *byte ^= pre;
+    }
+
+    root
+}
@@ -0,0 +1,20 @@
+//! A child module for the `modules` example. `echo` returns its argument, and
+//! `double` takes and returns a typed value.
+#![cfg_attr(target_arch = "wasm32", no_std)]
+
+use ewasm_sdk::{argument, return_data};
+
+#[no_mangle]
+pub extern "C" fn echo() -> i32 {
+    let mut data = [0; 64];
+    let len = argument(&mut data).min(data.len());
+
+    return_data(&data[..len]);
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn double(value: i32) -> i32 {
+    value.wrapping_mul(2)
+}
@@ -0,0 +1,27 @@
+//! Loads the child module passed as block data into slot 0 and calls it. The
+//! post-state root holds what `echo` returned for `hello`, followed by the
+//! result of `double(21)` in the last byte.
+#![cfg_attr(target_arch = "wasm32", no_std)]
+
+use ewasm_sdk::{
+    block_data_copy, block_data_size, call_module, call_module_typed, ee_main, load_module, Value,
+};
+
+#[ee_main]
+fn main() -> [u8; 32] {
+    let mut code = [0; 64 * 1024];
+    let code = &mut code[..block_data_size()];
+
+    block_data_copy(code, 0);
+    load_module(0, code);
+
+    let mut root = [0; 32];
+    call_module(0, "echo", b"hello", &mut root[..5]);
+
+    let mut results = [Value::default()];
+    if call_module_typed(0, "double", &[Value::i32(21)], &mut results) == Some(1) {
+        root[31] = results[0].as_i32().unwrap_or_default() as u8;
+    }
+
+    root
+}
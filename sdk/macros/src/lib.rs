@@ -0,0 +1,75 @@
+//! Procedural macros for `ewasm-sdk`. Use them through the re-exports in that
+//! crate.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Error, ItemFn};
+
+/// Marks the entry point of an execution environment.
+///
+/// The function takes no arguments and returns either `()` or the 32 byte
+/// post-state root, which is saved with `eth2_savePostStateRoot`. On wasm32
+/// it is exported as `main`, the export the runtime executes.
+///
+/// ```ignore
+/// #[ewasm_sdk::ee_main]
+/// fn main() -> [u8; 32] {
+///     ewasm_sdk::pre_state_root()
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn ee_main(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+
+    match expand(attr.into(), function) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(
+    attr: proc_macro2::TokenStream,
+    function: ItemFn,
+) -> Result<proc_macro2::TokenStream, Error> {
+    if !attr.is_empty() {
+        return Err(Error::new(attr.span(), "ee_main takes no arguments"));
+    }
+
+    let signature = &function.sig;
+
+    if !signature.inputs.is_empty() {
+        return Err(Error::new(
+            signature.inputs.span(),
+            "the entry point of an EE takes no arguments",
+        ));
+    }
+
+    if !signature.generics.params.is_empty() || signature.generics.where_clause.is_some() {
+        return Err(Error::new(
+            signature.generics.span(),
+            "the entry point of an EE can't be generic",
+        ));
+    }
+
+    if let Some(asyncness) = signature.asyncness {
+        return Err(Error::new(
+            asyncness.span(),
+            "the entry point of an EE can't be async",
+        ));
+    }
+
+    let name = &signature.ident;
+
+    Ok(quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        #[cfg_attr(target_arch = "wasm32", export_name = "main")]
+        pub extern "C" fn __ewasm_main() {
+            ::ewasm_sdk::MainResult::finish(#name())
+        }
+    })
+}
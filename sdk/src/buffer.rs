@@ -0,0 +1,142 @@
+//! The buffer: frames of 32 byte values stored under 32 byte keys, shared by
+//! the root and the children granted the `BUFFER` capability.
+
+use crate::sys::env as sys;
+
+/// Returns the value stored under `key` in `frame`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferGet(frame: u32, key_offset: u32, value_offset: u32) -> u32
+/// ```
+pub fn get(frame: u32, key: &[u8; 32]) -> Option<[u8; 32]> {
+    let mut value = [0; 32];
+
+    match unsafe { sys::eth2_bufferGet(frame, key.as_ptr(), value.as_mut_ptr()) } {
+        0 => Some(value),
+        _ => None,
+    }
+}
+
+/// Stores `value` under `key` in `frame`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferSet(frame: u32, key_offset: u32, value_offset: u32) -> ()
+/// ```
+pub fn set(frame: u32, key: &[u8; 32], value: &[u8; 32]) {
+    unsafe { sys::eth2_bufferSet(frame, key.as_ptr(), value.as_ptr()) }
+}
+
+/// Merges frame `b` into frame `a`, overwriting values in `a`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferMerge(a: u32, b: u32) -> ()
+/// ```
+pub fn merge(a: u32, b: u32) {
+    unsafe { sys::eth2_bufferMerge(a, b) }
+}
+
+/// Merges frame `b` into frame `a`, keeping any value already in `a`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferMergeMissing(a: u32, b: u32) -> ()
+/// ```
+pub fn merge_missing(a: u32, b: u32) {
+    unsafe { sys::eth2_bufferMergeMissing(a, b) }
+}
+
+/// Merges frame `b` into frame `a`, skipping keys present in both frames with
+/// different values. As many of the skipped keys as fit are copied into
+/// `conflicts`. Returns the number of skipped keys, so zero means the merge
+/// had no conflicts.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferMergeChecked(a: u32, b: u32, dest_offset: u32, dest_length: u32) -> u32
+/// ```
+pub fn merge_checked(a: u32, b: u32, conflicts: &mut [[u8; 32]]) -> usize {
+    let len = unsafe {
+        sys::eth2_bufferMergeChecked(
+            a,
+            b,
+            conflicts.as_mut_ptr() as *mut u8,
+            (conflicts.len() * 32) as u32,
+        )
+    };
+
+    len as usize / 32
+}
+
+/// Removes every entry of `frame`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferClear(frame: u32) -> ()
+/// ```
+pub fn clear(frame: u32) {
+    unsafe { sys::eth2_bufferClear(frame) }
+}
+
+/// Returns the number of entries in `frame`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferCount(frame: u32) -> u32
+/// ```
+pub fn count(frame: u32) -> usize {
+    unsafe { sys::eth2_bufferCount(frame) as usize }
+}
+
+/// Copies as many keys of `frame` as fit into `dest`, in ascending order.
+/// Returns the number of keys in the frame.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferKeys(frame: u32, dest_offset: u32, dest_length: u32) -> u32
+/// ```
+pub fn keys(frame: u32, dest: &mut [[u8; 32]]) -> usize {
+    let len = unsafe {
+        sys::eth2_bufferKeys(
+            frame,
+            dest.as_mut_ptr() as *mut u8,
+            (dest.len() * 32) as u32,
+        )
+    };
+
+    len as usize / 32
+}
+
+/// Removes the value stored under `key` from `frame`. Returns whether there
+/// was a value to remove.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferDelete(frame: u32, key_offset: u32) -> u32
+/// ```
+pub fn delete(frame: u32, key: &[u8; 32]) -> bool {
+    unsafe { sys::eth2_bufferDelete(frame, key.as_ptr()) == 0 }
+}
+
+/// Copies every entry of `src` into the empty frame `dest`. Returns `false`,
+/// copying nothing, if `dest` already has entries.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_bufferCopy(src: u32, dest: u32) -> u32
+/// ```
+pub fn copy(src: u32, dest: u32) -> bool {
+    unsafe { sys::eth2_bufferCopy(src, dest) == 0 }
+}
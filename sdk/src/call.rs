@@ -0,0 +1,267 @@
+use crate::sys::env as sys;
+
+/// An i32 or i64 passed to or returned from a typed call.
+///
+/// The layout is the runtime's packed encoding: a one byte type tag followed
+/// by the value as a little endian i64, so slices of values are passed to the
+/// host without copying. wasm32 is little endian.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct Value {
+    tag: u8,
+    bits: i64,
+}
+
+/// Returned by the call functions in place of the callee's return code when
+/// the callee trapped. The runtime treats a callee returning this value as
+/// having trapped.
+pub const TRAPPED: i32 = i32::MIN;
+
+const I32_TAG: u8 = 0x7f;
+const I64_TAG: u8 = 0x7e;
+
+impl Value {
+    pub fn i32(value: i32) -> Self {
+        Value {
+            tag: I32_TAG,
+            bits: i64::from(value),
+        }
+    }
+
+    pub fn i64(value: i64) -> Self {
+        Value {
+            tag: I64_TAG,
+            bits: value,
+        }
+    }
+
+    pub fn as_i32(self) -> Option<i32> {
+        match self.tag {
+            I32_TAG => Some(self.bits as i32),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(self) -> Option<i64> {
+        match self.tag {
+            I64_TAG => Some(self.bits),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::i32(0)
+    }
+}
+
+impl core::fmt::Debug for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let bits = self.bits;
+
+        match self.tag {
+            I32_TAG => write!(f, "I32({})", bits as i32),
+            I64_TAG => write!(f, "I64({})", bits),
+            tag => write!(f, "Invalid({:#x}, {})", tag, bits),
+        }
+    }
+}
+
+/// Copies as much of the argument of the current call as fits into `dest`.
+/// Returns the length of the whole argument.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_argument(dest_offset: u32, dest_length: u32) -> u32
+/// ```
+pub fn argument(dest: &mut [u8]) -> usize {
+    unsafe { sys::eth2_argument(dest.as_mut_ptr(), dest.len() as u32) as usize }
+}
+
+/// Copies as much of `data` as fits into the return buffer of the current
+/// call. Returns the length of the return buffer.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_return(offset: u32, length: u32) -> u32
+/// ```
+pub fn return_data(data: &[u8]) -> usize {
+    unsafe { sys::eth2_return(data.as_ptr(), data.len() as u32) as usize }
+}
+
+/// Calls the function `name` exposed by the root, passing `argument` and
+/// receiving up to `ret.len()` bytes of return data. Returns the callee's
+/// return code, or [`TRAPPED`] if it trapped. Only available to children.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_call(
+///     name_offset: u32,
+///     name_length: u32,
+///     argument_offset: u32,
+///     argument_length: u32,
+///     return_offset: u32,
+///     return_length: u32,
+/// ) -> u32
+/// ```
+pub fn call(name: &str, argument: &[u8], ret: &mut [u8]) -> i32 {
+    unsafe {
+        sys::eth2_call(
+            name.as_ptr(),
+            name.len() as u32,
+            argument.as_ptr(),
+            argument.len() as u32,
+            ret.as_mut_ptr(),
+            ret.len() as u32,
+        )
+    }
+}
+
+/// Calls the function `name` exposed by the root with typed `params`,
+/// writing up to `results.len()` results. Returns the number of results the
+/// callee produced, or `None` if it trapped. A typed callee can only fail by
+/// trapping. Only available to children.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_callTyped(
+///     name_offset: u32,
+///     name_length: u32,
+///     params_offset: u32,
+///     params_count: u32,
+///     results_offset: u32,
+///     results_count: u32,
+/// ) -> i32
+/// ```
+pub fn call_typed(name: &str, params: &[Value], results: &mut [Value]) -> Option<usize> {
+    let count = unsafe {
+        sys::eth2_callTyped(
+            name.as_ptr(),
+            name.len() as u32,
+            params.as_ptr() as *const u8,
+            params.len() as u32,
+            results.as_mut_ptr() as *mut u8,
+            results.len() as u32,
+        )
+    };
+
+    result_count(count)
+}
+
+/// Calls the function `name` of the module in `slot`, passing `argument` and
+/// receiving up to `ret.len()` bytes of return data. Returns the callee's
+/// return code, or [`TRAPPED`] if it trapped. Changes made by the callee are
+/// rolled back if it returns a negative code or traps.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_callModule(
+///     slot: u32,
+///     name_offset: u32,
+///     name_length: u32,
+///     argument_offset: u32,
+///     argument_length: u32,
+///     return_offset: u32,
+///     return_length: u32,
+/// ) -> u32
+/// ```
+pub fn call_module(slot: u32, name: &str, argument: &[u8], ret: &mut [u8]) -> i32 {
+    unsafe {
+        sys::eth2_callModule(
+            slot,
+            name.as_ptr(),
+            name.len() as u32,
+            argument.as_ptr(),
+            argument.len() as u32,
+            ret.as_mut_ptr(),
+            ret.len() as u32,
+        )
+    }
+}
+
+/// Calls the function `name` of the module in `slot` with typed `params`,
+/// writing up to `results.len()` results. Returns the number of results the
+/// callee produced, or `None` if it trapped, in which case its changes are
+/// rolled back. A typed callee can only fail by trapping. Traps if `params`
+/// don't match the callee's parameters.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_callModuleTyped(
+///     slot: u32,
+///     name_offset: u32,
+///     name_length: u32,
+///     params_offset: u32,
+///     params_count: u32,
+///     results_offset: u32,
+///     results_count: u32,
+/// ) -> i32
+/// ```
+pub fn call_module_typed(
+    slot: u32,
+    name: &str,
+    params: &[Value],
+    results: &mut [Value],
+) -> Option<usize> {
+    let count = unsafe {
+        sys::eth2_callModuleTyped(
+            slot,
+            name.as_ptr(),
+            name.len() as u32,
+            params.as_ptr() as *const u8,
+            params.len() as u32,
+            results.as_mut_ptr() as *mut u8,
+            results.len() as u32,
+        )
+    };
+
+    result_count(count)
+}
+
+fn result_count(count: i32) -> Option<usize> {
+    match count {
+        TRAPPED => None,
+        count => Some(count as usize),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::mem;
+
+    fn encode(value: Value) -> [u8; 9] {
+        unsafe { mem::transmute(value) }
+    }
+
+    #[test]
+    fn runtime_encoding() {
+        assert_eq!(mem::size_of::<Value>(), 9);
+
+        assert_eq!(
+            encode(Value::i32(-2)),
+            [0x7f, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            encode(Value::i64(0x0102)),
+            [0x7e, 0x02, 0x01, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        assert_eq!(Value::i32(7).as_i32(), Some(7));
+        assert_eq!(Value::i32(7).as_i64(), None);
+        assert_eq!(Value::i64(-7).as_i64(), Some(-7));
+        assert_eq!(Value::i64(-7).as_i32(), None);
+        assert_eq!(format!("{:?}", Value::i32(-1)), "I32(-1)");
+    }
+}
@@ -0,0 +1,33 @@
+//! The `debug` namespace. A module importing any of these functions can only
+//! be instantiated by a runtime built with debugging enabled.
+
+use crate::sys::debug as sys;
+
+/// Logs `bytes` as text.
+pub fn print_mem(bytes: &[u8]) {
+    unsafe { sys::debug_printMem(bytes.as_ptr(), bytes.len() as u32) }
+}
+
+/// Logs `bytes` as hex.
+pub fn print_mem_hex(bytes: &[u8]) {
+    unsafe { sys::debug_printMemHex(bytes.as_ptr(), bytes.len() as u32) }
+}
+
+pub fn print_i32(value: i32) {
+    unsafe { sys::debug_printI32(value) }
+}
+
+pub fn print_i64(value: i64) {
+    unsafe { sys::debug_printI64(value) }
+}
+
+/// Logs every entry of a buffer frame.
+pub fn print_buffer(frame: u32) {
+    unsafe { sys::debug_printBuffer(frame) }
+}
+
+/// Stops execution with a trap carrying `message`.
+pub fn abort(message: &str) -> ! {
+    unsafe { sys::debug_abort(message.as_ptr(), message.len() as u32) };
+    unreachable!("debug_abort returned")
+}
@@ -0,0 +1,97 @@
+//! Safe wrappers for the host functions of the ewasm runtime, for writing
+//! execution environments (EEs) in Rust.
+//!
+//! An EE is a `no_std` `cdylib` built for `wasm32-unknown-unknown`, with its
+//! entry point marked by [`ee_main`]:
+//!
+//! ```ignore
+//! #![no_std]
+//!
+//! #[ewasm_sdk::ee_main]
+//! fn main() -> [u8; 32] {
+//!     let mut root = ewasm_sdk::pre_state_root();
+//!     root[0] += 1;
+//!     root
+//! }
+//! ```
+//!
+//! Child modules export their functions with `#[no_mangle] pub extern "C"`,
+//! and read their argument and write their return data with [`argument`] and
+//! [`return_data`].
+//!
+//! The `panic-handler` feature, enabled by default, provides a panic handler
+//! on wasm32 that traps.
+#![cfg_attr(not(test), no_std)]
+
+extern crate self as ewasm_sdk;
+
+pub mod buffer;
+mod call;
+pub mod debug;
+mod module;
+mod state;
+pub mod sys;
+
+pub use call::{
+    argument, call, call_module, call_module_typed, call_typed, return_data, Value, TRAPPED,
+};
+pub use ewasm_sdk_macros::ee_main;
+pub use module::{
+    allow_call, capabilities, expose, limit_modules, load_module, load_module_with_capabilities,
+    load_module_with_limits, shared_memory_copy, shared_memory_size, shared_memory_write,
+};
+pub use state::{block_data_copy, block_data_size, pre_state_root, save_post_state_root};
+
+/// Logs a message through the runtime's logger.
+///
+/// # Host function
+///
+/// ```text
+/// print(offset: u32, length: u32) -> ()
+/// ```
+pub fn print(message: &str) {
+    unsafe { sys::env::print(message.as_ptr(), message.len() as u32) }
+}
+
+/// Values an [`ee_main`] function may return.
+pub trait MainResult {
+    /// Completes the execution of the EE with this value.
+    fn finish(self);
+}
+
+impl MainResult for () {
+    fn finish(self) {}
+}
+
+/// The post-state root, saved with `eth2_savePostStateRoot`.
+impl MainResult for [u8; 32] {
+    fn finish(self) {
+        save_post_state_root(&self)
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "panic-handler"))]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    core::arch::wasm32::unreachable()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    #[ee_main]
+    fn entry() {
+        RAN.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn ee_main_calls_entry_point() {
+        __ewasm_main();
+        assert!(RAN.load(Ordering::SeqCst));
+    }
+}
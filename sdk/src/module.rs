@@ -0,0 +1,171 @@
+use crate::sys;
+
+/// Bits of the capability mask passed to [`load_module_with_capabilities`].
+/// These mirror `ewasm::capabilities`.
+pub mod capabilities {
+    /// `eth2_call`, `eth2_callTyped` and the `root` import namespace.
+    pub const CALL_ROOT: u32 = 1 << 0;
+
+    /// `eth2_callModule` and `eth2_callModuleTyped`.
+    pub const CALL_MODULE: u32 = 1 << 1;
+
+    /// `print`.
+    pub const LOGGING: u32 = 1 << 2;
+
+    /// `eth2_sharedMemorySize` and `eth2_sharedMemoryCopy`.
+    pub const SHARED_MEMORY: u32 = 1 << 3;
+
+    /// The `eth2_buffer*` functions, operating on the root's buffer.
+    pub const BUFFER: u32 = 1 << 4;
+
+    /// `eth2_blockDataSize`, `eth2_blockDataCopy` and `eth2_loadPreStateRoot`.
+    pub const BLOCK_DATA: u32 = 1 << 5;
+
+    /// Host functions added by the embedder.
+    pub const EXTENSIONS: u32 = 1 << 6;
+
+    /// Granted by [`load_module`](crate::load_module).
+    pub const DEFAULT: u32 = CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY;
+}
+
+/// Loads the compiled module `code` into `slot`, with the default
+/// capabilities. Traps if the module can't be instantiated. Only available to
+/// the root.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_loadModule(slot: u32, code_offset: u32, code_length: u32) -> ()
+/// ```
+pub fn load_module(slot: u32, code: &[u8]) {
+    unsafe { sys::env::eth2_loadModule(slot, code.as_ptr(), code.len() as u32) }
+}
+
+/// Loads a module like [`load_module`], granting it `capabilities`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_loadModuleWithCapabilities(
+///     slot: u32,
+///     code_offset: u32,
+///     code_length: u32,
+///     capabilities: u32,
+/// ) -> ()
+/// ```
+pub fn load_module_with_capabilities(slot: u32, code: &[u8], capabilities: u32) {
+    unsafe {
+        sys::env::eth2_loadModuleWithCapabilities(
+            slot,
+            code.as_ptr(),
+            code.len() as u32,
+            capabilities,
+        )
+    }
+}
+
+/// Loads a module like [`load_module_with_capabilities`], limiting it to
+/// `max_pages` pages of memory and `gas` units of gas, deducted from the
+/// root's budget.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_loadModuleWithLimits(
+///     slot: u32,
+///     code_offset: u32,
+///     code_length: u32,
+///     capabilities: u32,
+///     max_pages: u32,
+///     gas: u64,
+/// ) -> ()
+/// ```
+pub fn load_module_with_limits(
+    slot: u32,
+    code: &[u8],
+    capabilities: u32,
+    max_pages: u32,
+    gas: u64,
+) {
+    unsafe {
+        sys::env::eth2_loadModuleWithLimits(
+            slot,
+            code.as_ptr(),
+            code.len() as u32,
+            capabilities,
+            max_pages,
+            gas,
+        )
+    }
+}
+
+/// Caps the number of modules that can be loaded. The cap can only be
+/// lowered.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_limitModules(count: u32) -> ()
+/// ```
+pub fn limit_modules(count: u32) {
+    unsafe { sys::env::eth2_limitModules(count) }
+}
+
+/// Allows the module in `caller` to call functions of the module in `callee`.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_allowCall(caller: u32, callee: u32) -> ()
+/// ```
+pub fn allow_call(caller: u32, callee: u32) {
+    unsafe { sys::env::eth2_allowCall(caller, callee) }
+}
+
+/// Allows children to call the root's exported function `name` with
+/// [`call`](crate::call), or to import it from the `root` namespace.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_expose(name_offset: u32, name_length: u32) -> ()
+/// ```
+pub fn expose(name: &str) {
+    unsafe { sys::env::eth2_expose(name.as_ptr(), name.len() as u32) }
+}
+
+/// Copies `data` into the shared memory at `offset`, growing the shared
+/// memory as needed. Only available to the root.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_sharedMemoryWrite(dest_offset: u32, src_offset: u32, length: u32) -> ()
+/// ```
+pub fn shared_memory_write(offset: u32, data: &[u8]) {
+    unsafe { sys::env::eth2_sharedMemoryWrite(offset, data.as_ptr(), data.len() as u32) }
+}
+
+/// Returns the size of the shared memory in bytes. Only available to
+/// children.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_sharedMemorySize() -> u32
+/// ```
+pub fn shared_memory_size() -> usize {
+    unsafe { sys::env::eth2_sharedMemorySize() as usize }
+}
+
+/// Fills `dest` with the shared memory starting at `offset`. Traps if the
+/// shared memory ends before `dest` is filled. Only available to children.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_sharedMemoryCopy(dest_offset: u32, offset: u32, length: u32) -> ()
+/// ```
+pub fn shared_memory_copy(dest: &mut [u8], offset: u32) {
+    unsafe { sys::env::eth2_sharedMemoryCopy(dest.as_mut_ptr(), offset, dest.len() as u32) }
+}
@@ -0,0 +1,48 @@
+use crate::sys;
+
+/// Returns the pre-state root.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_loadPreStateRoot(dest_offset: u32) -> ()
+/// ```
+pub fn pre_state_root() -> [u8; 32] {
+    let mut root = [0; 32];
+    unsafe { sys::env::eth2_loadPreStateRoot(root.as_mut_ptr()) };
+    root
+}
+
+/// Saves the post-state root. Only available to the root.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_savePostStateRoot(offset: u32) -> ()
+/// ```
+pub fn save_post_state_root(root: &[u8; 32]) {
+    unsafe { sys::env::eth2_savePostStateRoot(root.as_ptr()) }
+}
+
+/// Returns the length of the block data.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_blockDataSize() -> u32
+/// ```
+pub fn block_data_size() -> usize {
+    unsafe { sys::env::eth2_blockDataSize() as usize }
+}
+
+/// Fills `dest` with the block data starting at `offset`. Traps if the block
+/// data ends before `dest` is filled.
+///
+/// # Host function
+///
+/// ```text
+/// eth2_v2.eth2_blockDataCopy(dest_offset: u32, offset: u32, length: u32) -> ()
+/// ```
+pub fn block_data_copy(dest: &mut [u8], offset: u32) {
+    unsafe { sys::v2::eth2_blockDataCopy(dest.as_mut_ptr(), offset, dest.len() as u32) }
+}
@@ -0,0 +1,128 @@
+//! Raw imports of the host functions, named as the runtime exports them.
+//!
+//! Offsets and lengths are `u32`, and return codes are `i32`. See the runtime
+//! documentation for the semantics of each function. Outside of wasm32 the
+//! functions panic, so that code using them can still be built and unit
+//! tested on the host.
+#![allow(non_snake_case, clippy::missing_safety_doc)]
+
+macro_rules! imports {
+    ($namespace:tt { $(fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)* }) => {
+        #[cfg(target_arch = "wasm32")]
+        #[link(wasm_import_module = $namespace)]
+        extern "C" {
+            $(pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+
+        $(
+            #[cfg(not(target_arch = "wasm32"))]
+            pub unsafe fn $name($(_: $ty),*) $(-> $ret)? {
+                super::unavailable(stringify!($name))
+            }
+        )*
+    };
+}
+
+/// Functions of the `env` namespace, which serves the first version of the
+/// host function ABI.
+pub mod env {
+    imports!("env" {
+        fn eth2_loadPreStateRoot(dest: *mut u8);
+        fn eth2_savePostStateRoot(src: *const u8);
+        fn eth2_blockDataSize() -> u32;
+        fn eth2_blockDataCopy(dest: *mut u8, offset: u32, end: u32);
+
+        fn eth2_bufferGet(frame: u32, key: *const u8, value: *mut u8) -> i32;
+        fn eth2_bufferSet(frame: u32, key: *const u8, value: *const u8);
+        fn eth2_bufferMerge(a: u32, b: u32);
+        fn eth2_bufferMergeMissing(a: u32, b: u32);
+        fn eth2_bufferMergeChecked(a: u32, b: u32, dest: *mut u8, len: u32) -> u32;
+        fn eth2_bufferClear(frame: u32);
+        fn eth2_bufferCount(frame: u32) -> u32;
+        fn eth2_bufferKeys(frame: u32, dest: *mut u8, len: u32) -> u32;
+        fn eth2_bufferDelete(frame: u32, key: *const u8) -> i32;
+        fn eth2_bufferCopy(src: u32, dest: u32) -> i32;
+
+        fn eth2_sharedMemoryWrite(dest: u32, src: *const u8, len: u32);
+        fn eth2_sharedMemorySize() -> u32;
+        fn eth2_sharedMemoryCopy(dest: *mut u8, offset: u32, len: u32);
+
+        fn eth2_loadModule(slot: u32, code: *const u8, len: u32);
+        fn eth2_loadModuleWithCapabilities(slot: u32, code: *const u8, len: u32, capabilities: u32);
+        fn eth2_loadModuleWithLimits(
+            slot: u32,
+            code: *const u8,
+            len: u32,
+            capabilities: u32,
+            max_pages: u32,
+            gas: u64
+        );
+        fn eth2_limitModules(count: u32);
+        fn eth2_allowCall(caller: u32, callee: u32);
+        fn eth2_expose(name: *const u8, len: u32);
+
+        fn eth2_callModule(
+            slot: u32,
+            name: *const u8,
+            name_len: u32,
+            arg: *const u8,
+            arg_len: u32,
+            ret: *mut u8,
+            ret_len: u32
+        ) -> i32;
+        fn eth2_callModuleTyped(
+            slot: u32,
+            name: *const u8,
+            name_len: u32,
+            params: *const u8,
+            params_count: u32,
+            results: *mut u8,
+            results_count: u32
+        ) -> i32;
+        fn eth2_call(
+            name: *const u8,
+            name_len: u32,
+            arg: *const u8,
+            arg_len: u32,
+            ret: *mut u8,
+            ret_len: u32
+        ) -> i32;
+        fn eth2_callTyped(
+            name: *const u8,
+            name_len: u32,
+            params: *const u8,
+            params_count: u32,
+            results: *mut u8,
+            results_count: u32
+        ) -> i32;
+        fn eth2_argument(dest: *mut u8, len: u32) -> u32;
+        fn eth2_return(src: *const u8, len: u32) -> u32;
+
+        fn print(src: *const u8, len: u32);
+    });
+}
+
+/// Functions of the `eth2_v2` namespace that changed from the first version.
+pub mod v2 {
+    imports!("eth2_v2" {
+        fn eth2_blockDataCopy(dest: *mut u8, offset: u32, len: u32);
+    });
+}
+
+/// Functions of the `debug` namespace, which is only served when the runtime
+/// is built with debugging enabled.
+pub mod debug {
+    imports!("debug" {
+        fn debug_printMem(src: *const u8, len: u32);
+        fn debug_printMemHex(src: *const u8, len: u32);
+        fn debug_printI32(value: i32);
+        fn debug_printI64(value: i64);
+        fn debug_printBuffer(frame: u32);
+        fn debug_abort(message: *const u8, len: u32);
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unavailable(name: &str) -> ! {
+    panic!("{} is only available to wasm32 guests", name)
+}
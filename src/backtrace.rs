@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// The wasm functions that were executing when a module trapped, innermost
+/// first, across the root and its children.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backtrace {
+    pub frames: Vec<BacktraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    /// The slot of the child executing the function, or `None` for the root.
+    pub module: Option<u32>,
+
+    /// Index of the function in its module's function index space.
+    pub index: u32,
+
+    /// Name of the function, from the module's name section if it has one.
+    pub name: String,
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (depth, frame) in self.frames.iter().enumerate() {
+            match frame.module {
+                Some(slot) => write!(f, "{:>4}: child {}", depth, slot)?,
+                None => write!(f, "{:>4}: root", depth)?,
+            }
+
+            writeln!(f, ", function {} ({})", frame.index, frame.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let backtrace = Backtrace {
+            frames: vec![
+                BacktraceFrame {
+                    module: Some(3),
+                    index: 2,
+                    name: "helper".to_string(),
+                },
+                BacktraceFrame {
+                    module: None,
+                    index: 7,
+                    name: "main".to_string(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            backtrace.to_string(),
+            "   0: child 3, function 2 (helper)\n   1: root, function 7 (main)\n"
+        );
+    }
+}
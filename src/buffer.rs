@@ -1,37 +1,243 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-type K = u8;
-type V = HashMap<[u8; 32], [u8; 32]>;
+type K = u32;
+type V = BTreeMap<[u8; 32], [u8; 32]>;
 
 #[derive(Debug, Clone, Default)]
 pub struct Buffer {
     map: HashMap<K, V>,
+    journal: Journal,
+}
+
+/// How to undo a change to the buffer.
+#[derive(Debug, Clone)]
+enum Change {
+    /// A key of `frame` was set, replacing `previous`.
+    Entry {
+        frame: K,
+        key: [u8; 32],
+        previous: Option<[u8; 32]>,
+    },
+
+    /// The whole of `frame` was replaced or removed.
+    Frame { frame: K, previous: Option<V> },
+}
+
+/// Changes made to the buffer while a checkpoint is open. Nothing is recorded
+/// otherwise.
+#[derive(Debug, Clone, Default)]
+struct Journal {
+    changes: Vec<Change>,
+
+    /// Number of changes recorded before each open checkpoint.
+    checkpoints: Vec<usize>,
+}
+
+impl Journal {
+    fn record(&mut self, change: Change) {
+        if !self.checkpoints.is_empty() {
+            self.changes.push(change);
+        }
+    }
 }
 
 impl Buffer {
-    pub fn get(&self, frame: u8, key: [u8; 32]) -> Option<&[u8; 32]> {
+    pub fn get(&self, frame: u32, key: [u8; 32]) -> Option<&[u8; 32]> {
         match self.map.get(&frame) {
             Some(map) => map.get(&key),
             None => None,
         }
     }
 
-    pub fn insert(&mut self, frame: u8, key: [u8; 32], value: [u8; 32]) -> Option<[u8; 32]> {
-        let map = self.map.entry(frame).or_insert(HashMap::new());
-        map.insert(key, value)
+    pub fn insert(&mut self, frame: u32, key: [u8; 32], value: [u8; 32]) -> Option<[u8; 32]> {
+        let map = self.map.entry(frame).or_default();
+        let previous = map.insert(key, value);
+
+        self.journal.record(Change::Entry {
+            frame,
+            key,
+            previous,
+        });
+
+        previous
+    }
+
+    pub fn merge(&mut self, a: u32, b: u32) {
+        let frame = a;
+        let b = self.map.entry(b).or_default().to_owned();
+        let a = self.map.entry(a).or_default();
+
+        for (key, value) in b.iter() {
+            let previous = a.insert(*key, *value);
+
+            self.journal.record(Change::Entry {
+                frame,
+                key: *key,
+                previous,
+            });
+        }
+    }
+
+    pub fn clear(&mut self, frame: u32) {
+        let previous = self.map.remove(&frame);
+
+        self.journal.record(Change::Frame { frame, previous });
+    }
+
+    pub fn remove(&mut self, frame: u32, key: [u8; 32]) -> Option<[u8; 32]> {
+        let previous = self.map.get_mut(&frame).and_then(|map| map.remove(&key));
+
+        if previous.is_some() {
+            self.journal.record(Change::Entry {
+                frame,
+                key,
+                previous,
+            });
+        }
+
+        previous
+    }
+
+    /// Copies every entry of `src` into the empty frame `dest`. Returns
+    /// `false`, leaving the buffer untouched, if `dest` already has entries.
+    pub fn copy(&mut self, src: u32, dest: u32) -> bool {
+        if self.len(dest) != 0 {
+            return false;
+        }
+
+        if let Some(map) = self.map.get(&src).cloned() {
+            let previous = self.map.insert(dest, map);
+
+            self.journal.record(Change::Frame {
+                frame: dest,
+                previous,
+            });
+        }
+
+        true
+    }
+
+    /// Merges the entries of `b` into `a`, keeping the existing value of any
+    /// key already present in `a`.
+    pub fn merge_missing(&mut self, a: u32, b: u32) {
+        let frame = a;
+        let b = self.map.entry(b).or_default().to_owned();
+        let a = self.map.entry(a).or_default();
+
+        for (key, value) in b.iter() {
+            if !a.contains_key(key) {
+                a.insert(*key, *value);
+
+                self.journal.record(Change::Entry {
+                    frame,
+                    key: *key,
+                    previous: None,
+                });
+            }
+        }
     }
 
-    pub fn merge(&mut self, a: u8, b: u8) {
-        let b = self.map.entry(b).or_insert(HashMap::new()).to_owned();
-        let a = self.map.entry(a).or_insert(HashMap::new());
+    /// Merges the entries of `b` into `a`, skipping keys that are present in
+    /// both frames with different values. Returns the skipped keys in
+    /// ascending order.
+    pub fn merge_checked(&mut self, a: u32, b: u32) -> Vec<[u8; 32]> {
+        let frame = a;
+        let b = self.map.entry(b).or_default().to_owned();
+        let a = self.map.entry(a).or_default();
+
+        let mut conflicts = Vec::new();
 
         for (key, value) in b.iter() {
-            a.insert(*key, *value);
+            match a.get(key) {
+                Some(existing) if existing != value => conflicts.push(*key),
+                _ => {
+                    let previous = a.insert(*key, *value);
+
+                    self.journal.record(Change::Entry {
+                        frame,
+                        key: *key,
+                        previous,
+                    });
+                }
+            }
         }
+
+        conflicts
+    }
+
+    /// Returns the keys stored in `frame` in ascending order, so that
+    /// enumerating a frame is deterministic.
+    pub fn keys(&self, frame: u32) -> impl Iterator<Item = &[u8; 32]> {
+        self.map.get(&frame).into_iter().flat_map(|map| map.keys())
+    }
+
+    /// Returns the number of entries stored in `frame`.
+    pub fn len(&self, frame: u32) -> usize {
+        self.map.get(&frame).map_or(0, |map| map.len())
     }
 
-    pub fn clear(&mut self, frame: u8) {
-        self.map.remove(&frame);
+    /// Starts recording changes, so that they can be undone with `revert`.
+    /// Checkpoints nest.
+    pub fn checkpoint(&mut self) {
+        let journal = &mut self.journal;
+        journal.checkpoints.push(journal.changes.len());
+    }
+
+    /// Closes the most recent checkpoint, keeping every change made since it
+    /// was opened.
+    pub fn commit(&mut self) {
+        let journal = &mut self.journal;
+        journal
+            .checkpoints
+            .pop()
+            .expect("commit requires an open checkpoint");
+
+        if journal.checkpoints.is_empty() {
+            journal.changes.clear();
+        }
+    }
+
+    /// Closes the most recent checkpoint, undoing every change made since it
+    /// was opened.
+    pub fn revert(&mut self) {
+        let start = self
+            .journal
+            .checkpoints
+            .pop()
+            .expect("revert requires an open checkpoint");
+
+        while self.journal.changes.len() > start {
+            match self.journal.changes.pop().unwrap() {
+                Change::Entry {
+                    frame,
+                    key,
+                    previous: Some(value),
+                } => {
+                    self.map.entry(frame).or_default().insert(key, value);
+                }
+                Change::Entry {
+                    frame,
+                    key,
+                    previous: None,
+                } => {
+                    if let Some(map) = self.map.get_mut(&frame) {
+                        map.remove(&key);
+                    }
+                }
+                Change::Frame {
+                    frame,
+                    previous: Some(map),
+                } => {
+                    self.map.insert(frame, map);
+                }
+                Change::Frame {
+                    frame,
+                    previous: None,
+                } => {
+                    self.map.remove(&frame);
+                }
+            }
+        }
     }
 }
 
@@ -56,4 +262,147 @@ mod test {
         assert_eq!(buffer.get(1, [0u8; 32]), Some(&[3u8; 32]));
         assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
     }
+
+    #[test]
+    fn merge_missing() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [0u8; 32]);
+        buffer.insert(0, [1u8; 32], [1u8; 32]);
+        buffer.insert(1, [2u8; 32], [2u8; 32]);
+        buffer.insert(1, [0u8; 32], [3u8; 32]);
+
+        buffer.merge_missing(0, 1);
+
+        assert_eq!(buffer.get(0, [0u8; 32]), Some(&[0u8; 32]));
+        assert_eq!(buffer.get(0, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(0, [2u8; 32]), Some(&[2u8; 32]));
+        assert_eq!(buffer.get(1, [0u8; 32]), Some(&[3u8; 32]));
+        assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn merge_checked() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [0u8; 32]);
+        buffer.insert(0, [1u8; 32], [1u8; 32]);
+        buffer.insert(1, [2u8; 32], [2u8; 32]);
+        buffer.insert(1, [0u8; 32], [3u8; 32]);
+        buffer.insert(1, [1u8; 32], [1u8; 32]);
+
+        let conflicts = buffer.merge_checked(0, 1);
+
+        assert_eq!(conflicts, vec![[0u8; 32]]);
+        assert_eq!(buffer.get(0, [0u8; 32]), Some(&[0u8; 32]));
+        assert_eq!(buffer.get(0, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(0, [2u8; 32]), Some(&[2u8; 32]));
+        assert_eq!(buffer.get(1, [0u8; 32]), Some(&[3u8; 32]));
+        assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn remove() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [1u8; 32]);
+        buffer.insert(0, [1u8; 32], [2u8; 32]);
+
+        assert_eq!(buffer.remove(0, [0u8; 32]), Some([1u8; 32]));
+        assert_eq!(buffer.remove(0, [0u8; 32]), None);
+        assert_eq!(buffer.remove(1, [0u8; 32]), None);
+
+        assert_eq!(buffer.get(0, [0u8; 32]), None);
+        assert_eq!(buffer.get(0, [1u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn copy() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [1u8; 32]);
+        buffer.insert(2, [0u8; 32], [2u8; 32]);
+
+        assert!(buffer.copy(0, 1));
+        assert!(!buffer.copy(0, 2));
+
+        buffer.insert(0, [1u8; 32], [1u8; 32]);
+
+        assert_eq!(buffer.get(1, [0u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(1, [1u8; 32]), None);
+        assert_eq!(buffer.get(2, [0u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn frames_do_not_alias() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [1u8; 32]);
+        buffer.insert(256, [0u8; 32], [2u8; 32]);
+
+        assert_eq!(buffer.get(0, [0u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(256, [0u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn keys_and_len() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [2u8; 32], [0u8; 32]);
+        buffer.insert(0, [1u8; 32], [0u8; 32]);
+        buffer.insert(1, [3u8; 32], [0u8; 32]);
+
+        let keys: Vec<_> = buffer.keys(0).cloned().collect();
+
+        assert_eq!(keys, vec![[1u8; 32], [2u8; 32]]);
+        assert_eq!(buffer.len(0), 2);
+        assert_eq!(buffer.len(1), 1);
+        assert_eq!(buffer.len(2), 0);
+        assert_eq!(buffer.keys(2).count(), 0);
+    }
+
+    #[test]
+    fn revert() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(0, [0u8; 32], [1u8; 32]);
+        buffer.insert(1, [1u8; 32], [1u8; 32]);
+
+        buffer.checkpoint();
+        buffer.insert(0, [0u8; 32], [2u8; 32]);
+        buffer.insert(0, [2u8; 32], [2u8; 32]);
+        buffer.merge(2, 1);
+        buffer.clear(1);
+        assert!(buffer.copy(2, 3));
+        buffer.revert();
+
+        assert_eq!(buffer.get(0, [0u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(0, [2u8; 32]), None);
+        assert_eq!(buffer.get(1, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.len(2), 0);
+        assert_eq!(buffer.len(3), 0);
+    }
+
+    #[test]
+    fn nested_checkpoints() {
+        let mut buffer = Buffer::default();
+
+        buffer.checkpoint();
+        buffer.insert(0, [0u8; 32], [1u8; 32]);
+
+        buffer.checkpoint();
+        buffer.insert(0, [0u8; 32], [2u8; 32]);
+        buffer.remove(0, [0u8; 32]);
+        buffer.revert();
+
+        assert_eq!(buffer.get(0, [0u8; 32]), Some(&[1u8; 32]));
+
+        buffer.checkpoint();
+        buffer.insert(0, [1u8; 32], [1u8; 32]);
+        buffer.commit();
+        buffer.revert();
+
+        assert_eq!(buffer.get(0, [0u8; 32]), None);
+        assert_eq!(buffer.get(0, [1u8; 32]), None);
+    }
 }
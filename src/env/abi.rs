@@ -0,0 +1,19 @@
+/// Versions of the host function ABI. Each version is served from its own
+/// import namespace, so a module may mix functions from several versions.
+///
+/// Changes in `V2`:
+///
+/// * `eth2_blockDataCopy(dest_offset, offset, length)` copies `length` bytes
+///   starting at `offset`, and traps if they aren't all within the block data.
+///   `V1` copies the bytes from `offset` up to `length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Abi {
+    V1,
+    V2,
+}
+
+/// The namespaces host functions can be imported from. `env` is an alias of
+/// `eth2_v1`, kept for existing modules. Imports from any other namespace are
+/// rejected when the module is instantiated.
+pub const NAMESPACES: &[(&str, Abi)] =
+    &[("env", Abi::V1), ("eth2_v1", Abi::V1), ("eth2_v2", Abi::V2)];
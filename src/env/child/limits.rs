@@ -0,0 +1,169 @@
+use parity_wasm::elements::{self, MemoryType, Module};
+use pwasm_utils::rules;
+
+use wasmi::Error as InterpreterError;
+
+/// Resource limits placed on a module when it is loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of memory pages the child may declare or grow to.
+    pub max_pages: Option<u32>,
+
+    /// Gas allotted to the child, deducted from the root's budget when the
+    /// child is loaded. Unmetered if `None`.
+    pub gas: Option<u64>,
+}
+
+/// Gas charged to metered children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasSchedule {
+    /// Cost of every instruction.
+    pub instruction: u32,
+
+    /// Cost of each page added by `memory.grow`.
+    pub memory_grow: u32,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self {
+            instruction: 1,
+            memory_grow: 0,
+        }
+    }
+}
+
+impl GasSchedule {
+    fn rules(&self) -> rules::Set {
+        rules::Set::new(self.instruction, Default::default()).with_grow_cost(self.memory_grow)
+    }
+}
+
+impl Limits {
+    /// Rewrites `code` so that the limits are enforced by the interpreter: the
+    /// declared maximum of the child's own memory is capped at `max_pages`, and
+    /// calls to the `gas` host function are injected when the child is
+    /// metered, charging according to `schedule`. Fails if the module asks for
+    /// more memory than it may have.
+    pub fn apply(&self, code: &[u8], schedule: &GasSchedule) -> Result<Vec<u8>, InterpreterError> {
+        if *self == Limits::default() {
+            return Ok(code.to_vec());
+        }
+
+        let mut module = elements::deserialize_buffer::<Module>(code)
+            .map_err(|error| InterpreterError::Instantiation(error.to_string()))?;
+
+        if let Some(max_pages) = self.max_pages {
+            if let Some(memories) = module.memory_section_mut() {
+                for memory in memories.entries_mut() {
+                    check_initial(memory, max_pages)?;
+
+                    let initial = memory.limits().initial();
+                    let maximum = memory.limits().maximum().unwrap_or(max_pages);
+
+                    *memory = MemoryType::new(initial, Some(maximum.min(max_pages)));
+                }
+            }
+        }
+
+        if self.gas.is_some() {
+            module = pwasm_utils::inject_gas_counter(module, &schedule.rules()).map_err(|_| {
+                InterpreterError::Instantiation("module can't be metered".to_string())
+            })?;
+        }
+
+        elements::serialize(module)
+            .map_err(|error| InterpreterError::Instantiation(error.to_string()))
+    }
+}
+
+fn check_initial(memory: &MemoryType, max_pages: u32) -> Result<(), InterpreterError> {
+    let initial = memory.limits().initial();
+
+    if initial > max_pages {
+        Err(InterpreterError::Instantiation(format!(
+            "module requests {} pages of memory, but is limited to {}",
+            initial, max_pages
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wabt::wat2wasm;
+
+    fn memory(code: &[u8]) -> (u32, Option<u32>) {
+        let module: Module = elements::deserialize_buffer(code).unwrap();
+        let limits = *module.memory_section().unwrap().entries()[0].limits();
+
+        (limits.initial(), limits.maximum())
+    }
+
+    #[test]
+    fn unlimited_is_unchanged() {
+        let code = wat2wasm("(module (memory 1))").unwrap();
+
+        assert_eq!(
+            Limits::default()
+                .apply(&code, &GasSchedule::default())
+                .unwrap(),
+            code
+        );
+    }
+
+    #[test]
+    fn caps_memory_maximum() {
+        let limits = Limits {
+            max_pages: Some(4),
+            gas: None,
+        };
+
+        let unbounded = wat2wasm("(module (memory 1))").unwrap();
+        assert_eq!(
+            memory(&limits.apply(&unbounded, &GasSchedule::default()).unwrap()),
+            (1, Some(4))
+        );
+
+        let smaller = wat2wasm("(module (memory 1 2))").unwrap();
+        assert_eq!(
+            memory(&limits.apply(&smaller, &GasSchedule::default()).unwrap()),
+            (1, Some(2))
+        );
+
+        let larger = wat2wasm("(module (memory 1 8))").unwrap();
+        assert_eq!(
+            memory(&limits.apply(&larger, &GasSchedule::default()).unwrap()),
+            (1, Some(4))
+        );
+    }
+
+    #[test]
+    fn rejects_large_initial_memory() {
+        let limits = Limits {
+            max_pages: Some(4),
+            gas: None,
+        };
+
+        let code = wat2wasm("(module (memory 5))").unwrap();
+        assert!(limits.apply(&code, &GasSchedule::default()).is_err());
+    }
+
+    #[test]
+    fn injects_gas_import() {
+        let limits = Limits {
+            max_pages: None,
+            gas: Some(100),
+        };
+
+        let code = wat2wasm("(module (func (export \"main\") (nop)))").unwrap();
+        let metered: Module =
+            elements::deserialize_buffer(&limits.apply(&code, &GasSchedule::default()).unwrap())
+                .unwrap();
+
+        let imports = metered.import_section().unwrap().entries();
+        assert_eq!((imports[0].module(), imports[0].field()), ("env", "gas"));
+    }
+}
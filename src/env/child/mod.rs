@@ -1,66 +1,197 @@
-mod resolver;
+mod limits;
+pub(crate) mod resolver;
 
+use crate::env::abi;
+use crate::env::debug::{self, DebugModuleImportResolver};
+use crate::env::debugger::Stop;
+use crate::env::instrument::{self, FunctionNames, InstrumentModuleImportResolver};
 use crate::env::root::{RootRuntime, RootRuntimeWeak};
 
-use self::resolver::{externals, ChildModuleImportResolver};
+use self::resolver::{externals, ChildModuleImportResolver, RootLinker};
 
-use std::cell::RefCell;
+pub use self::limits::{GasSchedule, Limits};
+pub use self::resolver::capabilities;
 
-use super::{ExtResult, StackFrame};
+use crate::Error;
+
+use std::cell::{Cell, RefCell};
+
+use super::{read_name, values, ExtResult, StackFrame};
 
 use wasmi::{
-    Externals, FuncInstance, ImportsBuilder, MemoryRef, Module, ModuleInstance, ModuleRef,
-    RuntimeArgs, RuntimeValue, Trap,
+    Error as InterpreterError, Externals, FuncInstance, FuncRef, ImportsBuilder, MemoryRef, Module,
+    ModuleInstance, ModuleRef, RuntimeArgs, RuntimeValue, Trap, TrapKind,
 };
 
 pub struct ChildRuntime<'a> {
     instance: ModuleRef,
     root: RootRuntimeWeak<'a>,
+    slot: u32,
+    links: Vec<(String, FuncRef)>,
+
+    /// Names of the child's functions, if it was instrumented.
+    function_names: FunctionNames,
+
+    /// Gas left from the child's allotment, or `None` if it isn't metered.
+    gas: Option<Cell<u64>>,
 
     call_stack: RefCell<Vec<StackFrame>>,
 }
 
 impl<'a> ChildRuntime<'a> {
-    pub(crate) fn new(root: RootRuntimeWeak<'a>, code: &[u8]) -> Self {
-        let module = Module::from_buffer(code).expect("Module loading to succeed");
-
-        let mut imports = ImportsBuilder::new();
-        imports.push_resolver("env", &ChildModuleImportResolver);
-
-        let instance = ModuleInstance::new(&module, &imports)
-            .expect("Module instantation expected to succeed")
-            .assert_no_start();
-
-        Self {
+    /// Instantiates `code`, resolving only the imports permitted by
+    /// `capabilities`, a combination of the bits in `capabilities`, and
+    /// enforcing `limits`. Gas is charged according to `schedule`.
+    pub(crate) fn new(
+        root: RootRuntimeWeak<'a>,
+        slot: u32,
+        code: &[u8],
+        capabilities: u32,
+        limits: Limits,
+        schedule: &GasSchedule,
+    ) -> Result<Self, InterpreterError> {
+        let strong = root.upgrade().expect("root runtime dropped before child");
+
+        let metered = limits.apply(code, schedule)?;
+
+        let (code, function_names) = if strong.instrumented() {
+            let names = FunctionNames::read(code)?;
+            let profiling = strong.profiler().is_some();
+            (
+                instrument::instrument(&metered, names.len(), profiling)?,
+                names,
+            )
+        } else {
+            (metered, FunctionNames::default())
+        };
+
+        let module = Module::from_buffer(code)?;
+
+        let resolvers: Vec<_> = abi::NAMESPACES
+            .iter()
+            .map(|(_, abi)| {
+                ChildModuleImportResolver::new(
+                    *abi,
+                    capabilities,
+                    strong.registry(),
+                    limits.gas.is_some(),
+                )
+            })
+            .collect();
+
+        let linker = RootLinker::new(strong.clone(), capabilities);
+
+        let debug = DebugModuleImportResolver::new(externals::DEBUG);
+        let instrumentation = InstrumentModuleImportResolver::new(externals::INSTRUMENT);
+
+        let instance = {
+            let mut imports = ImportsBuilder::new();
+            for ((namespace, _), resolver) in abi::NAMESPACES.iter().zip(&resolvers) {
+                imports.push_resolver(*namespace, resolver);
+            }
+            imports.push_resolver("root", &linker);
+
+            if strong.debug_enabled() && capabilities & resolver::capabilities::LOGGING != 0 {
+                imports.push_resolver(debug::NAMESPACE, &debug);
+            }
+
+            if strong.instrumented() {
+                imports.push_resolver(instrument::NAMESPACE, &instrumentation);
+            }
+
+            let instance = ModuleInstance::new(&module, &imports)?;
+            if instance.has_start() {
+                return Err(InterpreterError::Instantiation(
+                    "modules with a start function aren't supported".to_string(),
+                ));
+            }
+
+            instance.assert_no_start()
+        };
+
+        Ok(Self {
             instance,
             root,
+            slot,
+            links: linker.into_links(),
+            function_names,
+            gas: limits.gas.map(Cell::new),
             call_stack: Default::default(),
-        }
+        })
     }
 
-    pub(super) fn call(&self, name: &str, frame: StackFrame) -> i32 {
-        let export = self
-            .instance
+    /// Returns the exported function `name`. Traps if there is none.
+    pub(super) fn function(&self, name: &str) -> Result<FuncRef, Trap> {
+        self.instance
             .export_by_name(name)
-            .expect("name doesn't exist in child");
+            .and_then(|export| export.as_func().cloned())
+            .ok_or_else(|| {
+                Error::UnknownFunction {
+                    slot: Some(self.slot),
+                    name: name.to_string(),
+                }
+                .into()
+            })
+    }
+
+    /// Calls the function `name`, which must take no parameters and return an
+    /// i32 return code.
+    pub(super) fn call(&self, name: &str, frame: StackFrame) -> Result<i32, Trap> {
+        let func = self.function(name)?;
+        values::check_call_signature(func.signature())?;
+
+        match self.invoke_func(name, &func, &[], frame)? {
+            Some(RuntimeValue::I32(retcode)) => Ok(retcode),
+            _ => unreachable!("signature checked above"),
+        }
+    }
+
+    /// Calls the function `name` with typed arguments. Traps if `args` don't
+    /// match its signature.
+    pub(super) fn invoke(
+        &self,
+        name: &str,
+        args: &[RuntimeValue],
+        frame: StackFrame,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let func = self.function(name)?;
+        values::check_signature(func.signature(), args)?;
 
-        let func = export.as_func().expect("name isn't a function");
+        self.invoke_func(name, &func, args, frame)
+    }
 
+    fn invoke_func(
+        &self,
+        name: &str,
+        func: &FuncRef,
+        args: &[RuntimeValue],
+        frame: StackFrame,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let root = self.root();
+
+        root.enter_call(Some(self.slot), name, args, &frame);
         self.call_stack.borrow_mut().push(frame);
 
+        let depth = root.frame_depth();
         let mut externals = ChildExternals(self);
-        let result = FuncInstance::invoke(&func, &[], &mut externals)
-            .expect("function provided by child runtime failed")
-            .expect("function provided by child runtime did not return a value")
-            .try_into()
-            .expect("funtion provided by child runtime return a non-i32 value");
+        let result = FuncInstance::invoke(func, args, &mut externals);
 
+        if result.is_err() {
+            root.capture_backtrace();
+        }
+
+        root.unwind_frames(depth);
         self.call_stack.borrow_mut().pop().unwrap();
+        root.exit_call(Some(self.slot), name, &result);
 
         result
     }
 
-    fn memory(&self) -> MemoryRef {
+    pub(crate) fn function_names(&self) -> &FunctionNames {
+        &self.function_names
+    }
+
+    pub(crate) fn memory(&self) -> MemoryRef {
         self.instance
             .export_by_name("memory")
             .expect("Module expected to have 'memory' export")
@@ -80,8 +211,7 @@ impl<'a> ChildRuntime<'a> {
 
         let name_ptr: u32 = args.nth(0);
         let name_len: u32 = args.nth(1);
-        let name_bytes = memory.get(name_ptr, name_len as usize).unwrap();
-        let name = String::from_utf8(name_bytes).unwrap();
+        let name = read_name(&memory, name_ptr, name_len)?;
 
         let arg_ptr: u32 = args.nth(2);
         let arg_len: u32 = args.nth(3);
@@ -97,11 +227,105 @@ impl<'a> ChildRuntime<'a> {
             .memory(memory)
             .build();
 
-        let retcode = self.root().call(&name, frame);
+        let root = self.root();
+        let retcode = root.journaled(|| root.call(&name, frame))?;
 
         Ok(Some(retcode.into()))
     }
 
+    /// Calls the function `name` from the module in `slot`. The root must have
+    /// permitted this module to call `slot` with `eth2_allowCall`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_callModule(
+    ///     slot: u32,
+    ///     name_offset: u32,
+    ///     name_length: u32
+    ///     argument_offset: u32,
+    ///     argument_length: u32,
+    ///     return_offset: u32,
+    ///     return_length: u32,
+    /// ) -> u32
+    /// ```
+    fn ext_call_module(&self, args: RuntimeArgs) -> ExtResult {
+        self.root()
+            .call_module(Some(self.slot), self.memory(), args)
+    }
+
+    /// Typed version of `eth2_callModule`, see `eth2_callTyped`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_callModuleTyped(
+    ///     slot: u32,
+    ///     name_offset: u32,
+    ///     name_length: u32,
+    ///     params_offset: u32,
+    ///     params_count: u32,
+    ///     results_offset: u32,
+    ///     results_count: u32,
+    /// ) -> i32
+    /// ```
+    fn ext_call_module_typed(&self, args: RuntimeArgs) -> ExtResult {
+        self.root()
+            .call_module_typed(Some(self.slot), self.memory(), args)
+    }
+
+    /// Calls the function `name` exposed by the root with a packed list of
+    /// typed arguments, writing its results as a packed list to
+    /// `results_offset`. At most `results_count` results are written. Returns
+    /// the number of results produced by the callee, or `i32::MIN` if it
+    /// trapped, in which case its changes are rolled back. Results aren't
+    /// return codes, so a typed callee can only fail by trapping.
+    ///
+    /// Traps if the arguments don't match the parameters of the callee, or if
+    /// the results can't be written to `results_offset`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_callTyped(
+    ///     name_offset: u32,
+    ///     name_length: u32,
+    ///     params_offset: u32,
+    ///     params_count: u32,
+    ///     results_offset: u32,
+    ///     results_count: u32,
+    /// ) -> i32
+    /// ```
+    fn ext_call_typed(&self, args: RuntimeArgs) -> ExtResult {
+        let memory = self.memory();
+
+        let name_ptr: u32 = args.nth(0);
+        let name_len: u32 = args.nth(1);
+        let name = read_name(&memory, name_ptr, name_len)?;
+
+        let params_ptr: u32 = args.nth(2);
+        let params_count: u32 = args.nth(3);
+
+        let results_ptr: u32 = args.nth(4);
+        let results_count: u32 = args.nth(5);
+
+        let params = values::read(&memory, params_ptr, params_count)?;
+
+        let root = self.root();
+
+        let frame = StackFrame::builder()
+            .argument_offset(0u32)
+            .argument_length(0u32)
+            .return_offset(0u32)
+            .return_length(0u32)
+            .memory(memory.clone())
+            .build();
+
+        root.journaled_typed(&memory, results_ptr, results_count, || {
+            root.invoke_exposed(&name, &params, frame)
+        })
+    }
+
     /// Copies the argument data from the most recent call into memory at the
     /// given offtet and length. Returns the actual length of the argument data.
     ///
@@ -121,7 +345,9 @@ impl<'a> ChildRuntime<'a> {
             .last()
             .expect("eth2_argument requires a call stack");
 
-        let len = top.transfer_argument(&memory, dest_ptr, dest_len).unwrap();
+        let len = top
+            .transfer_argument(&memory, dest_ptr, dest_len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(Some(len.into()))
     }
@@ -145,18 +371,93 @@ impl<'a> ChildRuntime<'a> {
             .last()
             .expect("eth2_return requires a call stack");
 
-        let len = top.transfer_return(&memory, src_ptr, src_len).unwrap();
+        let len = top
+            .transfer_return(&memory, src_ptr, src_len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(Some(len.into()))
     }
 
+    /// Calls a root function linked through the `root` import namespace. The
+    /// call is journaled like `eth2_call`, but a trap in the root function is
+    /// propagated to the child instead of being reported as a return code.
+    fn ext_linked(&self, index: usize, args: RuntimeArgs) -> ExtResult {
+        let (name, func) = &self.links[index];
+        let args: Vec<RuntimeValue> = args.as_ref().to_vec();
+
+        let frame = StackFrame::builder()
+            .argument_offset(0u32)
+            .argument_length(0u32)
+            .return_offset(0u32)
+            .return_length(0u32)
+            .memory(self.memory())
+            .build();
+
+        self.root().call_linked(name, func, &args, frame)
+    }
+
+    /// Charges gas against the child's allotment, trapping once it is used
+    /// up. Calls are injected into metered modules when they are loaded.
+    fn ext_gas(&self, args: RuntimeArgs) -> ExtResult {
+        let amount: u32 = args.nth(0);
+
+        if let Some(gas) = &self.gas {
+            let left = gas
+                .get()
+                .checked_sub(u64::from(amount))
+                .ok_or(Error::OutOfGas { slot: self.slot })?;
+
+            gas.set(left);
+        }
+
+        Ok(None)
+    }
+
+    /// Whether the child's host function at `index` has side effects, and so
+    /// traps in view mode. Linked root functions are checked as the root
+    /// calls host functions.
+    fn has_side_effects(&self, index: usize) -> bool {
+        match index {
+            index if (externals::DEBUG..externals::INSTRUMENT).contains(&index) => {
+                debug::prints(index - externals::DEBUG)
+            }
+            index if (externals::EXTENSION..externals::LINKED).contains(&index) => true,
+            index if index >= externals::LINKED => false,
+            index => externals::has_side_effects(index),
+        }
+    }
+
+    /// Returns the name of the child's host function at `index`. Linked root
+    /// functions are prefixed with their `root` namespace.
+    fn host_name(&self, index: usize) -> String {
+        match index {
+            index if (externals::DEBUG..externals::INSTRUMENT).contains(&index) => {
+                debug::name(index - externals::DEBUG).to_string()
+            }
+            index if (externals::EXTENSION..externals::LINKED).contains(&index) => self
+                .root()
+                .registry()
+                .get(index - externals::EXTENSION)
+                .name()
+                .to_string(),
+            index if index >= externals::LINKED => {
+                format!("root.{}", self.links[index - externals::LINKED].0)
+            }
+            index => externals::name(index)
+                .expect("unknown function index")
+                .to_string(),
+        }
+    }
+
     fn ext_print(&self, args: RuntimeArgs) -> ExtResult {
         let memory = self.memory();
 
         let ptr: u32 = args.nth(0);
         let len: u32 = args.nth(1);
 
-        let bytes = memory.get(ptr, len as usize).unwrap();
+        let bytes = memory
+            .get(ptr, len as usize)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         self.root().print(&bytes);
 
@@ -166,18 +467,104 @@ impl<'a> ChildRuntime<'a> {
 
 struct ChildExternals<'a, 'b>(&'a ChildRuntime<'b>);
 
-impl<'a, 'b> Externals for ChildExternals<'a, 'b> {
-    fn invoke_index(
-        &mut self,
-        index: usize,
-        args: RuntimeArgs,
-    ) -> Result<Option<RuntimeValue>, Trap> {
+impl<'a, 'b> ChildExternals<'a, 'b> {
+    fn dispatch(&mut self, index: usize, args: RuntimeArgs) -> ExtResult {
         match index {
             externals::CALL => self.0.ext_call(args),
+            externals::CALL_TYPED => self.0.ext_call_typed(args),
+            externals::CALL_MODULE => self.0.ext_call_module(args),
+            externals::CALL_MODULE_TYPED => self.0.ext_call_module_typed(args),
             externals::ARGUMENT => self.0.ext_argument(args),
             externals::RETURN => self.0.ext_return(args),
+            externals::BUFFER_GET => self.0.root().ext_buffer_get(&self.0.memory(), args),
+            externals::BUFFER_SET => self.0.root().ext_buffer_set(&self.0.memory(), args),
+            externals::BUFFER_MERGE => self.0.root().ext_buffer_merge(args),
+            externals::BUFFER_CLEAR => self.0.root().ext_buffer_clear(args),
+            externals::BUFFER_COUNT => self.0.root().ext_buffer_count(args),
+            externals::BUFFER_KEYS => self.0.root().ext_buffer_keys(&self.0.memory(), args),
+            externals::BUFFER_DELETE => self.0.root().ext_buffer_delete(&self.0.memory(), args),
+            externals::BUFFER_COPY => self.0.root().ext_buffer_copy(args),
+            externals::BUFFER_MERGE_MISSING => self.0.root().ext_buffer_merge_missing(args),
+            externals::BUFFER_MERGE_CHECKED => self
+                .0
+                .root()
+                .ext_buffer_merge_checked(&self.0.memory(), args),
+            externals::BLOCK_DATA_SIZE => self.0.root().ext_block_data_size(args),
+            externals::BLOCK_DATA_COPY => self.0.root().ext_block_data_copy(&self.0.memory(), args),
+            externals::BLOCK_DATA_COPY_V2 => {
+                self.0.root().ext_block_data_copy_v2(&self.0.memory(), args)
+            }
+            externals::LOAD_PRE_STATE_ROOT => self
+                .0
+                .root()
+                .ext_load_pre_state_root(&self.0.memory(), args),
+            externals::SHARED_MEMORY_SIZE => self.0.root().ext_shared_memory_size(args),
+            externals::SHARED_MEMORY_COPY => {
+                self.0.root().ext_shared_memory_copy(&self.0.memory(), args)
+            }
+            externals::GAS => self.0.ext_gas(args),
             externals::PRINT => self.0.ext_print(args),
+            index if (externals::DEBUG..externals::INSTRUMENT).contains(&index) => debug::invoke(
+                &self.0.root(),
+                &self.0.memory(),
+                index - externals::DEBUG,
+                args,
+            ),
+            index if (externals::EXTENSION..externals::LINKED).contains(&index) => {
+                let root = self.0.root();
+                root.call_extension(
+                    index - externals::EXTENSION,
+                    self.0.memory(),
+                    Some(self.0.slot),
+                    args,
+                )
+            }
+            index if index >= externals::LINKED => {
+                self.0.ext_linked(index - externals::LINKED, args)
+            }
             _ => panic!("unknown function index"),
         }
     }
 }
+
+impl<'a, 'b> Externals for ChildExternals<'a, 'b> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let root = self.0.root();
+
+        // Calls added by instrumentation aren't traced.
+        if (externals::INSTRUMENT..externals::EXTENSION).contains(&index) {
+            let names = &self.0.function_names;
+            let slot = Some(self.0.slot);
+            return instrument::invoke(&root, slot, names, index - externals::INSTRUMENT, args);
+        }
+
+        if root.debugging() {
+            root.break_at(Stop::HostCall {
+                caller: Some(self.0.slot),
+                name: self.0.host_name(index),
+                args: args.as_ref().to_vec(),
+            });
+        }
+
+        if root.viewing() && self.0.has_side_effects(index) {
+            let function = self.0.host_name(index);
+            return Err(Error::ViewViolation { function }.into());
+        }
+
+        if !root.observing() {
+            return self.dispatch(index, args);
+        }
+
+        let name = self.0.host_name(index);
+        let extension = (externals::EXTENSION..externals::LINKED).contains(&index);
+        let memory = self.0.memory();
+
+        root.host_call(Some(self.0.slot), name, extension, &memory, args, |args| {
+            self.dispatch(index, args)
+        })
+    }
+}
@@ -2,21 +2,199 @@ pub mod externals {
     pub const CALL: usize = 1;
     pub const ARGUMENT: usize = 2;
     pub const RETURN: usize = 3;
+    pub const CALL_TYPED: usize = 4;
+    pub const CALL_MODULE: usize = 5;
+    pub const CALL_MODULE_TYPED: usize = 6;
+    pub const BUFFER_GET: usize = 7;
+    pub const BUFFER_SET: usize = 8;
+    pub const BUFFER_MERGE: usize = 9;
+    pub const BUFFER_CLEAR: usize = 10;
+    pub const BUFFER_COUNT: usize = 11;
+    pub const BUFFER_KEYS: usize = 12;
+    pub const BUFFER_DELETE: usize = 13;
+    pub const BUFFER_COPY: usize = 14;
+    pub const BUFFER_MERGE_MISSING: usize = 15;
+    pub const BUFFER_MERGE_CHECKED: usize = 16;
+    pub const BLOCK_DATA_SIZE: usize = 17;
+    pub const BLOCK_DATA_COPY: usize = 18;
+    pub const LOAD_PRE_STATE_ROOT: usize = 19;
+    pub const GAS: usize = 20;
+    pub const BLOCK_DATA_COPY_V2: usize = 21;
+    pub const SHARED_MEMORY_SIZE: usize = 22;
+    pub const SHARED_MEMORY_COPY: usize = 23;
     pub const PRINT: usize = 99;
+
+    /// Indices from `DEBUG` up to `INSTRUMENT` refer to the functions of the
+    /// `debug` namespace.
+    pub const DEBUG: usize = 1 << 12;
+
+    /// Indices from `INSTRUMENT` up to `EXTENSION` refer to the functions of the
+    /// instrumentation namespace.
+    pub const INSTRUMENT: usize = 1 << 13;
+
+    /// Indices from `EXTENSION` up to `LINKED` refer to host functions added
+    /// by the embedder, in registration order.
+    pub const EXTENSION: usize = crate::env::registry::FUNC_INDEX;
+
+    /// Indices from `LINKED` onwards refer to root functions linked through
+    /// the `root` import namespace, in the order they were resolved.
+    pub const LINKED: usize = 1 << 16;
+
+    /// Returns the name of the built-in host function at `index`.
+    pub fn name(index: usize) -> Option<&'static str> {
+        let name = match index {
+            CALL => "eth2_call",
+            ARGUMENT => "eth2_argument",
+            RETURN => "eth2_return",
+            CALL_TYPED => "eth2_callTyped",
+            CALL_MODULE => "eth2_callModule",
+            CALL_MODULE_TYPED => "eth2_callModuleTyped",
+            BUFFER_GET => "eth2_bufferGet",
+            BUFFER_SET => "eth2_bufferSet",
+            BUFFER_MERGE => "eth2_bufferMerge",
+            BUFFER_CLEAR => "eth2_bufferClear",
+            BUFFER_COUNT => "eth2_bufferCount",
+            BUFFER_KEYS => "eth2_bufferKeys",
+            BUFFER_DELETE => "eth2_bufferDelete",
+            BUFFER_COPY => "eth2_bufferCopy",
+            BUFFER_MERGE_MISSING => "eth2_bufferMergeMissing",
+            BUFFER_MERGE_CHECKED => "eth2_bufferMergeChecked",
+            BLOCK_DATA_SIZE => "eth2_blockDataSize",
+            BLOCK_DATA_COPY => "eth2_blockDataCopy",
+            LOAD_PRE_STATE_ROOT => "eth2_loadPreStateRoot",
+            GAS => "gas",
+            BLOCK_DATA_COPY_V2 => "eth2_v2.eth2_blockDataCopy",
+            SHARED_MEMORY_SIZE => "eth2_sharedMemorySize",
+            SHARED_MEMORY_COPY => "eth2_sharedMemoryCopy",
+            PRINT => "print",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+
+    /// Whether the built-in host function at `index` has side effects, and so
+    /// traps in view mode.
+    pub fn has_side_effects(index: usize) -> bool {
+        matches!(
+            index,
+            BUFFER_SET
+                | BUFFER_MERGE
+                | BUFFER_MERGE_MISSING
+                | BUFFER_MERGE_CHECKED
+                | BUFFER_CLEAR
+                | BUFFER_DELETE
+                | BUFFER_COPY
+                | PRINT
+        )
+    }
 }
 
+/// Bits of the capability mask passed to `eth2_loadModuleWithCapabilities`.
+/// A child may only import the host functions covered by the capabilities it
+/// was granted. `eth2_argument` and `eth2_return` are always available.
+pub mod capabilities {
+    /// `eth2_call`, `eth2_callTyped` and the `root` import namespace.
+    pub const CALL_ROOT: u32 = 1 << 0;
+
+    /// `eth2_callModule` and `eth2_callModuleTyped`.
+    pub const CALL_MODULE: u32 = 1 << 1;
+
+    /// `print`.
+    pub const LOGGING: u32 = 1 << 2;
+
+    /// `eth2_sharedMemorySize` and `eth2_sharedMemoryCopy`, reading the shared
+    /// memory filled by the root.
+    pub const SHARED_MEMORY: u32 = 1 << 3;
+
+    /// The `eth2_buffer*` functions, operating on the root's buffer.
+    pub const BUFFER: u32 = 1 << 4;
+
+    /// Read-only access to the block data and pre-state root:
+    /// `eth2_blockDataSize`, `eth2_blockDataCopy` and `eth2_loadPreStateRoot`.
+    pub const BLOCK_DATA: u32 = 1 << 5;
+
+    /// Host functions added by the embedder.
+    pub const EXTENSIONS: u32 = 1 << 6;
+
+    /// Granted by `eth2_loadModule`.
+    pub const DEFAULT: u32 = CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY;
+
+    pub const ALL: u32 = DEFAULT | BUFFER | BLOCK_DATA | EXTENSIONS;
+}
+
+use crate::env::abi::Abi;
+use crate::env::registry::{self, Registry};
+use crate::env::root::RootRuntime;
+
+use std::cell::RefCell;
+
 use wasmi::{
     Error as InterpreterError, FuncInstance, FuncRef, ModuleImportResolver, Signature, ValueType,
 };
 
-pub struct ChildModuleImportResolver;
+pub struct ChildModuleImportResolver<'r, 'a> {
+    abi: Abi,
+    capabilities: u32,
+    registry: &'r Registry<'a>,
+
+    /// Whether the module is metered, and so may import `gas`.
+    metered: bool,
+}
+
+impl<'r, 'a> ChildModuleImportResolver<'r, 'a> {
+    pub fn new(abi: Abi, capabilities: u32, registry: &'r Registry<'a>, metered: bool) -> Self {
+        Self {
+            abi,
+            capabilities,
+            registry,
+            metered,
+        }
+    }
+
+    fn require(&self, capability: u32, field_name: &str) -> Result<(), InterpreterError> {
+        require(self.capabilities, capability, field_name)
+    }
+}
+
+fn require(granted: u32, capability: u32, field_name: &str) -> Result<(), InterpreterError> {
+    if granted & capability == capability {
+        Ok(())
+    } else {
+        Err(refuse(field_name))
+    }
+}
 
-impl<'a> ModuleImportResolver for ChildModuleImportResolver {
+fn refuse(field_name: &str) -> InterpreterError {
+    InterpreterError::Instantiation(format!("module isn't permitted to import {}", field_name))
+}
+
+impl<'r, 'a> ModuleImportResolver for ChildModuleImportResolver<'r, 'a> {
     fn resolve_func(
         &self,
         field_name: &str,
         _signature: &Signature,
     ) -> Result<FuncRef, InterpreterError> {
+        let required = match field_name {
+            "eth2_call" | "eth2_callTyped" => capabilities::CALL_ROOT,
+            "eth2_callModule" | "eth2_callModuleTyped" => capabilities::CALL_MODULE,
+            "print" => capabilities::LOGGING,
+            name if name.starts_with("eth2_buffer") => capabilities::BUFFER,
+            "eth2_blockDataSize" | "eth2_blockDataCopy" | "eth2_loadPreStateRoot" => {
+                capabilities::BLOCK_DATA
+            }
+            "eth2_sharedMemorySize" | "eth2_sharedMemoryCopy" => capabilities::SHARED_MEMORY,
+            _ => 0,
+        };
+
+        self.require(required, field_name)?;
+
+        // `gas` is only resolved for the calls injected into metered modules,
+        // so that a module can't charge gas on its own.
+        if field_name == "gas" && !self.metered {
+            return Err(refuse(field_name));
+        }
+
         let func_ref = match field_name {
             "eth2_return" => FuncInstance::alloc_host(
                 // eth2_return(offset: u32, length: u32) -> u32
@@ -33,18 +211,187 @@ impl<'a> ModuleImportResolver for ChildModuleImportResolver {
                 Signature::new(&[ValueType::I32; 6][..], Some(ValueType::I32)),
                 externals::CALL,
             ),
+            "eth2_callTyped" => FuncInstance::alloc_host(
+                // eth2_callTyped(name, name_len, params, params_count, results, results_count)
+                Signature::new(&[ValueType::I32; 6][..], Some(ValueType::I32)),
+                externals::CALL_TYPED,
+            ),
+            "eth2_callModule" => FuncInstance::alloc_host(
+                // eth2_callModule(slot, name, name_len, arg, arg_len, ret, ret_len)
+                Signature::new(&[ValueType::I32; 7][..], Some(ValueType::I32)),
+                externals::CALL_MODULE,
+            ),
+            "eth2_callModuleTyped" => FuncInstance::alloc_host(
+                // eth2_callModuleTyped(slot, name, name_len, params, params_count, results, results_count)
+                Signature::new(&[ValueType::I32; 7][..], Some(ValueType::I32)),
+                externals::CALL_MODULE_TYPED,
+            ),
+            "eth2_bufferGet" => FuncInstance::alloc_host(
+                // eth2_bufferGet(frame, key, value) -> u32
+                Signature::new(&[ValueType::I32; 3][..], Some(ValueType::I32)),
+                externals::BUFFER_GET,
+            ),
+            "eth2_bufferSet" => FuncInstance::alloc_host(
+                // eth2_bufferSet(frame, key, value)
+                Signature::new(&[ValueType::I32; 3][..], None),
+                externals::BUFFER_SET,
+            ),
+            "eth2_bufferMerge" => FuncInstance::alloc_host(
+                // eth2_bufferMerge(a, b)
+                Signature::new(&[ValueType::I32; 2][..], None),
+                externals::BUFFER_MERGE,
+            ),
+            "eth2_bufferClear" => FuncInstance::alloc_host(
+                // eth2_bufferClear(frame)
+                Signature::new(&[ValueType::I32][..], None),
+                externals::BUFFER_CLEAR,
+            ),
+            "eth2_bufferCount" => FuncInstance::alloc_host(
+                // eth2_bufferCount(frame) -> u32
+                Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                externals::BUFFER_COUNT,
+            ),
+            "eth2_bufferKeys" => FuncInstance::alloc_host(
+                // eth2_bufferKeys(frame, dest, dest_len) -> u32
+                Signature::new(&[ValueType::I32; 3][..], Some(ValueType::I32)),
+                externals::BUFFER_KEYS,
+            ),
+            "eth2_bufferDelete" => FuncInstance::alloc_host(
+                // eth2_bufferDelete(frame, key) -> u32
+                Signature::new(&[ValueType::I32; 2][..], Some(ValueType::I32)),
+                externals::BUFFER_DELETE,
+            ),
+            "eth2_bufferCopy" => FuncInstance::alloc_host(
+                // eth2_bufferCopy(src, dest) -> u32
+                Signature::new(&[ValueType::I32; 2][..], Some(ValueType::I32)),
+                externals::BUFFER_COPY,
+            ),
+            "eth2_bufferMergeMissing" => FuncInstance::alloc_host(
+                // eth2_bufferMergeMissing(a, b)
+                Signature::new(&[ValueType::I32; 2][..], None),
+                externals::BUFFER_MERGE_MISSING,
+            ),
+            "eth2_bufferMergeChecked" => FuncInstance::alloc_host(
+                // eth2_bufferMergeChecked(a, b, dest, dest_len) -> u32
+                Signature::new(&[ValueType::I32; 4][..], Some(ValueType::I32)),
+                externals::BUFFER_MERGE_CHECKED,
+            ),
+            "eth2_blockDataSize" => FuncInstance::alloc_host(
+                // eth2_blockDataSize() -> u32
+                Signature::new(&[][..], Some(ValueType::I32)),
+                externals::BLOCK_DATA_SIZE,
+            ),
+            "eth2_blockDataCopy" => FuncInstance::alloc_host(
+                // eth2_blockDataCopy(dest, offset, length)
+                Signature::new(&[ValueType::I32; 3][..], None),
+                match self.abi {
+                    Abi::V1 => externals::BLOCK_DATA_COPY,
+                    Abi::V2 => externals::BLOCK_DATA_COPY_V2,
+                },
+            ),
+            "eth2_loadPreStateRoot" => FuncInstance::alloc_host(
+                // eth2_loadPreStateRoot(dest)
+                Signature::new(&[ValueType::I32][..], None),
+                externals::LOAD_PRE_STATE_ROOT,
+            ),
+            "eth2_sharedMemorySize" => FuncInstance::alloc_host(
+                // eth2_sharedMemorySize() -> u32
+                Signature::new(&[][..], Some(ValueType::I32)),
+                externals::SHARED_MEMORY_SIZE,
+            ),
+            "eth2_sharedMemoryCopy" => FuncInstance::alloc_host(
+                // eth2_sharedMemoryCopy(dest, offset, length)
+                Signature::new(&[ValueType::I32; 3][..], None),
+                externals::SHARED_MEMORY_COPY,
+            ),
+            "gas" => FuncInstance::alloc_host(
+                // gas(amount), injected into metered modules
+                Signature::new(&[ValueType::I32][..], None),
+                externals::GAS,
+            ),
             "print" => FuncInstance::alloc_host(
                 // print(ptr, len)
                 Signature::new(&[ValueType::I32; 2][..], None),
                 externals::PRINT,
             ),
-            _ => {
-                return Err(InterpreterError::Function(format!(
-                    "host module doesn't export function with name {}",
-                    field_name
-                )))
-            }
+            // Host functions only the root can import, like
+            // `eth2_savePostStateRoot`.
+            name if registry::is_builtin(name) => return Err(refuse(field_name)),
+            _ => match self.registry.find(field_name) {
+                Some((index, function)) => {
+                    self.require(capabilities::EXTENSIONS, field_name)?;
+
+                    FuncInstance::alloc_host(
+                        function.signature().clone(),
+                        externals::EXTENSION + index,
+                    )
+                }
+                None => {
+                    return Err(InterpreterError::Function(format!(
+                        "host module doesn't export function with name {}",
+                        field_name
+                    )))
+                }
+            },
         };
         Ok(func_ref)
     }
 }
+
+/// Resolves imports from the `root` namespace to functions the root has
+/// exposed with `eth2_expose`, so that a child can call them directly instead
+/// of going through `eth2_call`. Missing or mismatched targets are reported
+/// when the child is loaded.
+pub struct RootLinker<'a> {
+    root: RootRuntime<'a>,
+    capabilities: u32,
+    links: RefCell<Vec<(String, FuncRef)>>,
+}
+
+impl<'a> RootLinker<'a> {
+    pub fn new(root: RootRuntime<'a>, capabilities: u32) -> Self {
+        Self {
+            root,
+            capabilities,
+            links: Default::default(),
+        }
+    }
+
+    /// Returns the names of the linked root functions and the functions
+    /// themselves, indexed by their offset from `externals::LINKED`.
+    pub fn into_links(self) -> Vec<(String, FuncRef)> {
+        self.links.into_inner()
+    }
+}
+
+impl<'a> ModuleImportResolver for RootLinker<'a> {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        require(self.capabilities, capabilities::CALL_ROOT, field_name)?;
+
+        let target = self.root.exposed(field_name).ok_or_else(|| {
+            InterpreterError::Function(format!(
+                "root doesn't expose function with name {}",
+                field_name
+            ))
+        })?;
+
+        if target.signature() != signature {
+            return Err(InterpreterError::Function(format!(
+                "root function {} has signature {:?}, but was imported as {:?}",
+                field_name,
+                target.signature(),
+                signature
+            )));
+        }
+
+        let mut links = self.links.borrow_mut();
+        let index = externals::LINKED + links.len();
+        links.push((field_name.to_string(), target));
+
+        Ok(FuncInstance::alloc_host(signature.clone(), index))
+    }
+}
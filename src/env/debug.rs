@@ -0,0 +1,154 @@
+//! Host functions served from the `debug` namespace when the runtime is built
+//! with debugging enabled. Output goes straight to the embedder's logger,
+//! bypassing the journal, so that it isn't lost when a call is rolled back.
+
+use crate::env::root::RootRuntime;
+use crate::error::Error;
+
+use super::ExtResult;
+
+use wasmi::{
+    Error as InterpreterError, FuncInstance, FuncRef, MemoryRef, ModuleImportResolver, RuntimeArgs,
+    Signature, Trap, TrapKind, ValueType,
+};
+
+pub const NAMESPACE: &str = "debug";
+
+const PRINT_MEM: usize = 0;
+const PRINT_MEM_HEX: usize = 1;
+const PRINT_I32: usize = 2;
+const PRINT_I64: usize = 3;
+const PRINT_BUFFER: usize = 4;
+const ABORT: usize = 5;
+
+/// Resolves the `debug` namespace. Indices are offset by `base`, so that the
+/// functions can share an index space with the rest of the host functions.
+pub struct DebugModuleImportResolver {
+    base: usize,
+}
+
+impl DebugModuleImportResolver {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+}
+
+impl ModuleImportResolver for DebugModuleImportResolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        _signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        let (params, index): (&[ValueType], usize) = match field_name {
+            "debug_printMem" => (&[ValueType::I32; 2], PRINT_MEM),
+            "debug_printMemHex" => (&[ValueType::I32; 2], PRINT_MEM_HEX),
+            "debug_printI32" => (&[ValueType::I32], PRINT_I32),
+            "debug_printI64" => (&[ValueType::I64], PRINT_I64),
+            "debug_printBuffer" => (&[ValueType::I32], PRINT_BUFFER),
+            "debug_abort" => (&[ValueType::I32; 2], ABORT),
+            _ => {
+                return Err(InterpreterError::Function(format!(
+                    "debug module doesn't export function with name {}",
+                    field_name
+                )))
+            }
+        };
+
+        Ok(FuncInstance::alloc_host(
+            Signature::new(params, None),
+            self.base + index,
+        ))
+    }
+}
+
+/// Returns the name of the debug function at `index` (without the resolver's
+/// base).
+pub fn name(index: usize) -> &'static str {
+    match index {
+        PRINT_MEM => "debug_printMem",
+        PRINT_MEM_HEX => "debug_printMemHex",
+        PRINT_I32 => "debug_printI32",
+        PRINT_I64 => "debug_printI64",
+        PRINT_BUFFER => "debug_printBuffer",
+        ABORT => "debug_abort",
+        _ => panic!("unknown function index"),
+    }
+}
+
+/// Whether the debug function at `index` (without the resolver's base)
+/// prints, which isn't permitted in view mode.
+pub fn prints(index: usize) -> bool {
+    index != ABORT
+}
+
+/// Invokes the debug function at `index` (without the resolver's base) for a
+/// module whose memory is `memory`.
+pub fn invoke(
+    root: &RootRuntime,
+    memory: &MemoryRef,
+    index: usize,
+    args: RuntimeArgs,
+) -> ExtResult {
+    match index {
+        PRINT_MEM => {
+            let bytes = read(memory, &args)?;
+            root.debug(&String::from_utf8_lossy(&bytes));
+        }
+        PRINT_MEM_HEX => {
+            let bytes = read(memory, &args)?;
+            root.debug(&hex(&bytes));
+        }
+        PRINT_I32 => {
+            let value: i32 = args.nth(0);
+            root.debug(&value.to_string());
+        }
+        PRINT_I64 => {
+            let value: i64 = args.nth(0);
+            root.debug(&value.to_string());
+        }
+        PRINT_BUFFER => {
+            let frame: u32 = args.nth(0);
+            root.debug(&format!("buffer frame {}:", frame));
+
+            for (key, value) in root.buffer_entries(frame) {
+                root.debug(&format!("  {}: {}", hex(&key), hex(&value)));
+            }
+        }
+        ABORT => {
+            let bytes = read(memory, &args)?;
+            let message = String::from_utf8_lossy(&bytes).into_owned();
+
+            root.debug(&format!("abort: {}", message));
+
+            return Err(Error::Aborted { message }.into());
+        }
+        _ => panic!("unknown function index"),
+    }
+
+    Ok(None)
+}
+
+/// Reads the bytes described by an `offset: u32, length: u32` argument pair.
+fn read(memory: &MemoryRef, args: &RuntimeArgs) -> Result<Vec<u8>, Trap> {
+    let offset: u32 = args.nth(0);
+    let length: u32 = args.nth(1);
+
+    memory
+        .get(offset, length as usize)
+        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex_encoding() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0x00, 0x0f, 0xab]), "000fab");
+    }
+}
@@ -0,0 +1,377 @@
+//! An interactive debugger for execution environments. wasmi can't suspend
+//! execution, so the runtime calls into a `Debugger` whenever it reaches a
+//! point where execution should stop, and resumes once the debugger returns.
+
+use crate::backtrace::Backtrace;
+use crate::env::root::RootRuntime;
+use crate::env::trace::Frame;
+
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use wasmi::RuntimeValue;
+
+/// Where execution may stop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// A wasm function is about to run its first instruction.
+    FunctionEntry {
+        module: Option<u32>,
+        index: u32,
+        name: String,
+    },
+
+    /// A module is about to call a host function.
+    HostCall {
+        caller: Option<u32>,
+        name: String,
+        args: Vec<RuntimeValue>,
+    },
+
+    /// A cross-module call is about to invoke `name`.
+    CallEnter { callee: Option<u32>, name: String },
+
+    /// A cross-module call finished.
+    CallExit { callee: Option<u32>, name: String },
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::FunctionEntry {
+                module,
+                index,
+                name,
+            } => write!(
+                f,
+                "entering {} in {} (function {})",
+                name,
+                module_name(*module),
+                index
+            ),
+            Stop::HostCall { caller, name, args } => {
+                write!(f, "{} calling {}{:?}", module_name(*caller), name, args)
+            }
+            Stop::CallEnter { callee, name } => {
+                write!(f, "calling {} in {}", name, module_name(*callee))
+            }
+            Stop::CallExit { callee, name } => {
+                write!(f, "returned from {} in {}", name, module_name(*callee))
+            }
+        }
+    }
+}
+
+fn module_name(module: Option<u32>) -> String {
+    match module {
+        Some(slot) => format!("child {}", slot),
+        None => "root".to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops when a wasm function with this name is entered, in any module.
+    Function(String),
+
+    /// Stops before a host function with this name is called.
+    HostFunction(String),
+}
+
+impl Breakpoint {
+    fn matches(&self, stop: &Stop) -> bool {
+        match (self, stop) {
+            (Breakpoint::Function(name), Stop::FunctionEntry { name: entered, .. }) => {
+                name == entered
+            }
+            (Breakpoint::HostFunction(name), Stop::HostCall { name: called, .. }) => name == called,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Function(name) => write!(f, "function {}", name),
+            Breakpoint::HostFunction(name) => write!(f, "host function {}", name),
+        }
+    }
+}
+
+/// How to resume execution after a stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Stop at the next function entry, host call or cross-module call,
+    /// whichever module it happens in.
+    Step,
+}
+
+/// Called by the runtime when execution stops. Install one with
+/// `RootRuntimeBuilder::debugger`. Execution stops at the first opportunity,
+/// so that breakpoints can be set before anything runs.
+pub trait Debugger {
+    fn stop(&mut self, session: &mut Session, stop: &Stop) -> Action;
+}
+
+/// A cross-module call in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The module that was called, or `None` for the root.
+    pub callee: Option<u32>,
+    pub name: String,
+    pub frame: Frame,
+}
+
+pub(crate) struct State<'a> {
+    debugger: Box<dyn Debugger + 'a>,
+    breakpoints: Vec<Breakpoint>,
+    stepping: bool,
+}
+
+impl<'a> State<'a> {
+    pub fn new(debugger: Box<dyn Debugger + 'a>) -> Self {
+        Self {
+            debugger,
+            breakpoints: Vec::new(),
+            stepping: true,
+        }
+    }
+
+    /// Hands control to the debugger if execution should stop at `stop`.
+    pub fn check(&mut self, runtime: &RootRuntime, stop: &Stop) {
+        let hit = self.breakpoints.iter().any(|b| b.matches(stop));
+
+        if !self.stepping && !hit {
+            return;
+        }
+
+        let mut session = Session {
+            runtime,
+            breakpoints: &mut self.breakpoints,
+        };
+
+        self.stepping = self.debugger.stop(&mut session, stop) == Action::Step;
+    }
+}
+
+/// What a debugger can see and change while execution is stopped.
+pub struct Session<'s, 'r, 'a> {
+    runtime: &'r RootRuntime<'a>,
+    breakpoints: &'s mut Vec<Breakpoint>,
+}
+
+impl<'s, 'r, 'a> Session<'s, 'r, 'a> {
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Removes the breakpoint at `index` in `breakpoints`, returning it.
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        if index < self.breakpoints.len() {
+            Some(self.breakpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Reads the memory of the root, represented by `None`, or the child in
+    /// `slot`. Returns `None` if the module or the range doesn't exist.
+    pub fn read_memory(&self, module: Option<u32>, offset: u32, length: u32) -> Option<Vec<u8>> {
+        self.runtime
+            .module_memory(module)?
+            .get(offset, length as usize)
+            .ok()
+    }
+
+    /// Returns the entries of a frame of the root's buffer, ordered by key.
+    pub fn buffer(&self, frame: u32) -> Vec<([u8; 32], [u8; 32])> {
+        self.runtime.buffer_entries(frame)
+    }
+
+    /// Returns the cross-module calls in progress, innermost first.
+    pub fn call_stack(&self) -> Vec<CallFrame> {
+        self.runtime.calls()
+    }
+
+    /// Returns the wasm functions being executed, innermost first.
+    pub fn backtrace(&self) -> Backtrace {
+        self.runtime.backtrace()
+    }
+}
+
+const HELP: &str = "\
+commands:
+  s, step                     stop at the next function, host call or module call
+  c, continue                 run until the next breakpoint
+  b, break <function>         stop when a wasm function is entered
+  h, hbreak <host function>   stop before a host function is called
+  l, list                     list breakpoints
+  d, delete <n>               delete breakpoint n
+  bt, backtrace               show the wasm functions being executed
+  calls                       show the cross-module calls in progress
+  m, mem <offset> <length> [slot]
+                              dump memory of the root, or of a child
+  buf, buffer <frame>         show a frame of the buffer
+  help                        show this message";
+
+/// A debugger driven by text commands, such as those typed on a terminal.
+/// Execution continues to the end once the input is exhausted.
+pub struct Console<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.input, self.output)
+    }
+
+    /// Runs `command`, returning an action if it resumes execution.
+    fn command(&mut self, session: &mut Session, command: &str) -> Option<Action> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let out = &mut self.output;
+
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["s"] | ["step"] => return Some(Action::Step),
+            ["c"] | ["continue"] => return Some(Action::Continue),
+            ["b", name] | ["break", name] => {
+                session.add_breakpoint(Breakpoint::Function(name.to_string()));
+                Ok(())
+            }
+            ["h", name] | ["hbreak", name] => {
+                session.add_breakpoint(Breakpoint::HostFunction(name.to_string()));
+                Ok(())
+            }
+            ["l"] | ["list"] => session
+                .breakpoints()
+                .iter()
+                .enumerate()
+                .try_for_each(|(index, breakpoint)| writeln!(out, "{}: {}", index, breakpoint)),
+            ["d", index] | ["delete", index] => {
+                match index
+                    .parse()
+                    .ok()
+                    .and_then(|i| session.remove_breakpoint(i))
+                {
+                    Some(_) => Ok(()),
+                    None => writeln!(out, "no breakpoint {}", index),
+                }
+            }
+            ["bt"] | ["backtrace"] => write!(out, "{}", session.backtrace()),
+            ["calls"] => session.call_stack().iter().try_for_each(|call| {
+                writeln!(
+                    out,
+                    "{} in {}: argument {}+{}, return {}+{}",
+                    call.name,
+                    module_name(call.callee),
+                    call.frame.argument_offset,
+                    call.frame.argument_length,
+                    call.frame.return_offset,
+                    call.frame.return_length
+                )
+            }),
+            ["m", args @ ..] | ["mem", args @ ..] if args.len() == 2 || args.len() == 3 => {
+                let offset = args[0].parse();
+                let length = args[1].parse();
+                let slot = args.get(2).map(|slot| slot.parse());
+
+                match (offset, length, slot) {
+                    (Ok(offset), Ok(length), None) => {
+                        dump(out, session.read_memory(None, offset, length))
+                    }
+                    (Ok(offset), Ok(length), Some(Ok(slot))) => {
+                        dump(out, session.read_memory(Some(slot), offset, length))
+                    }
+                    _ => writeln!(out, "usage: mem <offset> <length> [slot]"),
+                }
+            }
+            ["buf", frame] | ["buffer", frame] => match frame.parse() {
+                Ok(frame) => session
+                    .buffer(frame)
+                    .iter()
+                    .try_for_each(|(key, value)| writeln!(out, "{}: {}", hex(key), hex(value))),
+                Err(_) => writeln!(out, "usage: buffer <frame>"),
+            },
+            ["help"] => writeln!(out, "{}", HELP),
+            _ => writeln!(out, "unknown command, try help"),
+        };
+
+        result.expect("debugger output to be written");
+        None
+    }
+}
+
+impl<R: BufRead, W: Write> Debugger for Console<R, W> {
+    fn stop(&mut self, session: &mut Session, stop: &Stop) -> Action {
+        writeln!(self.output, "stopped: {}", stop).expect("debugger output to be written");
+
+        loop {
+            write!(self.output, "(ewasm) ").expect("debugger output to be written");
+            self.output.flush().expect("debugger output to be written");
+
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Action::Continue,
+                Ok(_) => (),
+            }
+
+            if let Some(action) = self.command(session, &line) {
+                return action;
+            }
+        }
+    }
+}
+
+fn dump<W: Write>(out: &mut W, bytes: Option<Vec<u8>>) -> std::io::Result<()> {
+    match bytes {
+        Some(bytes) => writeln!(out, "{}", hex(&bytes)),
+        None => writeln!(out, "memory out of range"),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn breakpoints_match_stops() {
+        let entry = Stop::FunctionEntry {
+            module: Some(1),
+            index: 3,
+            name: "transfer".to_string(),
+        };
+
+        let host = Stop::HostCall {
+            caller: None,
+            name: "eth2_bufferGet".to_string(),
+            args: Vec::new(),
+        };
+
+        let function = Breakpoint::Function("transfer".to_string());
+        let host_function = Breakpoint::HostFunction("eth2_bufferGet".to_string());
+
+        assert!(function.matches(&entry));
+        assert!(!function.matches(&host));
+        assert!(host_function.matches(&host));
+        assert!(!host_function.matches(&entry));
+    }
+}
@@ -0,0 +1,454 @@
+//! Instrumentation that makes modules report the functions they enter and
+//! exit, and optionally the number of instructions they execute, to host
+//! functions in the `ewasm_instrument` namespace. The runtime uses the reports
+//! to keep a stack of wasm frames for backtraces, and to profile execution.
+
+use crate::env::root::RootRuntime;
+
+use super::ExtResult;
+
+use parity_wasm::elements::{
+    self, BlockType, External, FunctionType, ImportEntry, ImportSection, Instruction, Instructions,
+    Internal, Module, Section, Type, ValueType as WasmValueType,
+};
+
+use wasmi::{
+    Error as InterpreterError, FuncInstance, FuncRef, ModuleImportResolver, RuntimeArgs, Signature,
+    ValueType,
+};
+
+pub const NAMESPACE: &str = "ewasm_instrument";
+
+const ENTER: usize = 0;
+const EXIT: usize = 1;
+const COUNT: usize = 2;
+
+/// Resolves the instrumentation namespace. Indices are offset by `base`, so
+/// that the functions can share an index space with the rest of the host
+/// functions.
+pub struct InstrumentModuleImportResolver {
+    base: usize,
+}
+
+impl InstrumentModuleImportResolver {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+}
+
+impl ModuleImportResolver for InstrumentModuleImportResolver {
+    fn resolve_func(
+        &self,
+        field_name: &str,
+        _signature: &Signature,
+    ) -> Result<FuncRef, InterpreterError> {
+        let index = match field_name {
+            "enter" => ENTER,
+            "exit" => EXIT,
+            "count" => COUNT,
+            _ => {
+                return Err(InterpreterError::Function(format!(
+                    "instrumentation module doesn't export function with name {}",
+                    field_name
+                )))
+            }
+        };
+
+        Ok(FuncInstance::alloc_host(
+            Signature::new(&[ValueType::I32][..], None),
+            self.base + index,
+        ))
+    }
+}
+
+/// Invokes the instrumentation function at `index` (without the resolver's
+/// base) for the root, represented by `None`, or the child in slot `module`,
+/// whose functions are named by `names`.
+pub fn invoke(
+    root: &RootRuntime,
+    module: Option<u32>,
+    names: &FunctionNames,
+    index: usize,
+    args: RuntimeArgs,
+) -> ExtResult {
+    match index {
+        ENTER => {
+            let position: u32 = args.nth(0);
+            root.enter_function(module, position, names);
+        }
+        EXIT => root.exit_function(),
+        COUNT => {
+            let instructions: u32 = args.nth(0);
+            root.count_instructions(u64::from(instructions));
+        }
+        _ => panic!("unknown function index"),
+    }
+
+    Ok(None)
+}
+
+/// Names of the functions a module defines, in the order they're defined.
+///
+/// Names come from the module's name section, falling back to the name a
+/// function is exported under, and then to `func[index]`.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionNames {
+    imported: u32,
+    names: Vec<String>,
+}
+
+impl FunctionNames {
+    /// Reads the names from `code`, before it is rewritten in any way.
+    pub fn read(code: &[u8]) -> Result<Self, InterpreterError> {
+        let module = elements::deserialize_buffer::<Module>(code)
+            .map_err(|error| InterpreterError::Instantiation(error.to_string()))?;
+        let module = module.parse_names().unwrap_or_else(|(_, module)| module);
+
+        let imported = module.import_count(elements::ImportCountType::Function);
+        let mut names = names(&module);
+
+        Ok(Self {
+            imported: imported as u32,
+            names: names.split_off(imported),
+        })
+    }
+
+    /// Number of functions the module defines.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Index of the function defined at `position` in the module's function
+    /// index space, which starts with the imported functions.
+    pub fn index(&self, position: u32) -> u32 {
+        self.imported + position
+    }
+
+    pub fn name(&self, position: u32) -> &str {
+        &self.names[position as usize]
+    }
+}
+
+/// Rewrites the first `functions` functions defined by `code` so that they
+/// report to the instrumentation namespace, identifying themselves by their
+/// position. Instruction counts are reported if `count` is set.
+///
+/// Functions appended by earlier rewrites, like gas metering, are left alone,
+/// as are the instructions those rewrites added: metering is applied first,
+/// and only charges for the module's own instructions.
+pub fn instrument(code: &[u8], functions: usize, count: bool) -> Result<Vec<u8>, InterpreterError> {
+    let mut module = elements::deserialize_buffer::<Module>(code)
+        .map_err(|error| InterpreterError::Instantiation(error.to_string()))?;
+
+    if module.code_section().is_none() {
+        return Ok(code.to_vec());
+    }
+
+    let imported = module.import_count(elements::ImportCountType::Function) as u32;
+    let signature = add_type(&mut module);
+
+    // The new imports come after the existing ones, so every function defined
+    // by the module moves up by their number.
+    let imports: &[&str] = if count {
+        &["enter", "exit", "count"]
+    } else {
+        &["enter", "exit"]
+    };
+    add_imports(&mut module, imports, signature);
+    renumber(&mut module, imported, imports.len() as u32);
+
+    let enter = imported;
+    let exit = imported + 1;
+    let count = if count { Some(imported + 2) } else { None };
+
+    let results: Vec<Option<WasmValueType>> = {
+        let types = module.type_section().map(|s| s.types()).unwrap_or(&[]);
+
+        module
+            .function_section()
+            .map(|s| s.entries())
+            .unwrap_or(&[])
+            .iter()
+            .map(|func| match &types[func.type_ref() as usize] {
+                Type::Function(function) => function.return_type(),
+            })
+            .collect()
+    };
+
+    let bodies = module.code_section_mut().unwrap().bodies_mut();
+    for (position, body) in bodies.iter_mut().enumerate().take(functions) {
+        let code = body.code_mut();
+        *code = wrap(code, position as u32, results[position], enter, exit, count);
+    }
+
+    elements::serialize(module).map_err(|error| InterpreterError::Instantiation(error.to_string()))
+}
+
+fn names(module: &Module) -> Vec<String> {
+    let mut names: Vec<String> = (0..module.functions_space())
+        .map(|index| format!("func[{}]", index))
+        .collect();
+
+    if let Some(exports) = module.export_section() {
+        for export in exports.entries() {
+            if let Internal::Function(index) = export.internal() {
+                names[*index as usize] = export.field().to_string();
+            }
+        }
+    }
+
+    if let Some(functions) = module.names_section().and_then(|s| s.functions()) {
+        for (index, name) in functions.names().iter() {
+            if let Some(slot) = names.get_mut(index as usize) {
+                *slot = name.clone();
+            }
+        }
+    }
+
+    names
+}
+
+/// Returns the index of the `(i32) -> ()` type, adding it if necessary.
+fn add_type(module: &mut Module) -> u32 {
+    let signature = FunctionType::new(vec![WasmValueType::I32], None);
+
+    let types = module
+        .type_section_mut()
+        .expect("module with code has a type section")
+        .types_mut();
+
+    let existing = types
+        .iter()
+        .position(|Type::Function(function)| *function == signature);
+
+    match existing {
+        Some(index) => index as u32,
+        None => {
+            types.push(Type::Function(signature));
+            types.len() as u32 - 1
+        }
+    }
+}
+
+fn add_imports(module: &mut Module, names: &[&str], signature: u32) {
+    if module.import_section().is_none() {
+        module
+            .insert_section(Section::Import(ImportSection::default()))
+            .expect("import section to be missing");
+    }
+
+    let entries = module.import_section_mut().unwrap().entries_mut();
+
+    for name in names {
+        entries.push(ImportEntry::new(
+            NAMESPACE.to_string(),
+            name.to_string(),
+            External::Function(signature),
+        ));
+    }
+}
+
+/// Moves the functions at or above `from` up by `by` in the function index
+/// space.
+fn renumber(module: &mut Module, from: u32, by: u32) {
+    let shift = |index: &mut u32| {
+        if *index >= from {
+            *index += by;
+        }
+    };
+
+    if let Some(code) = module.code_section_mut() {
+        for body in code.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+
+    if let Some(exports) = module.export_section_mut() {
+        for export in exports.entries_mut() {
+            if let Internal::Function(index) = export.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+
+    if let Some(elements) = module.elements_section_mut() {
+        for segment in elements.entries_mut() {
+            segment.members_mut().iter_mut().for_each(shift);
+        }
+    }
+
+    if let Some(mut start) = module.start_section() {
+        shift(&mut start);
+        module.set_start_section(start);
+    }
+}
+
+/// Wraps the body of the function defined at `position` in a block that is
+/// preceded by a call to `enter` and followed by a call to `exit`, turning
+/// `return` into a branch out of the block. If there's a `count` function,
+/// each run of instructions up to the next control instruction is preceded by
+/// a call to it with the run's length.
+fn wrap(
+    code: &Instructions,
+    position: u32,
+    result: Option<WasmValueType>,
+    enter: u32,
+    exit: u32,
+    count: Option<u32>,
+) -> Instructions {
+    let original = code.elements();
+
+    // The final `end` closes the function, and is added back at the end.
+    let body = &original[..original.len() - 1];
+
+    let block = match result {
+        Some(value) => BlockType::Value(value),
+        None => BlockType::NoResult,
+    };
+
+    let mut wrapped = vec![
+        Instruction::I32Const(position as i32),
+        Instruction::Call(enter),
+        Instruction::Block(block),
+    ];
+
+    let mut depth = 0;
+
+    for run in body.split_inclusive(is_control) {
+        if let Some(count) = count {
+            wrapped.push(Instruction::I32Const(run.len() as i32));
+            wrapped.push(Instruction::Call(count));
+        }
+
+        for instruction in run {
+            match instruction {
+                Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => depth += 1,
+                Instruction::End => depth -= 1,
+                _ => (),
+            }
+
+            wrapped.push(match instruction {
+                Instruction::Return => Instruction::Br(depth),
+                instruction => instruction.clone(),
+            });
+        }
+    }
+
+    wrapped.extend(vec![
+        Instruction::End,
+        Instruction::I32Const(position as i32),
+        Instruction::Call(exit),
+        Instruction::End,
+    ]);
+
+    Instructions::new(wrapped)
+}
+
+fn is_control(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Block(_)
+            | Instruction::Loop(_)
+            | Instruction::If(_)
+            | Instruction::Else
+            | Instruction::End
+            | Instruction::Br(_)
+            | Instruction::BrIf(_)
+            | Instruction::BrTable(_)
+            | Instruction::Return
+            | Instruction::Unreachable
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parity_wasm::elements::{FunctionNameSubsection, NameSection};
+    use wabt::wat2wasm;
+
+    fn without_names(code: &[u8]) -> Module {
+        let mut module = elements::deserialize_buffer::<Module>(code).unwrap();
+
+        module
+            .sections_mut()
+            .retain(|section| !matches!(section, Section::Custom(_) | Section::Name(_)));
+
+        module
+    }
+
+    #[test]
+    fn names_fall_back_to_exports() {
+        let code = wat2wasm(
+            r#"
+            (module
+                (import "env" "print" (func (param i32) (param i32)))
+                (func (export "main"))
+                (func))
+            "#,
+        )
+        .unwrap();
+
+        let module = without_names(&code);
+
+        assert_eq!(names(&module), vec!["func[0]", "main", "func[2]"]);
+    }
+
+    #[test]
+    fn names_from_name_section() {
+        let code = wat2wasm(r#"(module (func (export "main")) (func))"#).unwrap();
+
+        let mut module = without_names(&code);
+
+        let mut functions = FunctionNameSubsection::default();
+        functions.names_mut().insert(1, "helper".to_string());
+        module
+            .sections_mut()
+            .push(Section::Name(NameSection::new(None, Some(functions), None)));
+
+        assert_eq!(names(&module), vec!["main", "helper"]);
+    }
+
+    #[test]
+    fn return_becomes_branch() {
+        let code = Instructions::new(vec![
+            Instruction::Block(BlockType::NoResult),
+            Instruction::Return,
+            Instruction::End,
+            Instruction::I32Const(1),
+            Instruction::Return,
+            Instruction::End,
+        ]);
+
+        let wrapped = wrap(&code, 7, Some(WasmValueType::I32), 0, 1, Some(2));
+
+        assert_eq!(
+            wrapped.elements(),
+            &[
+                Instruction::I32Const(7),
+                Instruction::Call(0),
+                Instruction::Block(BlockType::Value(WasmValueType::I32)),
+                Instruction::I32Const(1),
+                Instruction::Call(2),
+                Instruction::Block(BlockType::NoResult),
+                Instruction::I32Const(1),
+                Instruction::Call(2),
+                Instruction::Br(1),
+                Instruction::I32Const(1),
+                Instruction::Call(2),
+                Instruction::End,
+                Instruction::I32Const(2),
+                Instruction::Call(2),
+                Instruction::I32Const(1),
+                Instruction::Br(0),
+                Instruction::End,
+                Instruction::I32Const(7),
+                Instruction::Call(1),
+                Instruction::End,
+            ][..]
+        );
+    }
+}
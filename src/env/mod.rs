@@ -1,12 +1,37 @@
+pub mod abi;
 pub mod child;
+pub mod debug;
+pub mod debugger;
+pub mod instrument;
+pub mod profile;
+pub mod registry;
+pub mod replay;
 pub mod root;
+pub mod shared;
+pub mod trace;
+pub mod values;
+
+use std::cell::Cell;
+use std::rc::Rc;
 
 use typed_builder::TypedBuilder;
 
-use wasmi::{MemoryInstance, MemoryRef, RuntimeValue, Trap};
+use crate::error::Error;
+
+use wasmi::{MemoryInstance, MemoryRef, RuntimeValue, Trap, TrapKind};
 
 pub type ExtResult = Result<Option<RuntimeValue>, Trap>;
 
+/// Reads the name of a function passed to a host function. Traps if the name
+/// isn't within `memory` or isn't valid UTF-8.
+fn read_name(memory: &MemoryRef, offset: u32, length: u32) -> Result<String, Trap> {
+    let bytes = memory
+        .get(offset, length as usize)
+        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+    String::from_utf8(bytes).map_err(|_| Error::InvalidName.into())
+}
+
 #[derive(Debug, Clone, TypedBuilder)]
 struct StackFrame {
     memory: MemoryRef,
@@ -16,6 +41,10 @@ struct StackFrame {
 
     return_offset: u32,
     return_length: u32,
+
+    /// The number of bytes copied by the callee's most recent `eth2_return`.
+    #[builder(default)]
+    returned: Rc<Cell<u32>>,
 }
 
 impl StackFrame {
@@ -52,6 +81,9 @@ impl StackFrame {
             self.return_offset as usize,
             len as usize,
         )
-        .map(|_| self.return_length)
+        .map(|_| {
+            self.returned.set(len);
+            self.return_length
+        })
     }
 }
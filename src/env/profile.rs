@@ -0,0 +1,92 @@
+//! Profiling of instructions executed by the root and its children. When the
+//! runtime is built with profiling enabled, modules are instrumented to report
+//! the instructions they execute, which are attributed to the stack of
+//! functions executing them.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Instructions executed, keyed by the stack of functions that executed them.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    /// The current stack, joined with `;`.
+    stack: String,
+
+    /// Length of `stack` before each of its frames was entered.
+    frames: Vec<usize>,
+
+    counts: BTreeMap<String, u64>,
+}
+
+impl Profiler {
+    pub fn enter(&mut self, frame: &str) {
+        self.frames.push(self.stack.len());
+
+        if !self.stack.is_empty() {
+            self.stack.push(';');
+        }
+
+        self.stack.push_str(frame);
+    }
+
+    pub fn exit(&mut self) {
+        if let Some(length) = self.frames.pop() {
+            self.stack.truncate(length);
+        }
+    }
+
+    pub fn count(&mut self, instructions: u64) {
+        if self.frames.is_empty() {
+            return;
+        }
+
+        *self.counts.entry(self.stack.clone()).or_insert(0) += instructions;
+    }
+
+    /// Drops the frames above `depth`, left behind by functions that trapped.
+    pub fn unwind(&mut self, depth: usize) {
+        while self.frames.len() > depth {
+            self.exit();
+        }
+    }
+
+    /// Writes one `stack count` line per stack, the folded format read by
+    /// flamegraph tools.
+    pub fn write_folded<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (stack, count) in &self.counts {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn folded_stacks() {
+        let mut profiler = Profiler::default();
+
+        profiler.enter("root:main");
+        profiler.count(2);
+        profiler.enter("child0:main");
+        profiler.count(3);
+        profiler.enter("child0:helper");
+        profiler.count(1);
+
+        // A trap in the child leaves its frames behind.
+        profiler.unwind(1);
+        profiler.count(4);
+        profiler.exit();
+
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "root:main 6\nroot:main;child0:main 3\nroot:main;child0:main;child0:helper 1\n"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::buffer::Buffer;
+use crate::env::root::RootRuntime;
+
+use super::ExtResult;
+
+use std::cell::RefCell;
+
+use wasmi::{MemoryRef, RuntimeArgs, Signature};
+
+/// Index of the first registered host function, shared by the root's and the
+/// children's host function indices. The rest follow in registration order.
+pub const FUNC_INDEX: usize = 1 << 15;
+
+/// Names of the host functions the runtime provides to the root or to
+/// children, in any namespace. Embedders can't register host functions under
+/// these names.
+pub const BUILTINS: &[&str] = &[
+    "eth2_loadPreStateRoot",
+    "eth2_blockDataSize",
+    "eth2_blockDataCopy",
+    "eth2_savePostStateRoot",
+    "eth2_bufferGet",
+    "eth2_bufferSet",
+    "eth2_bufferMerge",
+    "eth2_bufferClear",
+    "eth2_bufferCount",
+    "eth2_bufferKeys",
+    "eth2_bufferDelete",
+    "eth2_bufferCopy",
+    "eth2_bufferMergeMissing",
+    "eth2_bufferMergeChecked",
+    "eth2_loadModule",
+    "eth2_loadModuleWithCapabilities",
+    "eth2_loadModuleWithLimits",
+    "eth2_limitModules",
+    "eth2_expose",
+    "eth2_allowCall",
+    "eth2_call",
+    "eth2_callTyped",
+    "eth2_callModule",
+    "eth2_callModuleTyped",
+    "eth2_argument",
+    "eth2_return",
+    "eth2_sharedMemoryWrite",
+    "eth2_sharedMemorySize",
+    "eth2_sharedMemoryCopy",
+    "gas",
+    "print",
+];
+
+/// Whether `name` is one of the host functions provided by the runtime.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+type Callback<'a> = Box<dyn Fn(&HostContext, RuntimeArgs) -> ExtResult + 'a>;
+
+/// A host function added by the embedder, importable from the `env` namespace.
+pub struct HostFunction<'a> {
+    name: String,
+    signature: Signature,
+    callback: Callback<'a>,
+}
+
+impl<'a> HostFunction<'a> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+}
+
+/// Host functions added by the embedder, indexed in registration order.
+#[derive(Default)]
+pub struct Registry<'a>(Vec<HostFunction<'a>>);
+
+impl<'a> Registry<'a> {
+    /// Adds a host function. Panics if `name` is provided by the runtime or
+    /// has already been registered.
+    pub fn register<F>(&mut self, name: &str, signature: Signature, callback: F)
+    where
+        F: Fn(&HostContext, RuntimeArgs) -> ExtResult + 'a,
+    {
+        assert!(
+            !is_builtin(name),
+            "host function {} is provided by the runtime",
+            name
+        );
+        assert!(
+            self.find(name).is_none(),
+            "host function {} registered twice",
+            name
+        );
+
+        self.0.push(HostFunction {
+            name: name.to_string(),
+            signature,
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Returns the index and definition of the host function `name`.
+    pub fn find(&self, name: &str) -> Option<(usize, &HostFunction<'a>)> {
+        self.0.iter().enumerate().find(|(_, f)| f.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HostFunction<'a>> {
+        self.0.iter()
+    }
+
+    pub fn get(&self, index: usize) -> &HostFunction<'a> {
+        &self.0[index]
+    }
+
+    pub fn invoke(&self, index: usize, context: &HostContext, args: RuntimeArgs) -> ExtResult {
+        (self.0[index].callback)(context, args)
+    }
+}
+
+/// What a registered host function can see of the runtime while it runs.
+pub struct HostContext<'c, 'a> {
+    runtime: &'c RootRuntime<'a>,
+    memory: MemoryRef,
+    caller: Option<u32>,
+}
+
+impl<'c, 'a> HostContext<'c, 'a> {
+    pub(crate) fn new(
+        runtime: &'c RootRuntime<'a>,
+        memory: MemoryRef,
+        caller: Option<u32>,
+    ) -> Self {
+        Self {
+            runtime,
+            memory,
+            caller,
+        }
+    }
+
+    /// The memory of the module calling the host function.
+    pub fn memory(&self) -> &MemoryRef {
+        &self.memory
+    }
+
+    /// The slot of the calling child, or `None` if the root is calling.
+    pub fn caller(&self) -> Option<u32> {
+        self.caller
+    }
+
+    pub fn block_data(&self) -> &[u8] {
+        self.runtime.block_data()
+    }
+
+    pub fn pre_state_root(&self) -> [u8; 32] {
+        self.runtime.pre_state_root()
+    }
+
+    /// Emits a log message through the runtime's logger.
+    pub fn log(&self, message: &str) {
+        self.runtime.print(message.as_bytes())
+    }
+
+    /// The runtime's buffer, shared with the `eth2_buffer*` functions.
+    pub fn buffer(&self) -> HostBuffer<'c> {
+        HostBuffer(self.runtime.buffer())
+    }
+}
+
+/// A handle to the runtime's buffer for registered host functions. Writes are
+/// undone with the rest of a cross-module call's changes if the call fails.
+pub struct HostBuffer<'c>(&'c RefCell<Buffer>);
+
+impl<'c> HostBuffer<'c> {
+    pub fn get(&self, frame: u32, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.0.borrow().get(frame, key).copied()
+    }
+
+    /// Sets `key` in `frame`, returning the value it replaced.
+    pub fn set(&self, frame: u32, key: [u8; 32], value: [u8; 32]) -> Option<[u8; 32]> {
+        self.0.borrow_mut().insert(frame, key, value)
+    }
+
+    /// Removes `key` from `frame`, returning its value.
+    pub fn remove(&self, frame: u32, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.0.borrow_mut().remove(frame, key)
+    }
+
+    /// Returns the number of keys set in `frame`.
+    pub fn len(&self, frame: u32) -> usize {
+        self.0.borrow().len(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::env::child::resolver::externals;
+    use crate::env::root::resolver;
+
+    #[test]
+    fn builtins_cover_resolvers() {
+        let root = (0..resolver::DEBUG_FUNC_INDEX).filter_map(resolver::name);
+        let child = (0..externals::DEBUG).filter_map(externals::name);
+
+        for name in root.chain(child) {
+            // Versioned names are served under the same field name.
+            let name = name.rsplit('.').next().unwrap();
+            assert!(is_builtin(name), "{} is missing from BUILTINS", name);
+        }
+    }
+}
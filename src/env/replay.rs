@@ -0,0 +1,767 @@
+//! Recording of executions, and replay of recordings to check that a runtime
+//! still executes them the same way.
+//!
+//! A recording holds everything needed to re-execute the root: its code, the
+//! block data, the pre-state root, the settings of the runtime, and the
+//! results of the host functions added by the embedder, which aren't available
+//! on replay. Every host call is recorded, so that replay can report the first
+//! one that behaves differently.
+
+use crate::env::child::GasSchedule;
+use crate::env::root::RootRuntime;
+use crate::env::trace::Module;
+use crate::error::Error;
+use crate::execute::Execute;
+use crate::memory::MemoryConfig;
+
+use super::ExtResult;
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use wasmi::memory_units::Bytes;
+use wasmi::{MemoryRef, RuntimeValue, Signature, Trap, ValueType};
+
+const HEADER: &str = "ewasm-recording 1";
+
+/// A host function invocation, as seen by the module that made it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    /// The calling module: the root, represented by `None`, or a child slot.
+    pub caller: Module,
+    pub name: String,
+    pub args: Vec<RuntimeValue>,
+
+    /// Memory written by a host function added by the embedder, as offsets
+    /// into the caller's memory and the bytes written there. Empty for the
+    /// runtime's own host functions, which replay executes again.
+    pub writes: Vec<(u32, Vec<u8>)>,
+
+    /// The value returned, or the message of the trap raised.
+    pub result: Result<Option<RuntimeValue>, String>,
+}
+
+/// A self-contained record of an execution. Enable recording with
+/// `RootRuntimeBuilder::record`, and fetch the recording from
+/// `RootRuntime::recording` once `execute` returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub pre_root: [u8; 32],
+
+    pub gas_limit: u64,
+    pub gas_schedule: GasSchedule,
+    pub memory: MemoryConfig,
+    pub call_depth_limit: Option<usize>,
+    pub host_functions: u32,
+    pub debug: bool,
+    pub view: bool,
+
+    /// Names and signatures of the host functions added by the embedder.
+    pub extensions: Vec<(String, Signature)>,
+
+    pub calls: Vec<RecordedCall>,
+
+    /// The post-state root, or the message of the trap raised by the root.
+    pub outcome: Result<[u8; 32], String>,
+}
+
+/// The first difference between a replay and its recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The host call at `index` differs. A call is `None` if the execution
+    /// ended before making it.
+    HostCall {
+        index: usize,
+        expected: Option<RecordedCall>,
+        actual: Option<RecordedCall>,
+    },
+
+    /// Every host call matched, but the execution ended differently.
+    Outcome {
+        expected: Result<[u8; 32], String>,
+        actual: Result<[u8; 32], String>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let call = |call: &Option<RecordedCall>| match call {
+            Some(call) => write_call(call),
+            None => "nothing".to_string(),
+        };
+
+        match self {
+            Divergence::HostCall {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "host call {} diverged\n  expected: {}\n    actual: {}",
+                index,
+                call(expected),
+                call(actual)
+            ),
+            Divergence::Outcome { expected, actual } => write!(
+                f,
+                "outcome diverged\n  expected: {}\n    actual: {}",
+                write_outcome(expected),
+                write_outcome(actual)
+            ),
+        }
+    }
+}
+
+impl Recording {
+    /// Re-executes the recording, serving the embedder's host functions from
+    /// it, and returns the first divergence from it, if there is one.
+    pub fn replay(&self) -> Option<Divergence> {
+        let mut builder = RootRuntime::builder(&self.code, &self.data, self.pre_root)
+            .gas_limit(self.gas_limit)
+            .gas_schedule(self.gas_schedule)
+            .memory(self.memory)
+            .host_functions(self.host_functions)
+            .debug(self.debug)
+            .view(self.view)
+            .replay(self.calls.clone());
+
+        if let Some(limit) = self.call_depth_limit {
+            builder = builder.call_depth_limit(limit);
+        }
+
+        for (name, signature) in &self.extensions {
+            builder = builder.host_function(name, signature.clone(), |_, _| {
+                unreachable!("replayed host functions are served from the recording")
+            });
+        }
+
+        let mut runtime = match builder.build() {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                return Some(Divergence::Outcome {
+                    expected: self.outcome.clone(),
+                    actual: Err(message(&error)),
+                })
+            }
+        };
+        let outcome = runtime.execute().map_err(|error| message(&error));
+
+        let replayer = runtime.replayer().expect("runtime built for replay");
+        let divergence = replayer.borrow().finish(&self.outcome, outcome);
+        divergence
+    }
+
+    /// Writes the recording in a line-based text format.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "code {}", hex(&self.code))?;
+        writeln!(writer, "data {}", hex(&self.data))?;
+        writeln!(writer, "pre_root {}", hex(&self.pre_root))?;
+        writeln!(writer, "gas_limit {}", self.gas_limit)?;
+        writeln!(
+            writer,
+            "gas_schedule {} {}",
+            self.gas_schedule.instruction, self.gas_schedule.memory_grow
+        )?;
+        writeln!(
+            writer,
+            "memory {} {}",
+            self.memory.extra_pages,
+            optional(self.memory.max_pages)
+        )?;
+        writeln!(
+            writer,
+            "call_depth_limit {}",
+            optional(self.call_depth_limit)
+        )?;
+        writeln!(writer, "host_functions {}", self.host_functions)?;
+        writeln!(writer, "debug {}", self.debug)?;
+        writeln!(writer, "view {}", self.view)?;
+
+        for (name, signature) in &self.extensions {
+            let params = signature
+                .params()
+                .iter()
+                .map(|t| type_name(*t).to_string())
+                .collect();
+
+            writeln!(
+                writer,
+                "extension {} {} {}",
+                name,
+                list(params),
+                signature.return_type().map_or("-", type_name)
+            )?;
+        }
+
+        for call in &self.calls {
+            writeln!(writer, "call {}", write_call(call))?;
+        }
+
+        writeln!(writer, "{}", write_outcome(&self.outcome))
+    }
+
+    /// Reads a recording written by `write`.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines().enumerate();
+
+        let mut next = |key: &str| -> io::Result<(usize, String)> {
+            let (number, line) = match lines.next() {
+                Some((number, line)) => (number + 1, line?),
+                None => return Err(invalid(0, &format!("missing {}", key))),
+            };
+
+            match line.strip_prefix(key) {
+                Some(rest) => Ok((number, rest.trim_start().to_string())),
+                None => Err(invalid(number, &format!("expected {}", key))),
+            }
+        };
+
+        next(HEADER)?;
+
+        let code = parse(next("code")?, unhex)?;
+        let data = parse(next("data")?, unhex)?;
+        let pre_root = parse(next("pre_root")?, |s| {
+            let bytes = unhex(s)?;
+            if bytes.len() != 32 {
+                return None;
+            }
+
+            let mut root = [0u8; 32];
+            root.copy_from_slice(&bytes);
+            Some(root)
+        })?;
+        let gas_limit = parse(next("gas_limit")?, |s| s.parse().ok())?;
+        let gas_schedule = parse(next("gas_schedule")?, |s| {
+            let mut words = s.split(' ');
+            let instruction = words.next()?.parse().ok()?;
+            let memory_grow = words.next()?.parse().ok()?;
+            Some(GasSchedule {
+                instruction,
+                memory_grow,
+            })
+        })?;
+        let memory = parse(next("memory")?, |s| {
+            let mut words = s.split(' ');
+            let extra_pages = words.next()?.parse().ok()?;
+            let max_pages = read_optional(words.next()?)?;
+            Some(MemoryConfig {
+                extra_pages,
+                max_pages,
+            })
+        })?;
+        let call_depth_limit = parse(next("call_depth_limit")?, read_optional)?;
+        let host_functions = parse(next("host_functions")?, |s| s.parse().ok())?;
+        let debug = parse(next("debug")?, |s| s.parse().ok())?;
+        let view = parse(next("view")?, |s| s.parse().ok())?;
+
+        let mut extensions = Vec::new();
+        let mut calls = Vec::new();
+
+        let outcome = loop {
+            let (number, line) = match lines.next() {
+                Some((number, line)) => (number + 1, line?),
+                None => return Err(invalid(0, "missing outcome")),
+            };
+
+            let (key, rest) = match line.find(' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => (line.as_str(), ""),
+            };
+
+            let parsed = match key {
+                "extension" => read_extension(rest).map(|e| extensions.push(e)),
+                "call" => read_call(rest).map(|c| calls.push(c)),
+                "post_root" | "trap" => match read_outcome(&line) {
+                    Some(outcome) => break outcome,
+                    None => None,
+                },
+                _ => None,
+            };
+
+            if parsed.is_none() {
+                return Err(invalid(number, "malformed line"));
+            }
+        };
+
+        Ok(Self {
+            code,
+            data,
+            pre_root,
+            gas_limit,
+            gas_schedule,
+            memory,
+            call_depth_limit,
+            host_functions,
+            debug,
+            view,
+            extensions,
+            calls,
+            outcome,
+        })
+    }
+}
+
+/// Collects the host calls made while recording.
+pub(crate) struct Recorder {
+    recording: Recording,
+    finished: bool,
+}
+
+impl Recorder {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            finished: false,
+        }
+    }
+
+    /// Returns the recording, once an execution has finished.
+    pub fn recording(&self) -> Option<Recording> {
+        if self.finished {
+            Some(self.recording.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn start(&mut self, gas_limit: u64) {
+        self.recording.gas_limit = gas_limit;
+    }
+
+    pub fn record(&mut self, call: RecordedCall) {
+        self.recording.calls.push(call);
+    }
+
+    pub fn finish(&mut self, outcome: Result<[u8; 32], String>) {
+        self.recording.outcome = outcome;
+        self.finished = true;
+    }
+}
+
+/// Checks host calls made on replay against those recorded.
+pub(crate) struct Replayer {
+    calls: Vec<RecordedCall>,
+    next: usize,
+    divergence: Option<Divergence>,
+}
+
+impl Replayer {
+    pub fn new(calls: Vec<RecordedCall>) -> Self {
+        Self {
+            calls,
+            next: 0,
+            divergence: None,
+        }
+    }
+
+    /// Returns the recorded call if it is the one `caller` is about to make.
+    pub fn expected(
+        &self,
+        caller: Module,
+        name: &str,
+        args: &[RuntimeValue],
+    ) -> Option<&RecordedCall> {
+        if self.divergence.is_some() {
+            return None;
+        }
+
+        self.calls
+            .get(self.next)
+            .filter(|call| call.caller == caller && call.name == name && call.args == args)
+    }
+
+    /// Compares a call with the one recorded in its place. The first call
+    /// that differs, and every call after it, traps.
+    pub fn check(&mut self, actual: RecordedCall) -> Result<(), Trap> {
+        if self.divergence.is_none() {
+            let expected = self.calls.get(self.next);
+
+            if expected != Some(&actual) {
+                self.divergence = Some(Divergence::HostCall {
+                    index: self.next,
+                    expected: expected.cloned(),
+                    actual: Some(actual),
+                });
+            }
+        }
+
+        if self.divergence.is_some() {
+            return Err(Error::Diverged { index: self.next }.into());
+        }
+
+        self.next += 1;
+        Ok(())
+    }
+
+    fn finish(
+        &self,
+        expected: &Result<[u8; 32], String>,
+        actual: Result<[u8; 32], String>,
+    ) -> Option<Divergence> {
+        if let Some(divergence) = &self.divergence {
+            return Some(divergence.clone());
+        }
+
+        if self.next < self.calls.len() {
+            return Some(Divergence::HostCall {
+                index: self.next,
+                expected: Some(self.calls[self.next].clone()),
+                actual: None,
+            });
+        }
+
+        if *expected != actual {
+            return Some(Divergence::Outcome {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+
+        None
+    }
+}
+
+/// Returns the contents of `memory`, to find what a host function writes.
+pub(crate) fn snapshot(memory: &MemoryRef) -> Vec<u8> {
+    let size: Bytes = memory.current_size().into();
+    memory.get(0, size.0).expect("memory to be readable")
+}
+
+/// Returns the ranges of `after` that differ from `before`.
+pub(crate) fn writes(before: &[u8], after: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut writes: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for (offset, byte) in after.iter().enumerate() {
+        if before.get(offset) == Some(byte) {
+            continue;
+        }
+
+        match writes.last_mut() {
+            Some((start, bytes)) if *start as usize + bytes.len() == offset => bytes.push(*byte),
+            _ => writes.push((offset as u32, vec![*byte])),
+        }
+    }
+
+    writes
+}
+
+/// Applies writes recorded for a host function to `memory`, and returns the
+/// result it recorded.
+pub(crate) fn serve(memory: &MemoryRef, call: &RecordedCall) -> ExtResult {
+    for (offset, bytes) in &call.writes {
+        memory
+            .set(*offset, bytes)
+            .expect("recorded write in bounds");
+    }
+
+    call.result.clone().map_err(|message| {
+        Error::Trap {
+            message,
+            backtrace: Default::default(),
+        }
+        .into()
+    })
+}
+
+/// Returns the message recorded for an error returned by `execute`.
+pub(crate) fn message(error: &Error) -> String {
+    match error {
+        Error::Trap { message, .. } => message.clone(),
+        error => error.to_string(),
+    }
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("recording line {}: {}", line, message),
+    )
+}
+
+fn parse<T, F>((number, value): (usize, String), f: F) -> io::Result<T>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    f(&value).ok_or_else(|| invalid(number, "malformed value"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        return Some(Vec::new());
+    }
+
+    if s.len() % 2 == 1 {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+fn read_optional<T: std::str::FromStr>(s: &str) -> Option<Option<T>> {
+    match s {
+        "-" => Some(None),
+        s => s.parse().ok().map(Some),
+    }
+}
+
+fn list(items: Vec<String>) -> String {
+    if items.is_empty() {
+        "-".to_string()
+    } else {
+        items.join(",")
+    }
+}
+
+fn split_list(s: &str) -> Vec<&str> {
+    if s == "-" {
+        Vec::new()
+    } else {
+        s.split(',').collect()
+    }
+}
+
+fn type_name(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::I32 => "i32",
+        ValueType::I64 => "i64",
+        ValueType::F32 => "f32",
+        ValueType::F64 => "f64",
+    }
+}
+
+fn read_type(s: &str) -> Option<ValueType> {
+    match s {
+        "i32" => Some(ValueType::I32),
+        "i64" => Some(ValueType::I64),
+        "f32" => Some(ValueType::F32),
+        "f64" => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+/// Writes a value as its type and bits, so that floats survive exactly.
+fn write_value(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::I32(v) => format!("i32:{}", v),
+        RuntimeValue::I64(v) => format!("i64:{}", v),
+        RuntimeValue::F32(v) => format!("f32:{}", v.to_bits()),
+        RuntimeValue::F64(v) => format!("f64:{}", v.to_bits()),
+    }
+}
+
+fn read_value(s: &str) -> Option<RuntimeValue> {
+    let (kind, value) = s.split_at(s.find(':')?);
+    let value = &value[1..];
+
+    match kind {
+        "i32" => value.parse::<i32>().ok().map(RuntimeValue::I32),
+        "i64" => value.parse::<i64>().ok().map(RuntimeValue::I64),
+        "f32" => value.parse::<u32>().ok().map(RuntimeValue::decode_f32),
+        "f64" => value.parse::<u64>().ok().map(RuntimeValue::decode_f64),
+        _ => None,
+    }
+}
+
+fn read_extension(s: &str) -> Option<(String, Signature)> {
+    let words: Vec<_> = s.split(' ').collect();
+
+    match words.as_slice() {
+        [name, params, result] => {
+            let params = split_list(params)
+                .into_iter()
+                .map(read_type)
+                .collect::<Option<Vec<_>>>()?;
+
+            let result = match *result {
+                "-" => None,
+                result => Some(read_type(result)?),
+            };
+
+            Some((name.to_string(), Signature::new(params, result)))
+        }
+        _ => None,
+    }
+}
+
+/// Writes a call as `caller name args writes result`, where the result is
+/// `ok`, `ok value` or `err message`, with the message running to the end
+/// of the line.
+fn write_call(call: &RecordedCall) -> String {
+    let caller = optional(call.caller);
+    let args = list(call.args.iter().map(write_value).collect());
+    let writes = list(
+        call.writes
+            .iter()
+            .map(|(offset, bytes)| format!("{}:{}", offset, hex(bytes)))
+            .collect(),
+    );
+
+    let result = match &call.result {
+        Ok(Some(value)) => format!("ok {}", write_value(value)),
+        Ok(None) => "ok".to_string(),
+        Err(message) => format!("err {}", escape(message)),
+    };
+
+    format!("{} {} {} {} {}", caller, call.name, args, writes, result)
+}
+
+fn read_call(s: &str) -> Option<RecordedCall> {
+    let mut words = s.splitn(6, ' ');
+
+    let caller = read_optional(words.next()?)?;
+    let name = words.next()?.to_string();
+
+    let args = split_list(words.next()?)
+        .into_iter()
+        .map(read_value)
+        .collect::<Option<Vec<_>>>()?;
+
+    let writes = split_list(words.next()?)
+        .into_iter()
+        .map(|write| {
+            let (offset, bytes) = write.split_at(write.find(':')?);
+            Some((offset.parse().ok()?, unhex(&bytes[1..])?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let result = match (words.next()?, words.next()) {
+        ("ok", None) => Ok(None),
+        ("ok", Some(value)) => Ok(Some(read_value(value)?)),
+        ("err", message) => Err(unescape(message.unwrap_or(""))?),
+        _ => return None,
+    };
+
+    Some(RecordedCall {
+        caller,
+        name,
+        args,
+        writes,
+        result,
+    })
+}
+
+fn write_outcome(outcome: &Result<[u8; 32], String>) -> String {
+    match outcome {
+        Ok(root) => format!("post_root {}", hex(root)),
+        Err(message) => format!("trap {}", escape(message)),
+    }
+}
+
+fn read_outcome(s: &str) -> Option<Result<[u8; 32], String>> {
+    if let Some(root) = s.strip_prefix("post_root ") {
+        let bytes = unhex(root)?;
+        if bytes.len() != 32 {
+            return None;
+        }
+
+        let mut root = [0u8; 32];
+        root.copy_from_slice(&bytes);
+        return Some(Ok(root));
+    }
+
+    s.strip_prefix("trap ").and_then(unescape).map(Err)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '\\' => unescaped.push('\\'),
+                'n' => unescaped.push('\n'),
+                _ => return None,
+            },
+            c => unescaped.push(c),
+        }
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changed_ranges() {
+        let before = [0, 0, 0, 0, 0, 0];
+        let after = [0, 1, 2, 0, 3, 0, 4];
+
+        assert_eq!(
+            writes(&before, &after),
+            vec![(1, vec![1, 2]), (4, vec![3]), (6, vec![4])]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = Recording {
+            code: vec![0, 0x61, 0x73, 0x6d],
+            data: Vec::new(),
+            pre_root: [7u8; 32],
+            gas_limit: 1000,
+            gas_schedule: GasSchedule {
+                instruction: 2,
+                memory_grow: 3,
+            },
+            memory: MemoryConfig {
+                extra_pages: 1,
+                max_pages: Some(4),
+            },
+            call_depth_limit: None,
+            host_functions: 0xff,
+            debug: true,
+            view: true,
+            extensions: vec![(
+                "ext_random".to_string(),
+                Signature::new(&[ValueType::I32, ValueType::F64][..], Some(ValueType::I64)),
+            )],
+            calls: vec![
+                RecordedCall {
+                    caller: Some(2),
+                    name: "ext_random".to_string(),
+                    args: vec![RuntimeValue::I32(-1), RuntimeValue::F64(1.5.into())],
+                    writes: vec![(16, vec![0xab, 0xcd])],
+                    result: Ok(Some(RuntimeValue::I64(42))),
+                },
+                RecordedCall {
+                    caller: None,
+                    name: "debug_abort".to_string(),
+                    args: Vec::new(),
+                    writes: Vec::new(),
+                    result: Err("aborted: a\\b\nc".to_string()),
+                },
+            ],
+            outcome: Err("wasm trap: Unreachable".to_string()),
+        };
+
+        let mut written = Vec::new();
+        recording.write(&mut written).unwrap();
+
+        assert_eq!(Recording::read(&written[..]).unwrap(), recording);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::env::abi;
+use crate::env::child::{capabilities, GasSchedule, Limits};
+use crate::env::debug::{self, DebugModuleImportResolver};
+use crate::env::debugger::{self, Debugger};
+use crate::env::instrument::{self, FunctionNames, InstrumentModuleImportResolver};
+use crate::env::registry::{HostContext, Registry};
+use crate::env::replay::{RecordedCall, Recorder, Recording, Replayer};
+use crate::env::trace::Tracer;
+use crate::env::ExtResult;
+use crate::error::Error;
+use crate::memory::MemoryConfig;
+
+use super::resolver::{RuntimeModuleImportResolver, DEBUG_FUNC_INDEX, INSTRUMENT_FUNC_INDEX};
+use super::{Inner, RootRuntime};
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasmi::{ImportsBuilder, Module, ModuleInstance, RuntimeArgs, Signature};
+
+/// Configures and creates a `RootRuntime`.
+///
+/// ```ignore
+/// let runtime = RootRuntimeBuilder::new(&code, &data, pre_root)
+///     .gas_limit(1_000_000)
+///     .call_depth_limit(16)
+///     .host_functions(capabilities::ALL & !capabilities::BUFFER)
+///     .build()?;
+/// ```
+///
+/// Unlike `MemoryConfig`, this isn't derived with `TypedBuilder`: setters
+/// are called conditionally (`builder = builder.gas_limit(gas)`), which the
+/// typed builder's per-field type states don't allow, `host_function` can be
+/// called any number of times, and `build` does the fallible work of loading
+/// the root.
+pub struct RootRuntimeBuilder<'a, 'b> {
+    code: Cow<'b, [u8]>,
+    data: &'a [u8],
+    pre_root: [u8; 32],
+
+    gas_limit: u64,
+    gas_schedule: GasSchedule,
+    memory: MemoryConfig,
+    call_depth_limit: Option<usize>,
+    host_functions: u32,
+    logger: Option<Box<dyn Fn(&str) + 'a>>,
+    registry: Registry<'a>,
+    debug: bool,
+    tracer: Option<Box<dyn Tracer + 'a>>,
+    profile: bool,
+    backtraces: bool,
+    debugger: Option<Box<dyn Debugger + 'a>>,
+    record: bool,
+    replay: Option<Vec<RecordedCall>>,
+    view: bool,
+}
+
+impl<'a, 'b> RootRuntimeBuilder<'a, 'b> {
+    pub fn new(code: &'b [u8], data: &'a [u8], pre_root: [u8; 32]) -> Self {
+        Self {
+            code: Cow::Borrowed(code),
+            data,
+            pre_root,
+            gas_limit: u64::MAX,
+            gas_schedule: GasSchedule::default(),
+            memory: MemoryConfig::default(),
+            call_depth_limit: None,
+            host_functions: capabilities::ALL,
+            logger: None,
+            registry: Registry::default(),
+            debug: false,
+            tracer: None,
+            profile: false,
+            backtraces: false,
+            debugger: None,
+            record: false,
+            replay: None,
+            view: false,
+        }
+    }
+
+    /// Creates a builder for the root written in WebAssembly text.
+    #[cfg(feature = "wat")]
+    pub fn from_wat(wat: &str, data: &'a [u8], pre_root: [u8; 32]) -> Result<Self, Error> {
+        let code = crate::wat::wat2wasm(wat)?;
+
+        Ok(Self {
+            code: Cow::Owned(code),
+            ..Self::new(&[], data, pre_root)
+        })
+    }
+
+    /// Budget from which the gas allotted to metered children is deducted.
+    /// The root itself isn't metered. Unlimited by default.
+    pub fn gas_limit(mut self, gas: u64) -> Self {
+        self.gas_limit = gas;
+        self
+    }
+
+    /// Cost of the instructions executed by metered children.
+    pub fn gas_schedule(mut self, schedule: GasSchedule) -> Self {
+        self.gas_schedule = schedule;
+        self
+    }
+
+    pub fn memory(mut self, memory: MemoryConfig) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Maximum number of nested cross-module calls. A call beyond the limit
+    /// aborts the whole execution, rather than being reported to its caller as
+    /// a trapped callee.
+    pub fn call_depth_limit(mut self, depth: usize) -> Self {
+        self.call_depth_limit = Some(depth);
+        self
+    }
+
+    /// Groups of host functions available to the root, using the bits of
+    /// `capabilities`. Children can't be granted a group the root doesn't
+    /// have. `CALL_ROOT` covers `eth2_expose`, and `CALL_MODULE` covers
+    /// loading and calling modules. All groups are enabled by default.
+    pub fn host_functions(mut self, groups: u32) -> Self {
+        self.host_functions = groups;
+        self
+    }
+
+    /// Serves the `debug` namespace to the root, and to children granted
+    /// `LOGGING`. Meant for development, so it's disabled by default.
+    pub fn debug(mut self, enabled: bool) -> Self {
+        self.debug = enabled;
+        self
+    }
+
+    /// Reports host function invocations, cross-module calls and module
+    /// loads to `tracer` as they happen.
+    pub fn tracer<T: Tracer + 'a>(mut self, tracer: T) -> Self {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    /// Counts the instructions executed by each function of the root and its
+    /// children, for `RootRuntime::write_profile`. Modules are instrumented
+    /// when they are loaded, which slows execution down, so it's disabled by
+    /// default.
+    pub fn profile(mut self, enabled: bool) -> Self {
+        self.profile = enabled;
+        self
+    }
+
+    /// Attaches a backtrace of the wasm frames executing when the root traps
+    /// to the error returned by `execute`. Modules are instrumented to track
+    /// the frames, which slows execution down, so it's disabled by default.
+    /// Gas metering is unaffected either way.
+    pub fn backtraces(mut self, enabled: bool) -> Self {
+        self.backtraces = enabled;
+        self
+    }
+
+    /// Hands control to `debugger` when execution reaches a breakpoint, or
+    /// after every step. Modules are instrumented to report the functions
+    /// they enter, as they are for backtraces.
+    pub fn debugger<D: Debugger + 'a>(mut self, debugger: D) -> Self {
+        self.debugger = Some(Box::new(debugger));
+        self
+    }
+
+    /// Records the inputs of the execution, the runtime's settings and every
+    /// host call, for `RootRuntime::recording`. Recordings are meant to
+    /// reproduce a single call to `execute`.
+    pub fn record(mut self, enabled: bool) -> Self {
+        self.record = enabled;
+        self
+    }
+
+    /// Checks host calls against `calls`, which were recorded by a runtime
+    /// built with the same inputs and settings.
+    pub(crate) fn replay(mut self, calls: Vec<RecordedCall>) -> Self {
+        self.replay = Some(calls);
+        self
+    }
+
+    /// Executes in view mode, for querying the state of an EE without side
+    /// effects, usually with `RootRuntime::invoke`. `eth2_savePostStateRoot`,
+    /// buffer writes, `eth2_sharedMemoryWrite`, module loads, `print`, the
+    /// `debug` namespace's print functions and registered host functions abort
+    /// the execution when called by the root or a child. Modules loaded by the
+    /// embedder with `RootRuntime::load_module` are unaffected.
+    pub fn view(mut self, enabled: bool) -> Self {
+        self.view = enabled;
+        self
+    }
+
+    pub fn logger<F: Fn(&str) + 'a>(mut self, f: F) -> Self {
+        self.logger = Some(Box::new(f));
+        self
+    }
+
+    /// Adds a host function that modules can import from the `env`
+    /// namespace. Children need the `EXTENSIONS` capability to import it.
+    /// Panics if `name` is already taken.
+    pub fn host_function<F>(mut self, name: &str, signature: Signature, f: F) -> Self
+    where
+        F: Fn(&HostContext, RuntimeArgs) -> ExtResult + 'a,
+    {
+        self.registry.register(name, signature, f);
+        self
+    }
+
+    /// Loads the root. Fails with `Error::InvalidRoot` if the root can't be
+    /// parsed, exceeds the memory limit, or imports something the runtime
+    /// doesn't provide.
+    pub fn build(self) -> Result<RootRuntime<'a>, Error> {
+        let invalid = |error: wasmi::Error| Error::InvalidRoot {
+            reason: error.to_string(),
+        };
+
+        let limits = Limits {
+            max_pages: self.memory.max_pages,
+            gas: None,
+        };
+
+        let instrumented = self.backtraces || self.profile || self.debugger.is_some();
+
+        let code = limits
+            .apply(&self.code, &self.gas_schedule)
+            .map_err(invalid)?;
+
+        let (code, function_names) = if instrumented {
+            let names = FunctionNames::read(&self.code).map_err(invalid)?;
+            let code = instrument::instrument(&code, names.len(), self.profile).map_err(invalid)?;
+            (code, names)
+        } else {
+            (code, FunctionNames::default())
+        };
+
+        let module = Module::from_buffer(code).map_err(invalid)?;
+
+        let resolvers: Vec<_> = abi::NAMESPACES
+            .iter()
+            .map(|(_, abi)| {
+                RuntimeModuleImportResolver::new(*abi, self.host_functions, &self.registry)
+            })
+            .collect();
+
+        let mut imports = ImportsBuilder::new();
+        for ((namespace, _), resolver) in abi::NAMESPACES.iter().zip(&resolvers) {
+            imports.push_resolver(*namespace, resolver);
+        }
+
+        let debug = DebugModuleImportResolver::new(DEBUG_FUNC_INDEX);
+        if self.debug {
+            imports.push_resolver(debug::NAMESPACE, &debug);
+        }
+
+        let instrumentation = InstrumentModuleImportResolver::new(INSTRUMENT_FUNC_INDEX);
+        if instrumented {
+            imports.push_resolver(instrument::NAMESPACE, &instrumentation);
+        }
+
+        let instance = ModuleInstance::new(&module, &imports).map_err(invalid)?;
+        if instance.has_start() {
+            return Err(Error::InvalidRoot {
+                reason: "modules with a start function aren't supported".to_string(),
+            });
+        }
+        let instance = instance.assert_no_start();
+
+        let recorder = if self.record {
+            let extensions = self
+                .registry
+                .iter()
+                .map(|f| (f.name().to_string(), f.signature().clone()))
+                .collect();
+
+            Some(RefCell::new(Recorder::new(Recording {
+                code: self.code.to_vec(),
+                data: self.data.to_vec(),
+                pre_root: self.pre_root,
+                gas_limit: self.gas_limit,
+                gas_schedule: self.gas_schedule,
+                memory: self.memory,
+                call_depth_limit: self.call_depth_limit,
+                host_functions: self.host_functions,
+                debug: self.debug,
+                view: self.view,
+                extensions,
+                calls: Vec::new(),
+                outcome: Ok([0u8; 32]),
+            })))
+        } else {
+            None
+        };
+
+        Ok(RootRuntime(Rc::new(Inner {
+            instance,
+            data: self.data,
+            pre_root: self.pre_root,
+            children: Default::default(),
+            post_root: Default::default(),
+            call_targets: Default::default(),
+            call_permissions: Default::default(),
+            call_stack: Default::default(),
+            buffer: Default::default(),
+            shared: Default::default(),
+            journal: Default::default(),
+            changes: Default::default(),
+            logs: Default::default(),
+            logger: RefCell::new(self.logger),
+            gas: Cell::new(self.gas_limit),
+            gas_schedule: self.gas_schedule,
+            module_limit: Cell::new(u32::MAX),
+            memory: self.memory,
+            call_depth_limit: self.call_depth_limit,
+            host_functions: self.host_functions,
+            registry: self.registry,
+            debug: self.debug,
+            tracer: RefCell::new(self.tracer),
+            profiler: if self.profile {
+                Some(Default::default())
+            } else {
+                None
+            },
+            backtraces: self.backtraces,
+            function_names,
+            wasm_frames: Default::default(),
+            backtrace: Default::default(),
+            last_trap: Default::default(),
+            debugger: RefCell::new(self.debugger.map(debugger::State::new)),
+            calls: Default::default(),
+            recorder,
+            replayer: self.replay.map(|calls| RefCell::new(Replayer::new(calls))),
+            view: self.view,
+        })))
+    }
+}
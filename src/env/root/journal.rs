@@ -0,0 +1,69 @@
+use crate::env::shared::Overwritten;
+use crate::error::Error;
+
+use wasmi::{Trap, TrapKind};
+
+/// A change a cross-module call made to the runtime's state, recorded with
+/// what is needed to undo it. Changes to the buffer are recorded by the
+/// buffer itself.
+#[derive(Debug, Clone)]
+pub(super) enum Change {
+    /// The post-state root was overwritten.
+    PostRoot([u8; 32]),
+
+    /// A root function was exposed to children.
+    CallTarget(String),
+
+    /// A module was permitted to call another.
+    CallPermission(u32, u32),
+
+    /// A module was loaded into the slot.
+    Child(u32),
+
+    /// Gas was allotted to a module, leaving the root with less than the
+    /// previous budget.
+    Gas(u64),
+
+    /// The number of modules that can be loaded was lowered from the previous
+    /// limit.
+    ModuleLimit(u32),
+
+    /// The shared memory was written to.
+    SharedMemory(Overwritten),
+}
+
+/// An open cross-module call, recording where its changes start so that they
+/// can be undone if the callee fails.
+#[derive(Debug, Clone)]
+pub(super) struct Checkpoint {
+    /// Number of changes recorded before the call.
+    pub changes: usize,
+
+    /// Number of log messages emitted before the call.
+    pub logs: usize,
+}
+
+/// Returned to the caller of a cross-module call in place of the callee's
+/// return value when the callee traps. A callee returning this value is
+/// treated as having trapped, so the caller can always tell the two apart.
+pub const TRAPPED: i32 = i32::MIN;
+
+/// Whether a return code produced by a callee indicates failure, meaning the
+/// callee's changes should be rolled back.
+pub fn is_failure(retcode: i32) -> bool {
+    retcode < 0
+}
+
+/// Whether a trap must abort the whole execution rather than be reported to
+/// the caller of a cross-module call as `TRAPPED`.
+pub fn is_fatal(trap: &Trap) -> bool {
+    match trap.kind() {
+        TrapKind::Host(error) => matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::OutOfGas { .. })
+                | Some(Error::CallDepthExceeded { .. })
+                | Some(Error::ViewViolation { .. })
+        ),
+        _ => false,
+    }
+}
@@ -1,32 +1,76 @@
-mod resolver;
+mod builder;
+mod journal;
+pub(crate) mod resolver;
+
+pub use self::builder::RootRuntimeBuilder;
 
 use arrayref::array_ref;
 
+use crate::backtrace::{Backtrace, BacktraceFrame};
 use crate::buffer::Buffer;
-use crate::env::child::ChildRuntime;
+use crate::env::child::{capabilities, ChildRuntime, GasSchedule, Limits};
+use crate::env::debug;
+use crate::env::debugger::{self, CallFrame, Stop};
+use crate::env::instrument::{self, FunctionNames};
+use crate::env::profile::Profiler;
+use crate::env::registry::{HostContext, Registry};
+use crate::env::replay::{self, RecordedCall, Recorder, Recording, Replayer};
+use crate::env::shared::SharedMemory;
+use crate::env::trace::{self, Event, Tracer};
+use crate::error::Error;
 use crate::execute::Execute;
+use crate::memory::{MemoryConfig, MemoryUsage};
 
 use log::debug;
 
+use self::journal::{Change, Checkpoint};
 use self::resolver::{
-    RuntimeModuleImportResolver, ARGUMENT_FUNC_INDEX, BLOCKDATACOPY_FUNC_INDEX,
-    BLOCKDATASIZE_FUNC_INDEX, BUFFERCLEAR_FUNC_INDEX, BUFFERGET_FUNC_INDEX, BUFFERMERGE_FUNC_INDEX,
-    BUFFERSET_FUNC_INDEX, CALLMODULE_FUNC_INDEX, EXPOSE_FUNC_INDEX, LOADMODULE_FUNC_INDEX,
-    LOADPRESTATEROOT_FUNC_INDEX, PRINT_FUNC_INDEX, RETURN_FUNC_INDEX, SAVEPOSTSTATEROOT_FUNC_INDEX,
+    ALLOWCALL_FUNC_INDEX, ARGUMENT_FUNC_INDEX, BLOCKDATACOPYV2_FUNC_INDEX,
+    BLOCKDATACOPY_FUNC_INDEX, BLOCKDATASIZE_FUNC_INDEX, BUFFERCLEAR_FUNC_INDEX,
+    BUFFERCOPY_FUNC_INDEX, BUFFERCOUNT_FUNC_INDEX, BUFFERDELETE_FUNC_INDEX, BUFFERGET_FUNC_INDEX,
+    BUFFERKEYS_FUNC_INDEX, BUFFERMERGECHECKED_FUNC_INDEX, BUFFERMERGEMISSING_FUNC_INDEX,
+    BUFFERMERGE_FUNC_INDEX, BUFFERSET_FUNC_INDEX, CALLMODULETYPED_FUNC_INDEX,
+    CALLMODULE_FUNC_INDEX, DEBUG_FUNC_INDEX, EXPOSE_FUNC_INDEX, EXTENSION_FUNC_INDEX,
+    INSTRUMENT_FUNC_INDEX, LIMITMODULES_FUNC_INDEX, LOADMODULEWITHCAPABILITIES_FUNC_INDEX,
+    LOADMODULEWITHLIMITS_FUNC_INDEX, LOADMODULE_FUNC_INDEX, LOADPRESTATEROOT_FUNC_INDEX,
+    PRINT_FUNC_INDEX, RETURN_FUNC_INDEX, SAVEPOSTSTATEROOT_FUNC_INDEX,
+    SHAREDMEMORYWRITE_FUNC_INDEX,
 };
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
 use std::rc::{Rc, Weak};
 
-use super::{ExtResult, StackFrame};
+use super::{read_name, values, ExtResult, StackFrame};
 
+use wasmi::memory_units::{Bytes, Pages, RoundUpTo};
 use wasmi::{
-    Externals, FuncInstance, ImportsBuilder, MemoryRef, Module, ModuleInstance, ModuleRef,
-    RuntimeArgs, RuntimeValue, Trap,
+    Externals, FuncInstance, FuncRef, MemoryInstance, MemoryRef, ModuleRef, RuntimeArgs,
+    RuntimeValue, Trap, TrapKind, ValueType,
 };
 
+/// The outcome of a successful call to `RootRuntime::run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    pub post_root: [u8; 32],
+
+    /// The root's memory usage once `main` returned.
+    pub memory: MemoryUsage,
+}
+
+/// The outcome of a call to an exported function made with
+/// `RootRuntime::invoke`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    /// The code returned by the function.
+    pub code: i32,
+
+    /// The bytes the function copied with its last `eth2_return`.
+    pub data: Vec<u8>,
+}
+
 #[derive(Clone)]
 pub(crate) struct RootRuntimeWeak<'a>(Weak<Inner<'a>>);
 
@@ -40,27 +84,63 @@ impl<'a> RootRuntimeWeak<'a> {
 pub struct RootRuntime<'a>(Rc<Inner<'a>>);
 
 impl<'a> RootRuntime<'a> {
+    /// Creates a runtime with the default settings. Panics if the root can't
+    /// be loaded; use `builder` to handle the error instead.
     pub fn new<'b>(code: &'b [u8], data: &'a [u8], pre_root: [u8; 32]) -> RootRuntime<'a> {
-        let module = Module::from_buffer(code).expect("Module loading to succeed");
+        RootRuntimeBuilder::new(code, data, pre_root)
+            .build()
+            .expect("Module loading to succeed")
+    }
+
+    /// Creates a runtime for the root written in WebAssembly text.
+    #[cfg(feature = "wat")]
+    pub fn from_wat(wat: &str, data: &'a [u8], pre_root: [u8; 32]) -> Result<Self, Error> {
+        RootRuntimeBuilder::from_wat(wat, data, pre_root)?.build()
+    }
+
+    pub fn builder<'b>(
+        code: &'b [u8],
+        data: &'a [u8],
+        pre_root: [u8; 32],
+    ) -> RootRuntimeBuilder<'a, 'b> {
+        RootRuntimeBuilder::new(code, data, pre_root)
+    }
+
+    pub(crate) fn registry(&self) -> &Registry<'a> {
+        &self.0.registry
+    }
+
+    pub(crate) fn block_data(&self) -> &'a [u8] {
+        self.0.data
+    }
 
-        let mut imports = ImportsBuilder::new();
-        imports.push_resolver("env", &RuntimeModuleImportResolver);
+    pub(crate) fn pre_state_root(&self) -> [u8; 32] {
+        self.0.pre_root
+    }
+
+    pub(crate) fn buffer(&self) -> &RefCell<Buffer> {
+        &self.0.buffer
+    }
 
-        let instance = ModuleInstance::new(&module, &imports)
-            .expect("Module instantation expected to succeed")
-            .assert_no_start();
+    /// Invokes the registered host function at `index` on behalf of `caller`,
+    /// with `memory` being the caller's memory.
+    pub(crate) fn call_extension(
+        &self,
+        index: usize,
+        memory: MemoryRef,
+        caller: Option<u32>,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let context = HostContext::new(self, memory, caller);
+        self.0.registry.invoke(index, &context, args)
+    }
 
-        RootRuntime(Rc::new(Inner {
-            instance,
-            data,
-            pre_root,
-            children: Default::default(),
-            post_root: Default::default(),
-            call_targets: Default::default(),
-            call_stack: Default::default(),
-            buffer: Default::default(),
-            logger: Default::default(),
-        }))
+    /// Returns the size of the root's memory and the limit it is subject to.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            pages: self.memory().current_size().0 as u32,
+            max_pages: self.0.memory.max_pages,
+        }
     }
 
     pub fn set_logger<F: Fn(&str) + 'a>(&mut self, f: F) {
@@ -68,41 +148,755 @@ impl<'a> RootRuntime<'a> {
         *logger = Some(Box::new(f));
     }
 
+    /// Returns the gas budget that hasn't been allotted to children yet.
+    pub fn gas_left(&self) -> u64 {
+        self.0.gas.get()
+    }
+
+    /// Emits a log message. Messages emitted during a cross-module call are
+    /// held back until the outermost call commits, and are discarded if the
+    /// call is rolled back.
     pub(crate) fn print(&self, bytes: &[u8]) {
+        let message = String::from_utf8_lossy(bytes).into_owned();
+        self.0.logs.borrow_mut().push(message);
+
+        if self.0.journal.borrow().is_empty() {
+            self.flush_logs();
+        }
+    }
+
+    /// Whether the `debug` namespace is served.
+    pub(crate) fn debug_enabled(&self) -> bool {
+        self.0.debug
+    }
+
+    /// Sends a message from the `debug` namespace straight to the logger.
+    pub(crate) fn debug(&self, message: &str) {
+        if let Some(log) = self.0.logger.borrow().as_ref() {
+            log(message);
+        }
+    }
+
+    /// Whether a tracer is installed. Events are only built if there is one
+    /// to receive them.
+    pub(crate) fn tracing(&self) -> bool {
+        self.0.tracer.borrow().is_some()
+    }
+
+    pub(crate) fn trace(&self, event: Event) {
+        if let Some(tracer) = self.0.tracer.borrow_mut().as_mut() {
+            tracer.event(&event);
+        }
+    }
+
+    pub(crate) fn profiler(&self) -> Option<&RefCell<Profiler>> {
+        self.0.profiler.as_ref()
+    }
+
+    /// Whether modules are instrumented to report the functions they execute.
+    pub(crate) fn instrumented(&self) -> bool {
+        self.0.backtraces || self.0.profiler.is_some() || self.debugging()
+    }
+
+    /// Whether a debugger is installed.
+    pub(crate) fn debugging(&self) -> bool {
+        self.0.debugger.borrow().is_some()
+    }
+
+    /// Hands control to the debugger if execution should stop at `stop`.
+    pub(crate) fn break_at(&self, stop: Stop) {
+        if let Some(debugger) = self.0.debugger.borrow_mut().as_mut() {
+            debugger.check(self, &stop);
+        }
+    }
+
+    /// Reports a cross-module call to `name` in the root, represented by
+    /// `None`, or the child in slot `callee` to the tracer and the debugger.
+    pub(super) fn enter_call(
+        &self,
+        callee: Option<u32>,
+        name: &str,
+        args: &[RuntimeValue],
+        frame: &StackFrame,
+    ) {
+        if self.tracing() {
+            self.trace(Event::CallEnter {
+                callee,
+                name: name.to_string(),
+                args: args.to_vec(),
+                frame: frame.into(),
+            });
+        }
+
+        if self.debugging() {
+            self.0.calls.borrow_mut().push(CallFrame {
+                callee,
+                name: name.to_string(),
+                frame: frame.into(),
+            });
+
+            self.break_at(Stop::CallEnter {
+                callee,
+                name: name.to_string(),
+            });
+        }
+    }
+
+    pub(super) fn exit_call(&self, callee: Option<u32>, name: &str, result: &ExtResult) {
+        if self.debugging() {
+            self.0.calls.borrow_mut().pop();
+
+            self.break_at(Stop::CallExit {
+                callee,
+                name: name.to_string(),
+            });
+        }
+
+        if self.tracing() {
+            self.trace(Event::CallExit {
+                callee,
+                name: name.to_string(),
+                result: trace::outcome(result),
+            });
+        }
+    }
+
+    pub(crate) fn viewing(&self) -> bool {
+        self.0.view
+    }
+
+    /// Whether host calls are observed, by a tracer or for a recording or
+    /// replay.
+    pub(crate) fn observing(&self) -> bool {
+        self.tracing() || self.0.recorder.is_some() || self.0.replayer.is_some()
+    }
+
+    /// Invokes the host function `name` on behalf of the root, represented by
+    /// `None`, or the child in slot `caller`, whose memory is `memory`. The
+    /// call is traced, recorded or checked against the replayed recording.
+    /// Host functions added by the embedder aren't invoked on replay; their
+    /// recorded results and writes are used instead.
+    pub(crate) fn host_call<F>(
+        &self,
+        caller: Option<u32>,
+        name: String,
+        extension: bool,
+        memory: &MemoryRef,
+        args: RuntimeArgs,
+        dispatch: F,
+    ) -> ExtResult
+    where
+        F: FnOnce(RuntimeArgs) -> ExtResult,
+    {
+        let values = args.as_ref().to_vec();
+        let mut writes = Vec::new();
+
+        let replayed = match (&self.0.replayer, extension) {
+            (Some(replayer), true) => {
+                Some(match replayer.borrow().expected(caller, &name, &values) {
+                    Some(call) => {
+                        writes = call.writes.clone();
+                        replay::serve(memory, call)
+                    }
+                    None => Ok(None),
+                })
+            }
+            _ => None,
+        };
+
+        let result = match replayed {
+            Some(result) => result,
+            None if extension && self.0.recorder.is_some() => {
+                let before = replay::snapshot(memory);
+                let result = dispatch(args);
+                writes = replay::writes(&before, &replay::snapshot(memory));
+                result
+            }
+            None => dispatch(args),
+        };
+
+        if self.tracing() {
+            self.trace(Event::HostCall {
+                caller,
+                name: name.clone(),
+                memory: trace::accesses(&name, &values),
+                args: values.clone(),
+                result: trace::outcome(&result),
+            });
+        }
+
+        let call = RecordedCall {
+            caller,
+            name,
+            args: values,
+            writes,
+            result: trace::outcome(&result),
+        };
+
+        if let Some(replayer) = &self.0.replayer {
+            replayer.borrow_mut().check(call)?;
+        } else if let Some(recorder) = &self.0.recorder {
+            recorder.borrow_mut().record(call);
+        }
+
+        result
+    }
+
+    /// Returns the recording of the last execution, if the runtime was built
+    /// with recording enabled.
+    pub fn recording(&self) -> Option<Recording> {
+        self.0
+            .recorder
+            .as_ref()
+            .and_then(|recorder| recorder.borrow().recording())
+    }
+
+    pub(crate) fn replayer(&self) -> Option<&RefCell<Replayer>> {
+        self.0.replayer.as_ref()
+    }
+
+    /// Returns the cross-module calls in progress, innermost first.
+    pub(crate) fn calls(&self) -> Vec<CallFrame> {
+        self.0.calls.borrow().iter().rev().cloned().collect()
+    }
+
+    /// Pushes the function defined at `position` by the root, represented by
+    /// `None`, or the child in slot `module` onto the stack of wasm frames.
+    pub(crate) fn enter_function(&self, module: Option<u32>, position: u32, names: &FunctionNames) {
+        self.0.wasm_frames.borrow_mut().push((module, position));
+
+        let name = names.name(position);
+
+        if let Some(profiler) = self.profiler() {
+            let frame = match module {
+                Some(slot) => format!("child{}:{}", slot, name),
+                None => format!("root:{}", name),
+            };
+
+            profiler.borrow_mut().enter(&frame);
+        }
+
+        if self.debugging() {
+            self.break_at(Stop::FunctionEntry {
+                module,
+                index: names.index(position),
+                name: name.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn exit_function(&self) {
+        self.0.wasm_frames.borrow_mut().pop();
+
+        if let Some(profiler) = self.profiler() {
+            profiler.borrow_mut().exit();
+        }
+    }
+
+    pub(crate) fn count_instructions(&self, instructions: u64) {
+        if let Some(profiler) = self.profiler() {
+            profiler.borrow_mut().count(instructions);
+        }
+    }
+
+    /// Number of wasm frames on the stack, used to drop the frames left behind
+    /// by a callee that traps.
+    pub(crate) fn frame_depth(&self) -> usize {
+        self.0.wasm_frames.borrow().len()
+    }
+
+    pub(crate) fn unwind_frames(&self, depth: usize) {
+        self.0.wasm_frames.borrow_mut().truncate(depth);
+
+        if let Some(profiler) = self.profiler() {
+            profiler.borrow_mut().unwind(depth);
+        }
+    }
+
+    /// Returns the wasm frames currently on the stack, innermost first.
+    pub(crate) fn backtrace(&self) -> Backtrace {
+        let children = self.0.children.borrow();
+
+        let frames = self
+            .0
+            .wasm_frames
+            .borrow()
+            .iter()
+            .rev()
+            .map(|&(module, position)| {
+                let names = match module {
+                    Some(slot) => children[&slot].function_names(),
+                    None => &self.0.function_names,
+                };
+
+                BacktraceFrame {
+                    module,
+                    index: names.index(position),
+                    name: names.name(position).to_string(),
+                }
+            })
+            .collect();
+
+        Backtrace { frames }
+    }
+
+    /// Records the wasm frames on the stack when a trap is first seen, before
+    /// the frames of the trapping calls are dropped.
+    pub(crate) fn capture_backtrace(&self) {
+        if self.0.backtrace.borrow().is_none() {
+            let backtrace = self.backtrace();
+            *self.0.backtrace.borrow_mut() = Some(backtrace);
+        }
+    }
+
+    /// Returns the most recent trap raised during a cross-module call, which
+    /// the caller saw as a return code of `i32::MIN`.
+    pub fn last_trap(&self) -> Option<Error> {
+        self.0.last_trap.borrow().clone()
+    }
+
+    /// Turns a trap into an error carrying the backtrace recorded for it.
+    fn trap_error(&self, message: String) -> Error {
+        let backtrace = self
+            .0
+            .backtrace
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| self.backtrace());
+
+        Error::Trap { message, backtrace }
+    }
+
+    /// Writes the instructions executed so far, attributed to stacks of
+    /// functions across the root and its children, in the folded format read
+    /// by flamegraph tools. Writes nothing unless profiling was enabled with
+    /// `RootRuntimeBuilder::profile`.
+    pub fn write_profile<W: Write>(&self, writer: W) -> io::Result<()> {
+        match self.profiler() {
+            Some(profiler) => profiler.borrow().write_folded(writer),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the root's host function at `index` has side effects, and so
+    /// traps in view mode. Registered host functions are assumed to have some.
+    fn has_side_effects(&self, index: usize) -> bool {
+        match index {
+            index if (DEBUG_FUNC_INDEX..INSTRUMENT_FUNC_INDEX).contains(&index) => {
+                debug::prints(index - DEBUG_FUNC_INDEX)
+            }
+            index if index >= EXTENSION_FUNC_INDEX => true,
+            index => self::resolver::has_side_effects(index),
+        }
+    }
+
+    /// Returns the name of the root's host function at `index`.
+    fn host_name(&self, index: usize) -> String {
+        match index {
+            index if (DEBUG_FUNC_INDEX..INSTRUMENT_FUNC_INDEX).contains(&index) => {
+                debug::name(index - DEBUG_FUNC_INDEX).to_string()
+            }
+            index if index >= EXTENSION_FUNC_INDEX => self
+                .0
+                .registry
+                .get(index - EXTENSION_FUNC_INDEX)
+                .name()
+                .to_string(),
+            index => self::resolver::name(index)
+                .expect("unknown function index")
+                .to_string(),
+        }
+    }
+
+    /// Returns the entries of a buffer frame, ordered by key.
+    pub(crate) fn buffer_entries(&self, frame: u32) -> Vec<([u8; 32], [u8; 32])> {
+        let buffer = self.0.buffer.borrow();
+
+        buffer
+            .keys(frame)
+            .map(|key| (*key, *buffer.get(frame, *key).unwrap()))
+            .collect()
+    }
+
+    fn flush_logs(&self) {
+        let logs: Vec<String> = self.0.logs.borrow_mut().drain(..).collect();
+
         match self.0.logger.borrow().as_ref() {
-            Some(log) => log(&String::from_utf8_lossy(bytes)),
+            Some(log) => logs.iter().for_each(|message| log(message)),
             None => (),
         }
     }
 
-    pub(super) fn call(&self, name: &str, frame: StackFrame) -> i32 {
-        if !self.0.call_targets.borrow().contains(name) {
-            panic!("function `{}` is not a safe call target", name);
+    /// Opens a new journal entry. Changes made from now on are recorded, so
+    /// that they can be undone if the cross-module call fails.
+    fn checkpoint(&self) {
+        let checkpoint = Checkpoint {
+            changes: self.0.changes.borrow().len(),
+            logs: self.0.logs.borrow().len(),
+        };
+
+        self.0.journal.borrow_mut().push(checkpoint);
+        self.0.buffer.borrow_mut().checkpoint();
+    }
+
+    /// Records how to undo a change, if a cross-module call is in progress.
+    fn record(&self, change: Change) {
+        if !self.0.journal.borrow().is_empty() {
+            self.0.changes.borrow_mut().push(change);
+        }
+    }
+
+    /// Closes the most recent journal entry, keeping every change made since
+    /// it was opened.
+    fn commit(&self) {
+        self.0
+            .journal
+            .borrow_mut()
+            .pop()
+            .expect("commit requires an open checkpoint");
+
+        self.0.buffer.borrow_mut().commit();
+
+        if self.0.journal.borrow().is_empty() {
+            self.0.changes.borrow_mut().clear();
+            self.flush_logs();
         }
+    }
 
-        let export = self
+    /// Closes the most recent journal entry, undoing every change made since
+    /// it was opened.
+    fn revert(&self) {
+        let checkpoint = self
             .0
+            .journal
+            .borrow_mut()
+            .pop()
+            .expect("revert requires an open checkpoint");
+
+        self.0.buffer.borrow_mut().revert();
+        self.0.logs.borrow_mut().truncate(checkpoint.logs);
+
+        let undone = self.0.changes.borrow_mut().split_off(checkpoint.changes);
+
+        for change in undone.into_iter().rev() {
+            match change {
+                Change::PostRoot(root) => *self.0.post_root.borrow_mut() = root,
+                Change::CallTarget(name) => {
+                    self.0.call_targets.borrow_mut().remove(&name);
+                }
+                Change::CallPermission(caller, callee) => {
+                    self.0
+                        .call_permissions
+                        .borrow_mut()
+                        .remove(&(caller, callee));
+                }
+                Change::Child(slot) => {
+                    self.0.children.borrow_mut().remove(&slot);
+                }
+                Change::Gas(budget) => self.0.gas.set(budget),
+                Change::ModuleLimit(limit) => self.0.module_limit.set(limit),
+                Change::SharedMemory(overwritten) => self.0.shared.restore(overwritten),
+            }
+        }
+    }
+
+    /// Runs a cross-module call inside a checkpoint. The callee's changes are
+    /// committed if it returns a non-negative value, and rolled back if it
+    /// returns a negative value or traps. A trap is reported to the caller as
+    /// `journal::TRAPPED`, except for running out of gas or exceeding the call
+    /// depth, which abort the whole execution.
+    pub(crate) fn journaled<F>(&self, f: F) -> Result<i32, Trap>
+    where
+        F: FnOnce() -> Result<i32, Trap>,
+    {
+        self.check_depth()?;
+        self.checkpoint();
+
+        match f() {
+            Ok(retcode) if !journal::is_failure(retcode) => {
+                self.commit();
+                Ok(retcode)
+            }
+            Ok(journal::TRAPPED) => {
+                debug!("callee returned the reserved code, reverting");
+                *self.0.last_trap.borrow_mut() = Some(Error::Trap {
+                    message: format!("callee returned the reserved code {}", journal::TRAPPED),
+                    backtrace: Default::default(),
+                });
+                self.revert();
+                Ok(journal::TRAPPED)
+            }
+            Ok(retcode) => {
+                debug!("callee failed with {}, reverting", retcode);
+                self.revert();
+                Ok(retcode)
+            }
+            Err(trap) if journal::is_fatal(&trap) => {
+                debug!("callee trapped, aborting: {}", trap);
+                self.revert();
+                Err(trap)
+            }
+            Err(trap) => {
+                let error = self.trap_error(describe(&trap));
+                debug!("callee trapped, reverting: {}", error);
+                *self.0.last_trap.borrow_mut() = Some(error);
+                self.revert();
+                Ok(journal::TRAPPED)
+            }
+        }
+    }
+
+    /// Runs a typed cross-module call inside a checkpoint, then writes at most
+    /// `count` of the callee's results to `memory` at `offset` as a packed
+    /// list. Returns the number of results the callee produced, or
+    /// `journal::TRAPPED` if it trapped.
+    ///
+    /// The results are written once the callee's changes are committed, so a
+    /// bad `offset` traps the caller instead of counting as a failure of the
+    /// callee.
+    pub(crate) fn journaled_typed<F>(
+        &self,
+        memory: &MemoryRef,
+        offset: u32,
+        count: u32,
+        f: F,
+    ) -> ExtResult
+    where
+        F: FnOnce() -> Result<Option<RuntimeValue>, Trap>,
+    {
+        let mut results = Vec::new();
+
+        let produced = self.journaled(|| {
+            results.extend(f()?);
+            Ok(results.len() as i32)
+        })?;
+
+        if produced != journal::TRAPPED {
+            values::write(memory, offset, count, &results)?;
+        }
+
+        Ok(Some(produced.into()))
+    }
+
+    /// Fails if opening another cross-module call would exceed the call depth
+    /// limit. Each open call has a journal entry, so the journal's length is
+    /// the current depth.
+    fn check_depth(&self) -> Result<(), Trap> {
+        match self.0.call_depth_limit {
+            Some(limit) if self.0.journal.borrow().len() >= limit => {
+                Err(Error::CallDepthExceeded { limit }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the exported function `name` if it has been exposed to child
+    /// modules with `eth2_expose`.
+    pub(crate) fn exposed(&self, name: &str) -> Option<FuncRef> {
+        if !self.0.call_targets.borrow().contains(name) {
+            return None;
+        }
+
+        self.0
             .instance
             .export_by_name(name)
-            .expect("Exposed name doesn't exist");
+            .and_then(|export| export.as_func().cloned())
+    }
+
+    /// Returns the exported function `name` if it has been exposed to child
+    /// modules. Traps if it hasn't.
+    pub(super) fn function(&self, name: &str) -> Result<FuncRef, Trap> {
+        self.exposed(name).ok_or_else(|| {
+            Error::UnknownFunction {
+                slot: None,
+                name: name.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Calls the exposed function `name`, which must take no parameters and
+    /// return an i32 return code.
+    pub(super) fn call(&self, name: &str, frame: StackFrame) -> Result<i32, Trap> {
+        let func = self.function(name)?;
+        values::check_call_signature(func.signature())?;
 
-        let func = export.as_func().expect("Exposed name isn't a function");
+        match self.invoke_func(name, &func, &[], frame)? {
+            Some(RuntimeValue::I32(retcode)) => Ok(retcode),
+            _ => unreachable!("signature checked above"),
+        }
+    }
+
+    /// Calls the exposed function `name` with typed arguments. Traps if `args`
+    /// don't match its signature.
+    pub(super) fn invoke_exposed(
+        &self,
+        name: &str,
+        args: &[RuntimeValue],
+        frame: StackFrame,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let func = self.function(name)?;
+        values::check_signature(func.signature(), args)?;
 
+        self.invoke_func(name, &func, args, frame)
+    }
+
+    fn invoke_func(
+        &self,
+        name: &str,
+        func: &FuncRef,
+        args: &[RuntimeValue],
+        frame: StackFrame,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        self.enter_call(None, name, args, &frame);
         self.0.call_stack.borrow_mut().push(frame);
 
+        let depth = self.frame_depth();
         let mut externals = RootExternals(self);
 
-        let result = FuncInstance::invoke(&func, &[], &mut externals)
-            .expect("function provided by root runtime failed")
-            .expect("function provided by root runtime did not return a value")
-            .try_into()
-            .expect("funtion provided by rooot runtime return a non-i32 value");
+        let result = FuncInstance::invoke(func, args, &mut externals);
+
+        if result.is_err() {
+            self.capture_backtrace();
+        }
 
+        self.unwind_frames(depth);
         self.0.call_stack.borrow_mut().pop().unwrap();
+        self.exit_call(None, name, &result);
+
+        result
+    }
+
+    /// Calls a root function that was linked into a child at load time. The
+    /// root's state is rolled back if the function traps, and the trap is
+    /// passed on to the child.
+    pub(super) fn call_linked(
+        &self,
+        name: &str,
+        func: &FuncRef,
+        args: &[RuntimeValue],
+        frame: StackFrame,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        self.check_depth()?;
+        self.checkpoint();
+
+        let result = self.invoke_func(name, func, args, frame);
+
+        match result {
+            Ok(_) => self.commit(),
+            Err(_) => self.revert(),
+        }
 
         result
     }
 
+    /// Returns the module in `slot`, checking that `caller` is permitted to
+    /// call it. The root, represented by `None`, may call every module. Traps
+    /// if the slot is empty.
+    fn callee(&self, caller: Option<u32>, slot: u32) -> Result<Rc<ChildRuntime<'a>>, Trap> {
+        if let Some(caller) = caller {
+            if !self.0.call_permissions.borrow().contains(&(caller, slot)) {
+                return Err(Error::CallNotPermitted {
+                    caller,
+                    callee: slot,
+                }
+                .into());
+            }
+        }
+
+        self.0
+            .children
+            .borrow()
+            .get(&slot)
+            .cloned()
+            .ok_or_else(|| Error::EmptySlot { slot }.into())
+    }
+
+    /// Implements `eth2_callModule` on behalf of the root or the child in slot
+    /// `caller`, whose memory is `memory`.
+    pub(crate) fn call_module(
+        &self,
+        caller: Option<u32>,
+        memory: MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let slot: u32 = args.nth(0);
+
+        let name_ptr: u32 = args.nth(1);
+        let name_len: u32 = args.nth(2);
+        let name = read_name(&memory, name_ptr, name_len)?;
+
+        let arg_ptr: u32 = args.nth(3);
+        let arg_len: u32 = args.nth(4);
+
+        let ret_ptr: u32 = args.nth(5);
+        let ret_len: u32 = args.nth(6);
+
+        let frame = StackFrame::builder()
+            .argument_offset(arg_ptr)
+            .argument_length(arg_len)
+            .return_offset(ret_ptr)
+            .return_length(ret_len)
+            .memory(memory)
+            .build();
+
+        let child = self.callee(caller, slot)?;
+        let retcode = self.journaled(|| child.call(&name, frame))?;
+
+        Ok(Some(retcode.into()))
+    }
+
+    /// Implements `eth2_callModuleTyped` on behalf of the root or the child in
+    /// slot `caller`, whose memory is `memory`.
+    pub(crate) fn call_module_typed(
+        &self,
+        caller: Option<u32>,
+        memory: MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let slot: u32 = args.nth(0);
+
+        let name_ptr: u32 = args.nth(1);
+        let name_len: u32 = args.nth(2);
+        let name = read_name(&memory, name_ptr, name_len)?;
+
+        let params_ptr: u32 = args.nth(3);
+        let params_count: u32 = args.nth(4);
+
+        let results_ptr: u32 = args.nth(5);
+        let results_count: u32 = args.nth(6);
+
+        let params = values::read(&memory, params_ptr, params_count)?;
+
+        let child = self.callee(caller, slot)?;
+
+        let frame = StackFrame::builder()
+            .argument_offset(0u32)
+            .argument_length(0u32)
+            .return_offset(0u32)
+            .return_length(0u32)
+            .memory(memory.clone())
+            .build();
+
+        self.journaled_typed(&memory, results_ptr, results_count, || {
+            child.invoke(&name, &params, frame)
+        })
+    }
+
+    /// Returns the memory of the root, represented by `None`, or the child in
+    /// `slot`, if there is one.
+    pub(crate) fn module_memory(&self, module: Option<u32>) -> Option<MemoryRef> {
+        match module {
+            Some(slot) => self
+                .0
+                .children
+                .borrow()
+                .get(&slot)
+                .map(|child| child.memory()),
+            None => Some(self.memory()),
+        }
+    }
+
     fn memory(&self) -> MemoryRef {
         self.0
             .instance
@@ -136,7 +930,9 @@ impl<'a> RootRuntime<'a> {
             .last()
             .expect("eth2_return requires a call stack");
 
-        let len = top.transfer_return(&memory, src_ptr, src_len).unwrap();
+        let len = top
+            .transfer_return(&memory, src_ptr, src_len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(Some(len.into()))
     }
@@ -160,7 +956,9 @@ impl<'a> RootRuntime<'a> {
             .last()
             .expect("eth2_argument requires a call stack");
 
-        let len = top.transfer_argument(&memory, dest_ptr, dest_len).unwrap();
+        let len = top
+            .transfer_argument(&memory, dest_ptr, dest_len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(Some(len.into()))
     }
@@ -170,24 +968,27 @@ impl<'a> RootRuntime<'a> {
 
         let name_ptr: u32 = args.nth(0);
         let name_len: u32 = args.nth(1);
-        let name_bytes = memory.get(name_ptr, name_len as usize).unwrap();
-        let name = String::from_utf8(name_bytes).unwrap();
+        let name = read_name(&memory, name_ptr, name_len)?;
 
-        self.0.call_targets.borrow_mut().insert(name);
+        if self.0.call_targets.borrow_mut().insert(name.clone()) {
+            self.record(Change::CallTarget(name));
+        }
 
         Ok(None)
     }
 
-    fn ext_load_pre_state_root(&self, args: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_load_pre_state_root(
+        &self,
+        memory: &MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
         let ptr: u32 = args.nth(0);
 
         debug!("loadprestateroot to {}", ptr);
 
-        // TODO: add checks for out of bounds access
-        let memory = self.memory();
         memory
             .set(ptr, &self.0.pre_root[..])
-            .expect("expects writing to memory to succeed");
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(None)
     }
@@ -196,23 +997,24 @@ impl<'a> RootRuntime<'a> {
         let ptr: u32 = args.nth(0);
         debug!("savepoststateroot from {}", ptr);
 
-        // TODO: add checks for out of bounds access
+        self.record(Change::PostRoot(*self.0.post_root.borrow()));
+
         let mut post_root = self.0.post_root.borrow_mut();
         let memory = self.memory();
         memory
             .get_into(ptr, &mut post_root[..])
-            .expect("expects reading from memory to succeed");
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(None)
     }
 
-    fn ext_block_data_size(&self, _: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_block_data_size(&self, _: RuntimeArgs) -> ExtResult {
         let ret: i32 = self.0.data.len() as i32;
         debug!("blockdatasize {}", ret);
         Ok(Some(ret.into()))
     }
 
-    fn ext_block_data_copy(&self, args: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_block_data_copy(&self, memory: &MemoryRef, args: RuntimeArgs) -> ExtResult {
         let ptr: u32 = args.nth(0);
         let offset: u32 = args.nth(1);
         let length: u32 = args.nth(2);
@@ -221,42 +1023,74 @@ impl<'a> RootRuntime<'a> {
             ptr, offset, length
         );
 
-        // TODO: add overflow check
-        let offset = offset as usize;
-        let length = length as usize;
+        // v1 takes the end of the range in place of its length.
+        let bytes = self
+            .0
+            .data
+            .get(offset as usize..length as usize)
+            .ok_or_else(|| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
-        // TODO: add checks for out of bounds access
-        let memory = self.memory();
         memory
-            .set(ptr, &self.0.data[offset..length])
-            .expect("expects writing to memory to succeed");
+            .set(ptr, bytes)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         Ok(None)
     }
 
-    fn ext_buffer_get(&self, args: RuntimeArgs) -> ExtResult {
-        let frame: u32 = args.nth(0);
-        let key_ptr: u32 = args.nth(1);
-        let value_ptr: u32 = args.nth(2);
-
-        debug!(
-            "bufferget for frame {} with key at {}, and returning the value to {}",
-            frame, key_ptr, value_ptr
-        );
-
-        // TODO: add overflow check
-        let frame = frame as u8;
-
-        // TODO: add checks for out of bounds access
-        let memory = self.memory();
-
-        let key = memory.get(key_ptr, 32).expect("read to suceed");
+    /// Copies `length` bytes of the block data, starting at `offset`, into
+    /// memory at `dest_offset`. Traps if the bytes aren't all within the block
+    /// data.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_v2.eth2_blockDataCopy(dest_offset: u32, offset: u32, length: u32) -> ()
+    /// ```
+    pub(crate) fn ext_block_data_copy_v2(
+        &self,
+        memory: &MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let ptr: u32 = args.nth(0);
+        let offset: u32 = args.nth(1);
+        let length: u32 = args.nth(2);
+        debug!(
+            "blockdatacopy (v2) to {} from {} for {} bytes",
+            ptr, offset, length
+        );
+
+        let start = offset as usize;
+        let bytes = start
+            .checked_add(length as usize)
+            .and_then(|end| self.0.data.get(start..end))
+            .ok_or_else(|| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        memory
+            .set(ptr, bytes)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        Ok(None)
+    }
+
+    pub(crate) fn ext_buffer_get(&self, memory: &MemoryRef, args: RuntimeArgs) -> ExtResult {
+        let frame: u32 = args.nth(0);
+        let key_ptr: u32 = args.nth(1);
+        let value_ptr: u32 = args.nth(2);
+
+        debug!(
+            "bufferget for frame {} with key at {}, and returning the value to {}",
+            frame, key_ptr, value_ptr
+        );
+
+        let key = memory
+            .get(key_ptr, 32)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
         let key = *array_ref![key, 0, 32];
 
         if let Some(value) = self.0.buffer.borrow().get(frame, key) {
             memory
                 .set(value_ptr, value)
-                .expect("writing to memory to succeed");
+                .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
             Ok(Some(0.into()))
         } else {
@@ -264,7 +1098,7 @@ impl<'a> RootRuntime<'a> {
         }
     }
 
-    fn ext_buffer_set(&self, args: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_buffer_set(&self, memory: &MemoryRef, args: RuntimeArgs) -> ExtResult {
         let frame: u32 = args.nth(0);
         let key_ptr: u32 = args.nth(1);
         let value_ptr: u32 = args.nth(2);
@@ -274,16 +1108,14 @@ impl<'a> RootRuntime<'a> {
             frame, key_ptr, value_ptr
         );
 
-        // TODO: add overflow check
-        let frame = frame as u8;
-
-        // TODO: add checks for out of bounds access
-        let memory = self.memory();
-
-        let key = memory.get(key_ptr, 32).expect("read to suceed");
+        let key = memory
+            .get(key_ptr, 32)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
         let key = *array_ref![key, 0, 32];
 
-        let value = memory.get(value_ptr, 32).expect("read to suceed");
+        let value = memory
+            .get(value_ptr, 32)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
         let value = *array_ref![value, 0, 32];
 
         self.0.buffer.borrow_mut().insert(frame, key, value);
@@ -291,27 +1123,20 @@ impl<'a> RootRuntime<'a> {
         Ok(None)
     }
 
-    fn ext_buffer_merge(&self, args: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_buffer_merge(&self, args: RuntimeArgs) -> ExtResult {
         let frame_a: u32 = args.nth(0);
         let frame_b: u32 = args.nth(1);
 
         debug!("buffermerge frame {} into frame {}", frame_b, frame_a);
 
-        // TODO: add overflow check
-        let frame_a = frame_a as u8;
-        let frame_b = frame_b as u8;
-
         self.0.buffer.borrow_mut().merge(frame_a, frame_b);
 
         Ok(None)
     }
 
-    fn ext_buffer_clear(&self, args: RuntimeArgs) -> ExtResult {
+    pub(crate) fn ext_buffer_clear(&self, args: RuntimeArgs) -> ExtResult {
         let frame: u32 = args.nth(0);
 
-        // TODO: add overflow check
-        let frame = frame as u8;
-
         debug!("bufferclear on frame {}", frame);
 
         self.0.buffer.borrow_mut().clear(frame);
@@ -319,8 +1144,248 @@ impl<'a> RootRuntime<'a> {
         Ok(None)
     }
 
+    /// Returns the number of entries stored in the given frame.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferCount(frame: u32) -> u32
+    /// ```
+    pub(crate) fn ext_buffer_count(&self, args: RuntimeArgs) -> ExtResult {
+        let frame: u32 = args.nth(0);
+
+        let count = self.0.buffer.borrow().len(frame) as u32;
+
+        debug!("buffercount on frame {} is {}", frame, count);
+
+        Ok(Some(count.into()))
+    }
+
+    /// Copies the keys stored in the given frame, in ascending order, into
+    /// memory at the given offset. Only as many whole keys as fit in
+    /// `dest_length` bytes are copied. Returns the total length in bytes of
+    /// all keys in the frame.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferKeys(frame: u32, dest_offset: u32, dest_length: u32) -> u32
+    /// ```
+    pub(crate) fn ext_buffer_keys(&self, memory: &MemoryRef, args: RuntimeArgs) -> ExtResult {
+        let frame: u32 = args.nth(0);
+        let dest_ptr: u32 = args.nth(1);
+        let dest_len: u32 = args.nth(2);
+
+        debug!(
+            "bufferkeys for frame {} into {} ({} bytes)",
+            frame, dest_ptr, dest_len
+        );
+
+        let buffer = self.0.buffer.borrow();
+        let fits = dest_len as usize / 32;
+
+        let keys: Vec<u8> = buffer.keys(frame).take(fits).flatten().cloned().collect();
+
+        memory
+            .set(dest_ptr, &keys)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        let total = buffer.len(frame) as u32 * 32;
+
+        Ok(Some(total.into()))
+    }
+
+    /// Removes the value stored under the key at `key_offset` from the given
+    /// frame. Returns 0 if a value was removed, or 1 if the key was not set.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferDelete(frame: u32, key_offset: u32) -> u32
+    /// ```
+    pub(crate) fn ext_buffer_delete(&self, memory: &MemoryRef, args: RuntimeArgs) -> ExtResult {
+        let frame: u32 = args.nth(0);
+        let key_ptr: u32 = args.nth(1);
+
+        debug!("bufferdelete for frame {} with key at {}", frame, key_ptr);
+
+        let key = memory
+            .get(key_ptr, 32)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+        let key = *array_ref![key, 0, 32];
+
+        if self.0.buffer.borrow_mut().remove(frame, key).is_some() {
+            Ok(Some(0.into()))
+        } else {
+            Ok(Some(1.into()))
+        }
+    }
+
+    /// Copies every entry of the `src` frame into the empty `dest` frame.
+    /// Returns 0 on success, or 1 if `dest` already has entries.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferCopy(src: u32, dest: u32) -> u32
+    /// ```
+    pub(crate) fn ext_buffer_copy(&self, args: RuntimeArgs) -> ExtResult {
+        let src: u32 = args.nth(0);
+        let dest: u32 = args.nth(1);
+
+        debug!("buffercopy frame {} to frame {}", src, dest);
+
+        if self.0.buffer.borrow_mut().copy(src, dest) {
+            Ok(Some(0.into()))
+        } else {
+            Ok(Some(1.into()))
+        }
+    }
+
+    /// Merges frame `b` into frame `a`, keeping any value already in `a`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferMergeMissing(a: u32, b: u32) -> ()
+    /// ```
+    pub(crate) fn ext_buffer_merge_missing(&self, args: RuntimeArgs) -> ExtResult {
+        let frame_a: u32 = args.nth(0);
+        let frame_b: u32 = args.nth(1);
+
+        debug!(
+            "buffermergemissing frame {} into frame {}",
+            frame_b, frame_a
+        );
+
+        self.0.buffer.borrow_mut().merge_missing(frame_a, frame_b);
+
+        Ok(None)
+    }
+
+    /// Merges frame `b` into frame `a`, skipping keys present in both frames
+    /// with different values. The skipped keys are copied into memory the same
+    /// way as `eth2_bufferKeys`. Returns the total length in bytes of the
+    /// skipped keys, so zero means the merge had no conflicts.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_bufferMergeChecked(
+    ///     a: u32,
+    ///     b: u32,
+    ///     dest_offset: u32,
+    ///     dest_length: u32,
+    /// ) -> u32
+    /// ```
+    pub(crate) fn ext_buffer_merge_checked(
+        &self,
+        memory: &MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let frame_a: u32 = args.nth(0);
+        let frame_b: u32 = args.nth(1);
+        let dest_ptr: u32 = args.nth(2);
+        let dest_len: u32 = args.nth(3);
+
+        debug!(
+            "buffermergechecked frame {} into frame {}, reporting to {} ({} bytes)",
+            frame_b, frame_a, dest_ptr, dest_len
+        );
+
+        let conflicts = self.0.buffer.borrow_mut().merge_checked(frame_a, frame_b);
+        let fits = dest_len as usize / 32;
+
+        let keys: Vec<u8> = conflicts.iter().take(fits).flatten().cloned().collect();
+
+        memory
+            .set(dest_ptr, &keys)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        let total = conflicts.len() as u32 * 32;
+
+        Ok(Some(total.into()))
+    }
+
+    /// Copies data from memory into the shared memory that child modules can
+    /// read, growing the shared memory as needed. Traps if the shared memory
+    /// would exceed 256 pages.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_sharedMemoryWrite(dest_offset: u32, src_offset: u32, length: u32) -> ()
+    /// ```
+    fn ext_shared_memory_write(&self, args: RuntimeArgs) -> ExtResult {
+        let dest_ptr: u32 = args.nth(0);
+        let src_ptr: u32 = args.nth(1);
+        let len: u32 = args.nth(2);
+
+        debug!(
+            "sharedmemorywrite from {} to {} ({} bytes)",
+            src_ptr, dest_ptr, len
+        );
+
+        let overwritten = self
+            .0
+            .shared
+            .write(&self.memory(), src_ptr, dest_ptr, len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        self.record(Change::SharedMemory(overwritten));
+
+        Ok(None)
+    }
+
+    /// Returns the size of the shared memory in bytes.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_sharedMemorySize() -> u32
+    /// ```
+    pub(crate) fn ext_shared_memory_size(&self, _: RuntimeArgs) -> ExtResult {
+        let size = self.0.shared.size();
+        debug!("sharedmemorysize {}", size);
+        Ok(Some(size.into()))
+    }
+
+    /// Copies data from the shared memory into memory. Traps if the range
+    /// isn't within the shared memory.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_sharedMemoryCopy(dest_offset: u32, offset: u32, length: u32) -> ()
+    /// ```
+    pub(crate) fn ext_shared_memory_copy(
+        &self,
+        memory: &MemoryRef,
+        args: RuntimeArgs,
+    ) -> ExtResult {
+        let dest_ptr: u32 = args.nth(0);
+        let offset: u32 = args.nth(1);
+        let len: u32 = args.nth(2);
+
+        debug!(
+            "sharedmemorycopy from {} to {} ({} bytes)",
+            offset, dest_ptr, len
+        );
+
+        self.0
+            .shared
+            .read(memory, offset, dest_ptr, len)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+        Ok(None)
+    }
+
     /// Loads a compiled Wasm module from memory into the slot specified.
     ///
+    /// Imports from the `root` namespace are linked to the root functions of
+    /// the same name, which must already have been exposed with
+    /// `eth2_expose`. Traps if the module can't be instantiated.
+    ///
     /// # Signature
     ///
     /// ```text
@@ -331,29 +1396,284 @@ impl<'a> RootRuntime<'a> {
         let code_ptr: u32 = args.nth(1);
         let code_len: u32 = args.nth(2);
 
+        self.load_module_at(
+            slot,
+            code_ptr,
+            code_len,
+            capabilities::DEFAULT,
+            Limits::default(),
+        )
+    }
+
+    /// Loads a compiled Wasm module like `eth2_loadModule`, but only grants it
+    /// the host functions covered by `capabilities`. The module fails to load
+    /// if it imports anything else.
+    ///
+    /// | Bit  | Capability      | Imports                                          |
+    /// |------|-----------------|--------------------------------------------------|
+    /// | 0x01 | `CALL_ROOT`     | `eth2_call`, `eth2_callTyped`, `root` namespace |
+    /// | 0x02 | `CALL_MODULE`   | `eth2_callModule`, `eth2_callModuleTyped`        |
+    /// | 0x04 | `LOGGING`       | `print`                                          |
+    /// | 0x08 | `SHARED_MEMORY` | `eth2_sharedMemorySize`, `eth2_sharedMemoryCopy` |
+    /// | 0x10 | `BUFFER`        | `eth2_buffer*`                                   |
+    /// | 0x20 | `BLOCK_DATA`    | `eth2_blockData*`, `eth2_loadPreStateRoot`       |
+    /// | 0x40 | `EXTENSIONS`    | Host functions added by the embedder             |
+    ///
+    /// `eth2_loadModule` grants `CALL_ROOT`, `CALL_MODULE`, `LOGGING` and
+    /// `SHARED_MEMORY`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_loadModuleWithCapabilities(
+    ///     slot: u32,
+    ///     code_offset: u32,
+    ///     code_length: u32,
+    ///     capabilities: u32,
+    /// ) -> ()
+    /// ```
+    fn ext_load_module_with_capabilities(&self, args: RuntimeArgs) -> ExtResult {
+        let slot: u32 = args.nth(0);
+        let code_ptr: u32 = args.nth(1);
+        let code_len: u32 = args.nth(2);
+        let granted: u32 = args.nth(3);
+
+        self.load_module_at(slot, code_ptr, code_len, granted, Limits::default())
+    }
+
+    /// Loads a compiled Wasm module like `eth2_loadModuleWithCapabilities`,
+    /// limiting the module to `max_pages` pages of memory and `gas` units of
+    /// gas. The gas is deducted from the root's budget. Once a module has used
+    /// up its gas, any further call into it traps.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_loadModuleWithLimits(
+    ///     slot: u32,
+    ///     code_offset: u32,
+    ///     code_length: u32,
+    ///     capabilities: u32,
+    ///     max_pages: u32,
+    ///     gas: u64,
+    /// ) -> ()
+    /// ```
+    fn ext_load_module_with_limits(&self, args: RuntimeArgs) -> ExtResult {
+        let slot: u32 = args.nth(0);
+        let code_ptr: u32 = args.nth(1);
+        let code_len: u32 = args.nth(2);
+        let granted: u32 = args.nth(3);
+        let max_pages: u32 = args.nth(4);
+        let gas: u64 = args.nth(5);
+
+        let limits = Limits {
+            max_pages: Some(max_pages),
+            gas: Some(gas),
+        };
+
+        self.load_module_at(slot, code_ptr, code_len, granted, limits)
+    }
+
+    /// Caps the number of modules that can be loaded. The cap can only be
+    /// lowered, and doesn't unload modules that have already been loaded.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_limitModules(count: u32) -> ()
+    /// ```
+    fn ext_limit_modules(&self, args: RuntimeArgs) -> ExtResult {
+        let count: u32 = args.nth(0);
+
+        debug!("limit modules to {}", count);
+
+        let limit = &self.0.module_limit;
+        self.record(Change::ModuleLimit(limit.get()));
+        limit.set(limit.get().min(count));
+
+        Ok(None)
+    }
+
+    /// Calls the function `name` exported by the root on behalf of the
+    /// embedder. The function reads `argument` with `eth2_argument`, and may
+    /// copy up to `return_length` bytes back with `eth2_return`, as if it were
+    /// called by a child. It must take no parameters and return an i32 code.
+    pub fn invoke(
+        &self,
+        name: &str,
+        argument: &[u8],
+        return_length: u32,
+    ) -> Result<Invocation, Error> {
+        let func = self
+            .0
+            .instance
+            .export_by_name(name)
+            .and_then(|export| export.as_func().cloned())
+            .filter(|func| {
+                let signature = func.signature();
+                signature.params().is_empty() && signature.return_type() == Some(ValueType::I32)
+            })
+            .ok_or_else(|| Error::UnknownExport {
+                name: name.to_string(),
+            })?;
+
+        // The embedder's side of the call holds the argument, followed by the
+        // buffer the function returns into.
+        let length = argument.len() + return_length as usize;
+        let pages: Pages = Bytes(length).round_up_to();
+        let memory = MemoryInstance::alloc(pages, Some(pages)).expect("memory to be allocated");
+        memory
+            .set(0, argument)
+            .expect("argument to fit in the caller's memory");
+
+        let frame = StackFrame::builder()
+            .argument_offset(0u32)
+            .argument_length(argument.len() as u32)
+            .return_offset(argument.len() as u32)
+            .return_length(return_length)
+            .memory(memory.clone())
+            .build();
+        let returned = frame.returned.clone();
+
+        let code = match self.invoke_func(name, &func, &[], frame) {
+            Ok(Some(RuntimeValue::I32(code))) => code,
+            Ok(_) => unreachable!("signature checked above"),
+            Err(trap) => return Err(self.trap_error(describe(&trap))),
+        };
+
+        let data = memory
+            .get(argument.len() as u32, returned.get() as usize)
+            .expect("return data within the caller's memory");
+
+        Ok(Invocation { code, data })
+    }
+
+    /// Loads a module into `slot` on behalf of the embedder, before `execute`
+    /// is called, as if the root had called `eth2_loadModuleWithCapabilities`.
+    pub fn load_module(&self, slot: u32, code: &[u8], capabilities: u32) -> Result<(), Error> {
+        let result = self.instantiate_module(slot, code, capabilities, Limits::default());
+
+        if self.tracing() {
+            self.trace(Event::ModuleLoad {
+                slot,
+                code_length: code.len() as u32,
+                capabilities,
+                result: result.as_ref().map(|_| ()).map_err(|trap| trap.to_string()),
+            });
+        }
+
+        result.map(|_| ()).map_err(|trap| into_error(&trap))
+    }
+
+    /// Loads a module written in WebAssembly text, like `load_module`.
+    #[cfg(feature = "wat")]
+    pub fn load_module_wat(&self, slot: u32, wat: &str, capabilities: u32) -> Result<(), Error> {
+        self.load_module(slot, &crate::wat::wat2wasm(wat)?, capabilities)
+    }
+
+    fn load_module_at(
+        &self,
+        slot: u32,
+        code_ptr: u32,
+        code_len: u32,
+        granted: u32,
+        limits: Limits,
+    ) -> ExtResult {
+        debug!("load module from 0x{:x} ({} bytes)", code_ptr, code_len);
+
+        let code = self
+            .memory()
+            .get(code_ptr, code_len as usize)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+        let result = self.instantiate_module(slot, &code, granted, limits);
+
+        if self.tracing() {
+            self.trace(Event::ModuleLoad {
+                slot,
+                code_length: code_len,
+                capabilities: granted,
+                result: result.as_ref().map(|_| ()).map_err(|trap| trap.to_string()),
+            });
+        }
+
+        result
+    }
+
+    fn instantiate_module(
+        &self,
+        slot: u32,
+        code: &[u8],
+        granted: u32,
+        limits: Limits,
+    ) -> ExtResult {
         debug!(
-            "load module 0x{:x} ({} bytes) into {}",
-            code_ptr, code_len, slot
+            "load module into {} with capabilities 0x{:x} and {:?}",
+            slot, granted, limits
         );
 
+        let refuse =
+            |reason: String| -> ExtResult { Err(Error::Instantiation { slot, reason }.into()) };
+
+        if granted & !capabilities::ALL != 0 {
+            return refuse(format!("unknown capabilities 0x{:x}", granted));
+        }
+
+        let granted = granted & self.0.host_functions;
+
         let mut children = self.0.children.borrow_mut();
 
+        let limit = self.0.module_limit.get();
+        if children.len() >= limit as usize {
+            return refuse(format!("no more than {} modules can be loaded", limit));
+        }
+
+        let limits = Limits {
+            max_pages: self.0.memory.clamp(limits.max_pages),
+            ..limits
+        };
+
+        let budget = self.0.gas.get();
+        let allotment = limits.gas.unwrap_or(0);
+        if allotment > budget {
+            return refuse(format!(
+                "{} gas requested, but only {} is left",
+                allotment, budget
+            ));
+        }
+
         let entry = match children.entry(slot) {
-            Entry::Occupied(_) => panic!("reusing module slot identifiers not supported"),
+            Entry::Occupied(_) => return refuse("the slot is already in use".to_string()),
             Entry::Vacant(x) => x,
         };
 
-        let memory = self.memory();
-        let code = memory.get(code_ptr, code_len as usize).unwrap();
+        let child = ChildRuntime::new(
+            self.downgrade(),
+            slot,
+            code,
+            granted,
+            limits,
+            &self.0.gas_schedule,
+        )
+        .map_err(|error| Error::Instantiation {
+            slot,
+            reason: error.to_string(),
+        })?;
 
-        let child = ChildRuntime::new(self.downgrade(), &code);
-        entry.insert(child);
+        entry.insert(Rc::new(child));
+        self.0.gas.set(budget - allotment);
+        self.record(Change::Child(slot));
+        self.record(Change::Gas(budget));
 
         Ok(None)
     }
 
     /// Calls the function `name` from the module in `slot`.
     ///
+    /// Changes made during the call are rolled back if the callee returns a
+    /// negative value or traps. A trap is reported as `i32::MIN`, except for
+    /// running out of gas or exceeding the call depth limit, which abort the
+    /// execution.
+    ///
     /// # Signature
     ///
     /// ```text
@@ -368,36 +1688,60 @@ impl<'a> RootRuntime<'a> {
     /// ) -> u32
     /// ```
     fn ext_call_module(&self, args: RuntimeArgs) -> ExtResult {
-        let memory = self.memory();
-
-        let slot: u32 = args.nth(0);
-
-        let name_ptr: u32 = args.nth(1);
-        let name_len: u32 = args.nth(2);
-        let name_bytes = memory.get(name_ptr, name_len as usize).unwrap();
-        let name = String::from_utf8(name_bytes).unwrap();
-
-        let arg_ptr: u32 = args.nth(3);
-        let arg_len: u32 = args.nth(4);
+        self.call_module(None, self.memory(), args)
+    }
 
-        let ret_ptr: u32 = args.nth(5);
-        let ret_len: u32 = args.nth(6);
+    /// Calls the function `name` from the module in `slot` with a packed list
+    /// of typed arguments, writing its results as a packed list to
+    /// `results_offset`. At most `results_count` results are written. Returns
+    /// the number of results produced by the callee, or `i32::MIN` if it
+    /// trapped, in which case its changes are rolled back. Results aren't
+    /// return codes, so a typed callee can only fail by trapping.
+    ///
+    /// Traps if the arguments don't match the parameters of the callee, or if
+    /// the results can't be written to `results_offset`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_callModuleTyped(
+    ///     slot: u32,
+    ///     name_offset: u32,
+    ///     name_length: u32,
+    ///     params_offset: u32,
+    ///     params_count: u32,
+    ///     results_offset: u32,
+    ///     results_count: u32,
+    /// ) -> i32
+    /// ```
+    fn ext_call_module_typed(&self, args: RuntimeArgs) -> ExtResult {
+        self.call_module_typed(None, self.memory(), args)
+    }
 
-        let frame = StackFrame::builder()
-            .argument_offset(arg_ptr)
-            .argument_length(arg_len)
-            .return_offset(ret_ptr)
-            .return_length(ret_len)
-            .memory(memory)
-            .build();
+    /// Allows the module in `caller` to call functions of the module in
+    /// `callee` with `eth2_callModule` and `eth2_callModuleTyped`.
+    ///
+    /// # Signature
+    ///
+    /// ```text
+    /// eth2_allowCall(caller: u32, callee: u32) -> ()
+    /// ```
+    fn ext_allow_call(&self, args: RuntimeArgs) -> ExtResult {
+        let caller: u32 = args.nth(0);
+        let callee: u32 = args.nth(1);
 
-        // TODO: There's probably a bug here. It might be impossible to load a
-        // new module depending on the callstack.
+        debug!("allow calls from slot {} to slot {}", caller, callee);
 
-        let children = self.0.children.borrow();
-        let retcode = children[&slot].call(&name, frame);
+        if self
+            .0
+            .call_permissions
+            .borrow_mut()
+            .insert((caller, callee))
+        {
+            self.record(Change::CallPermission(caller, callee));
+        }
 
-        Ok(Some(retcode.into()))
+        Ok(None)
     }
 
     fn ext_print(&self, args: RuntimeArgs) -> ExtResult {
@@ -406,7 +1750,9 @@ impl<'a> RootRuntime<'a> {
         let ptr: u32 = args.nth(0);
         let len: u32 = args.nth(1);
 
-        let bytes = memory.get(ptr, len as usize).unwrap();
+        let bytes = memory
+            .get(ptr, len as usize)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
 
         self.print(&bytes);
 
@@ -420,63 +1766,221 @@ struct Inner<'a> {
     post_root: RefCell<[u8; 32]>,
     instance: ModuleRef,
     buffer: RefCell<Buffer>,
+    shared: SharedMemory,
 
-    children: RefCell<HashMap<u32, ChildRuntime<'a>>>,
+    children: RefCell<HashMap<u32, Rc<ChildRuntime<'a>>>>,
 
     call_targets: RefCell<HashSet<String>>,
+    call_permissions: RefCell<HashSet<(u32, u32)>>,
     call_stack: RefCell<Vec<StackFrame>>,
 
-    logger: RefCell<Option<Box<dyn Fn(&str) + 'a>>>,
+    journal: RefCell<Vec<Checkpoint>>,
+    changes: RefCell<Vec<Change>>,
+    logs: RefCell<Vec<String>>,
+    logger: RefCell<Option<Box<dyn Fn(&str) + 'a>>>,
+
+    gas: Cell<u64>,
+    gas_schedule: GasSchedule,
+    module_limit: Cell<u32>,
+    memory: MemoryConfig,
+    call_depth_limit: Option<usize>,
+    host_functions: u32,
+    registry: Registry<'a>,
+    debug: bool,
+    tracer: RefCell<Option<Box<dyn Tracer + 'a>>>,
+    profiler: Option<RefCell<Profiler>>,
+    backtraces: bool,
+
+    /// Names of the root's functions, if it was instrumented.
+    function_names: FunctionNames,
+
+    /// Functions being executed, as the module executing them and the
+    /// position at which the module defines them.
+    wasm_frames: RefCell<Vec<(Option<u32>, u32)>>,
+
+    /// Frames recorded when a trap was first seen, until the trap is turned
+    /// into an error or a return code.
+    backtrace: RefCell<Option<Backtrace>>,
+    last_trap: RefCell<Option<Error>>,
+
+    debugger: RefCell<Option<debugger::State<'a>>>,
+
+    /// Cross-module calls in progress, tracked only while debugging.
+    calls: RefCell<Vec<CallFrame>>,
+
+    recorder: Option<RefCell<Recorder>>,
+    replayer: Option<RefCell<Replayer>>,
+
+    /// Whether host functions with side effects trap.
+    view: bool,
 }
 
 impl<'a> Execute for RootRuntime<'a> {
-    fn execute(&mut self) -> [u8; 32] {
+    fn execute(&mut self) -> Result<[u8; 32], Error> {
+        self.run().map(|execution| execution.post_root)
+    }
+}
+
+impl<'a> RootRuntime<'a> {
+    /// Runs the module's `main` function like `Execute::execute`, also
+    /// reporting the memory the root used. Fails with `Error::ExtraPages` if
+    /// the configured extra pages exceed the memory limit.
+    pub fn run(&mut self) -> Result<Execution, Error> {
         let mut externals = RootExternals(self);
 
-        #[cfg(feature = "extra-pages")]
-        externals
-            .0
-            .memory()
-            .grow(wasmi::memory_units::Pages(100))
-            .expect("page count to increase by 100");
+        let extra_pages = self.0.memory.extra_pages;
+        if extra_pages > 0 {
+            self.memory()
+                .grow(Pages(extra_pages as usize))
+                .map_err(|_| Error::ExtraPages { pages: extra_pages })?;
+        }
 
-        self.0
+        if let Some(recorder) = &self.0.recorder {
+            recorder.borrow_mut().start(self.gas_left());
+        }
+
+        let result = self
+            .0
             .instance
             .invoke_export("main", &[], &mut externals)
-            .expect("Executed 'main'");
+            .map(|_| *self.0.post_root.borrow())
+            .map_err(|error| {
+                let message = match error {
+                    wasmi::Error::Trap(trap) => describe(&trap),
+                    error => error.to_string(),
+                };
+
+                let error = self.trap_error(message);
+                self.unwind_frames(0);
+                error
+            });
+
+        if let Some(recorder) = &self.0.recorder {
+            let outcome = result.as_ref().map(|root| *root).map_err(replay::message);
+            recorder.borrow_mut().finish(outcome);
+        }
+
+        result.map(|post_root| Execution {
+            post_root,
+            memory: self.memory_usage(),
+        })
+    }
+}
+
+/// Describes a trap, using the runtime's own message if it raised the trap.
+fn describe(trap: &Trap) -> String {
+    match trap.kind() {
+        TrapKind::Host(error) => match error.downcast_ref::<Error>() {
+            Some(error) => error.to_string(),
+            None => error.to_string(),
+        },
+        kind => format!("wasm trap: {:?}", kind),
+    }
+}
 
-        *self.0.post_root.borrow()
+/// Recovers the runtime's error from a trap, or describes the trap.
+fn into_error(trap: &Trap) -> Error {
+    match trap.kind() {
+        TrapKind::Host(error) => match error.downcast_ref::<Error>() {
+            Some(error) => error.clone(),
+            None => Error::Trap {
+                message: describe(trap),
+                backtrace: Default::default(),
+            },
+        },
+        _ => Error::Trap {
+            message: describe(trap),
+            backtrace: Default::default(),
+        },
     }
 }
 
 struct RootExternals<'a, 'b>(&'a RootRuntime<'b>);
 
-impl<'a, 'b> Externals for RootExternals<'a, 'b> {
-    fn invoke_index(
-        &mut self,
-        index: usize,
-        args: RuntimeArgs,
-    ) -> Result<Option<RuntimeValue>, Trap> {
+impl<'a, 'b> RootExternals<'a, 'b> {
+    fn dispatch(&mut self, index: usize, args: RuntimeArgs) -> ExtResult {
         match index {
-            LOADPRESTATEROOT_FUNC_INDEX => self.0.ext_load_pre_state_root(args),
+            LOADPRESTATEROOT_FUNC_INDEX => self.0.ext_load_pre_state_root(&self.0.memory(), args),
             SAVEPOSTSTATEROOT_FUNC_INDEX => self.0.ext_save_post_state_root(args),
             BLOCKDATASIZE_FUNC_INDEX => self.0.ext_block_data_size(args),
-            BLOCKDATACOPY_FUNC_INDEX => self.0.ext_block_data_copy(args),
-            BUFFERGET_FUNC_INDEX => self.0.ext_buffer_get(args),
-            BUFFERSET_FUNC_INDEX => self.0.ext_buffer_set(args),
+            BLOCKDATACOPY_FUNC_INDEX => self.0.ext_block_data_copy(&self.0.memory(), args),
+            BLOCKDATACOPYV2_FUNC_INDEX => self.0.ext_block_data_copy_v2(&self.0.memory(), args),
+            BUFFERGET_FUNC_INDEX => self.0.ext_buffer_get(&self.0.memory(), args),
+            BUFFERSET_FUNC_INDEX => self.0.ext_buffer_set(&self.0.memory(), args),
             BUFFERMERGE_FUNC_INDEX => self.0.ext_buffer_merge(args),
             BUFFERCLEAR_FUNC_INDEX => self.0.ext_buffer_clear(args),
+            BUFFERCOUNT_FUNC_INDEX => self.0.ext_buffer_count(args),
+            BUFFERKEYS_FUNC_INDEX => self.0.ext_buffer_keys(&self.0.memory(), args),
+            BUFFERDELETE_FUNC_INDEX => self.0.ext_buffer_delete(&self.0.memory(), args),
+            BUFFERCOPY_FUNC_INDEX => self.0.ext_buffer_copy(args),
+            BUFFERMERGEMISSING_FUNC_INDEX => self.0.ext_buffer_merge_missing(args),
+            BUFFERMERGECHECKED_FUNC_INDEX => {
+                self.0.ext_buffer_merge_checked(&self.0.memory(), args)
+            }
+            SHAREDMEMORYWRITE_FUNC_INDEX => self.0.ext_shared_memory_write(args),
             LOADMODULE_FUNC_INDEX => self.0.ext_load_module(args),
+            LOADMODULEWITHCAPABILITIES_FUNC_INDEX => self.0.ext_load_module_with_capabilities(args),
+            LOADMODULEWITHLIMITS_FUNC_INDEX => self.0.ext_load_module_with_limits(args),
+            LIMITMODULES_FUNC_INDEX => self.0.ext_limit_modules(args),
             CALLMODULE_FUNC_INDEX => self.0.ext_call_module(args),
+            CALLMODULETYPED_FUNC_INDEX => self.0.ext_call_module_typed(args),
+            ALLOWCALL_FUNC_INDEX => self.0.ext_allow_call(args),
             EXPOSE_FUNC_INDEX => self.0.ext_expose(args),
             ARGUMENT_FUNC_INDEX => self.0.ext_argument(args),
             RETURN_FUNC_INDEX => self.0.ext_return(args),
             PRINT_FUNC_INDEX => self.0.ext_print(args),
+            index if (DEBUG_FUNC_INDEX..INSTRUMENT_FUNC_INDEX).contains(&index) => {
+                debug::invoke(self.0, &self.0.memory(), index - DEBUG_FUNC_INDEX, args)
+            }
+            index if index >= EXTENSION_FUNC_INDEX => {
+                self.0
+                    .call_extension(index - EXTENSION_FUNC_INDEX, self.0.memory(), None, args)
+            }
             _ => panic!("unknown function index"),
         }
     }
 }
 
+impl<'a, 'b> Externals for RootExternals<'a, 'b> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        // Calls added by instrumentation aren't traced.
+        if (INSTRUMENT_FUNC_INDEX..EXTENSION_FUNC_INDEX).contains(&index) {
+            let names = &(self.0).0.function_names;
+            let index = index - INSTRUMENT_FUNC_INDEX;
+            return instrument::invoke(self.0, None, names, index, args);
+        }
+
+        if self.0.debugging() {
+            self.0.break_at(Stop::HostCall {
+                caller: None,
+                name: self.0.host_name(index),
+                args: args.as_ref().to_vec(),
+            });
+        }
+
+        if self.0.viewing() && self.0.has_side_effects(index) {
+            let function = self.0.host_name(index);
+            return Err(Error::ViewViolation { function }.into());
+        }
+
+        if !self.0.observing() {
+            return self.dispatch(index, args);
+        }
+
+        let root = self.0;
+        let name = root.host_name(index);
+        let extension = index >= EXTENSION_FUNC_INDEX;
+
+        root.host_call(None, name, extension, &root.memory(), args, |args| {
+            self.dispatch(index, args)
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -509,6 +2013,18 @@ mod test {
         rt
     }
 
+    fn save_post_root(runtime: &RootRuntime, root: [u8; 32]) {
+        runtime.memory().set(0, &root).unwrap();
+
+        let mut externals = RootExternals(runtime);
+        Externals::invoke_index(
+            &mut externals,
+            SAVEPOSTSTATEROOT_FUNC_INDEX,
+            [0.into()][..].into(),
+        )
+        .unwrap();
+    }
+
     #[test]
     fn return_long_value_does_not_overwrite() {
         let memory = MemoryInstance::alloc(Pages(1), None).unwrap();
@@ -867,4 +2383,403 @@ mod test {
         assert_eq!(buffer.get(1, [0u8; 32]), Some(&[0u8; 32]));
         assert_eq!(buffer.get(2, [0u8; 32]), None);
     }
+
+    #[test]
+    fn buffer_count() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [0u8; 32], [0u8; 32]);
+        buffer.insert(1, [1u8; 32], [0u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        assert_eq!(
+            Externals::invoke_index(&mut externals, BUFFERCOUNT_FUNC_INDEX, [1.into()][..].into())
+                .unwrap()
+                .unwrap(),
+            2.into()
+        );
+    }
+
+    #[test]
+    fn buffer_keys() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(300, [2u8; 32], [0u8; 32]);
+        buffer.insert(300, [1u8; 32], [0u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        let result: u32 = Externals::invoke_index(
+            &mut externals,
+            BUFFERKEYS_FUNC_INDEX,
+            [300.into(), 0.into(), 48.into()][..].into(),
+        )
+        .unwrap()
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        // Only the first key fits, but the full length is reported.
+        assert_eq!(result, 64);
+        assert_eq!(runtime.memory().get(0, 32).unwrap(), [1u8; 32]);
+        assert_eq!(runtime.memory().get(32, 32).unwrap(), [0u8; 32]);
+    }
+
+    #[test]
+    fn buffer_keys_out_of_bounds() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [1u8; 32], [0u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        let trap = Externals::invoke_index(
+            &mut externals,
+            BUFFERKEYS_FUNC_INDEX,
+            [1.into(), 65_536.into(), 32.into()][..].into(),
+        )
+        .unwrap_err();
+
+        match trap.kind() {
+            TrapKind::MemoryAccessOutOfBounds => (),
+            kind => panic!("unexpected trap {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn buffer_delete() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [1u8; 32], [1u8; 32]);
+        buffer.insert(1, [2u8; 32], [2u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+        runtime.memory().set(0, &[1u8; 32]).unwrap();
+
+        let mut externals = RootExternals(&runtime);
+        let deleted = Externals::invoke_index(
+            &mut externals,
+            BUFFERDELETE_FUNC_INDEX,
+            [1.into(), 0.into()][..].into(),
+        )
+        .unwrap();
+
+        let missing = Externals::invoke_index(
+            &mut externals,
+            BUFFERDELETE_FUNC_INDEX,
+            [1.into(), 0.into()][..].into(),
+        )
+        .unwrap();
+
+        assert_eq!(deleted, Some(0.into()));
+        assert_eq!(missing, Some(1.into()));
+
+        let buffer = runtime.0.buffer.borrow();
+        assert_eq!(buffer.get(1, [1u8; 32]), None);
+        assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn buffer_copy() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [0u8; 32], [1u8; 32]);
+        buffer.insert(3, [0u8; 32], [3u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        let copied = Externals::invoke_index(
+            &mut externals,
+            BUFFERCOPY_FUNC_INDEX,
+            [1.into(), 2.into()][..].into(),
+        )
+        .unwrap();
+
+        let occupied = Externals::invoke_index(
+            &mut externals,
+            BUFFERCOPY_FUNC_INDEX,
+            [1.into(), 3.into()][..].into(),
+        )
+        .unwrap();
+
+        assert_eq!(copied, Some(0.into()));
+        assert_eq!(occupied, Some(1.into()));
+
+        let buffer = runtime.0.buffer.borrow();
+        assert_eq!(buffer.get(2, [0u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(3, [0u8; 32]), Some(&[3u8; 32]));
+    }
+
+    #[test]
+    fn buffer_merge_missing() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [0u8; 32], [0u8; 32]);
+        buffer.insert(1, [1u8; 32], [1u8; 32]);
+        buffer.insert(2, [2u8; 32], [2u8; 32]);
+        buffer.insert(2, [0u8; 32], [3u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        Externals::invoke_index(
+            &mut externals,
+            BUFFERMERGEMISSING_FUNC_INDEX,
+            [1.into(), 2.into()][..].into(),
+        )
+        .unwrap();
+
+        let buffer = runtime.0.buffer.borrow();
+        assert_eq!(buffer.get(1, [0u8; 32]), Some(&[0u8; 32]));
+        assert_eq!(buffer.get(1, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
+        assert_eq!(buffer.get(2, [0u8; 32]), Some(&[3u8; 32]));
+        assert_eq!(buffer.get(2, [2u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn buffer_merge_checked() {
+        let mut buffer = Buffer::default();
+
+        buffer.insert(1, [0u8; 32], [0u8; 32]);
+        buffer.insert(1, [1u8; 32], [1u8; 32]);
+        buffer.insert(2, [2u8; 32], [2u8; 32]);
+        buffer.insert(2, [0u8; 32], [3u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let mut externals = RootExternals(&runtime);
+        let result = Externals::invoke_index(
+            &mut externals,
+            BUFFERMERGECHECKED_FUNC_INDEX,
+            [1.into(), 2.into(), 64.into(), 32.into()][..].into(),
+        )
+        .unwrap();
+
+        assert_eq!(result, Some(32.into()));
+        assert_eq!(runtime.memory().get(64, 32).unwrap(), [0u8; 32]);
+
+        let buffer = runtime.0.buffer.borrow();
+        assert_eq!(buffer.get(1, [0u8; 32]), Some(&[0u8; 32]));
+        assert_eq!(buffer.get(1, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(1, [2u8; 32]), Some(&[2u8; 32]));
+    }
+
+    #[test]
+    fn journaled_commits_on_success() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+
+        let retcode = runtime.journaled(|| {
+            runtime
+                .0
+                .buffer
+                .borrow_mut()
+                .insert(0, [1u8; 32], [2u8; 32]);
+            save_post_root(&runtime, build_root(42));
+            Ok(7)
+        });
+
+        assert_eq!(retcode.unwrap(), 7);
+        assert!(runtime.0.journal.borrow().is_empty());
+        assert_eq!(*runtime.0.post_root.borrow(), build_root(42));
+        assert_eq!(
+            runtime.0.buffer.borrow().get(0, [1u8; 32]),
+            Some(&[2u8; 32])
+        );
+    }
+
+    #[test]
+    fn journaled_reverts_on_failure() {
+        let mut buffer = Buffer::default();
+        buffer.insert(0, [1u8; 32], [1u8; 32]);
+
+        let runtime = build_runtime(&[], build_root(0), buffer);
+
+        let retcode = runtime.journaled(|| {
+            runtime
+                .0
+                .buffer
+                .borrow_mut()
+                .insert(0, [1u8; 32], [2u8; 32]);
+            runtime
+                .0
+                .buffer
+                .borrow_mut()
+                .insert(1, [1u8; 32], [2u8; 32]);
+            save_post_root(&runtime, build_root(42));
+            runtime.print(b"discarded");
+            Ok(-3)
+        });
+
+        assert_eq!(retcode.unwrap(), -3);
+        assert!(runtime.0.logs.borrow().is_empty());
+        assert_eq!(*runtime.0.post_root.borrow(), build_root(0));
+        assert_eq!(
+            runtime.0.buffer.borrow().get(0, [1u8; 32]),
+            Some(&[1u8; 32])
+        );
+        assert_eq!(runtime.0.buffer.borrow().get(1, [1u8; 32]), None);
+    }
+
+    #[test]
+    fn journaled_reverts_on_trap() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+
+        let retcode = runtime.journaled(|| {
+            runtime
+                .0
+                .buffer
+                .borrow_mut()
+                .insert(0, [1u8; 32], [2u8; 32]);
+            Err(Trap::new(TrapKind::Unreachable))
+        });
+
+        assert_eq!(retcode.unwrap(), journal::TRAPPED);
+        assert_eq!(runtime.0.buffer.borrow().get(0, [1u8; 32]), None);
+    }
+
+    #[test]
+    fn journaled_propagates_out_of_gas() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+
+        let result = runtime.journaled(|| {
+            runtime
+                .0
+                .buffer
+                .borrow_mut()
+                .insert(0, [1u8; 32], [2u8; 32]);
+            Err(Error::OutOfGas { slot: 0 }.into())
+        });
+
+        assert!(result.is_err());
+        assert!(runtime.0.journal.borrow().is_empty());
+        assert_eq!(runtime.0.buffer.borrow().get(0, [1u8; 32]), None);
+    }
+
+    #[test]
+    fn journaled_reserves_trap_code() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+
+        let retcode = runtime.journaled(|| {
+            save_post_root(&runtime, build_root(42));
+            Ok(journal::TRAPPED)
+        });
+
+        assert_eq!(retcode.unwrap(), journal::TRAPPED);
+        assert_eq!(*runtime.0.post_root.borrow(), build_root(0));
+        assert!(runtime.last_trap().is_some());
+    }
+
+    #[test]
+    fn journaled_reverts_shared_memory_write() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+        let memory = runtime.memory();
+        memory.set(0, &[1, 1, 2, 2]).unwrap();
+
+        let mut externals = RootExternals(&runtime);
+        Externals::invoke_index(
+            &mut externals,
+            SHAREDMEMORYWRITE_FUNC_INDEX,
+            [0.into(), 0.into(), 2.into()][..].into(),
+        )
+        .unwrap();
+
+        let retcode = runtime.journaled(|| {
+            let mut externals = RootExternals(&runtime);
+            Externals::invoke_index(
+                &mut externals,
+                SHAREDMEMORYWRITE_FUNC_INDEX,
+                [1.into(), 2.into(), 2.into()][..].into(),
+            )?;
+            Ok(-1)
+        });
+
+        assert_eq!(retcode.unwrap(), -1);
+        assert_eq!(runtime.0.shared.size(), 2);
+
+        runtime.0.shared.read(&memory, 0, 10, 2).unwrap();
+        assert_eq!(memory.get(10, 2).unwrap(), [1, 1]);
+    }
+
+    #[test]
+    fn journaled_refunds_module_load() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+        runtime.0.gas.set(100);
+        runtime.0.module_limit.set(1);
+
+        let limits = Limits {
+            max_pages: None,
+            gas: Some(40),
+        };
+
+        let retcode = runtime.journaled(|| {
+            runtime.instantiate_module(0, &NOP, capabilities::DEFAULT, limits)?;
+            Ok(-1)
+        });
+
+        assert_eq!(retcode.unwrap(), -1);
+        assert_eq!(runtime.gas_left(), 100);
+        assert!(runtime.0.children.borrow().is_empty());
+
+        // The slot and the module count are free again.
+        runtime
+            .instantiate_module(0, &NOP, capabilities::DEFAULT, limits)
+            .unwrap();
+        assert_eq!(runtime.gas_left(), 60);
+    }
+
+    #[test]
+    fn nested_journal_reverts_inner_only() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+
+        runtime
+            .journaled(|| {
+                runtime
+                    .0
+                    .buffer
+                    .borrow_mut()
+                    .insert(0, [1u8; 32], [1u8; 32]);
+
+                runtime.journaled(|| {
+                    runtime
+                        .0
+                        .buffer
+                        .borrow_mut()
+                        .insert(0, [2u8; 32], [2u8; 32]);
+                    Ok(-1)
+                })?;
+
+                Ok(0)
+            })
+            .unwrap();
+
+        let buffer = runtime.0.buffer.borrow();
+        assert_eq!(buffer.get(0, [1u8; 32]), Some(&[1u8; 32]));
+        assert_eq!(buffer.get(0, [2u8; 32]), None);
+    }
+
+    #[test]
+    fn shared_memory_write() {
+        let runtime = build_runtime(&[], build_root(0), Buffer::default());
+        runtime.memory().set(0, &[1, 2, 3]).unwrap();
+
+        let mut externals = RootExternals(&runtime);
+        Externals::invoke_index(
+            &mut externals,
+            SHAREDMEMORYWRITE_FUNC_INDEX,
+            [100.into(), 0.into(), 3.into()][..].into(),
+        )
+        .unwrap();
+
+        let memory = runtime.memory();
+        runtime.0.shared.read(&memory, 100, 10, 3).unwrap();
+
+        assert_eq!(runtime.0.shared.size(), 103);
+        assert_eq!(memory.get(10, 3).unwrap(), [1, 2, 3]);
+    }
 }
@@ -1,3 +1,7 @@
+use crate::env::abi::Abi;
+use crate::env::child::capabilities;
+use crate::env::registry::{self, Registry};
+
 use wasmi::{
     Error as InterpreterError, FuncInstance, FuncRef, ModuleImportResolver, Signature, ValueType,
 };
@@ -15,16 +19,145 @@ pub const EXPOSE_FUNC_INDEX: usize = 9;
 pub const ARGUMENT_FUNC_INDEX: usize = 10;
 pub const RETURN_FUNC_INDEX: usize = 11;
 pub const CALLMODULE_FUNC_INDEX: usize = 12;
+pub const BUFFERCOUNT_FUNC_INDEX: usize = 13;
+pub const BUFFERKEYS_FUNC_INDEX: usize = 14;
+pub const BUFFERDELETE_FUNC_INDEX: usize = 15;
+pub const BUFFERCOPY_FUNC_INDEX: usize = 16;
+pub const BUFFERMERGEMISSING_FUNC_INDEX: usize = 17;
+pub const BUFFERMERGECHECKED_FUNC_INDEX: usize = 18;
+pub const SHAREDMEMORYWRITE_FUNC_INDEX: usize = 19;
+pub const CALLMODULETYPED_FUNC_INDEX: usize = 20;
+pub const ALLOWCALL_FUNC_INDEX: usize = 21;
+pub const LOADMODULEWITHCAPABILITIES_FUNC_INDEX: usize = 22;
+pub const LOADMODULEWITHLIMITS_FUNC_INDEX: usize = 23;
+pub const LIMITMODULES_FUNC_INDEX: usize = 24;
+pub const BLOCKDATACOPYV2_FUNC_INDEX: usize = 25;
 pub const PRINT_FUNC_INDEX: usize = 99;
 
-pub struct RuntimeModuleImportResolver;
+/// Indices from `DEBUG_FUNC_INDEX` up to `INSTRUMENT_FUNC_INDEX` refer to the
+/// functions of the `debug` namespace.
+pub const DEBUG_FUNC_INDEX: usize = 1 << 12;
+
+/// Indices from `INSTRUMENT_FUNC_INDEX` up to `EXTENSION_FUNC_INDEX` refer to the
+/// functions of the instrumentation namespace.
+pub const INSTRUMENT_FUNC_INDEX: usize = 1 << 13;
+
+/// Indices from `EXTENSION_FUNC_INDEX` onwards refer to host functions added
+/// by the embedder, in registration order.
+pub const EXTENSION_FUNC_INDEX: usize = registry::FUNC_INDEX;
+
+/// Returns the name of the built-in host function at `index`.
+pub fn name(index: usize) -> Option<&'static str> {
+    let name = match index {
+        LOADPRESTATEROOT_FUNC_INDEX => "eth2_loadPreStateRoot",
+        BLOCKDATASIZE_FUNC_INDEX => "eth2_blockDataSize",
+        BLOCKDATACOPY_FUNC_INDEX => "eth2_blockDataCopy",
+        SAVEPOSTSTATEROOT_FUNC_INDEX => "eth2_savePostStateRoot",
+        BUFFERGET_FUNC_INDEX => "eth2_bufferGet",
+        BUFFERSET_FUNC_INDEX => "eth2_bufferSet",
+        BUFFERMERGE_FUNC_INDEX => "eth2_bufferMerge",
+        BUFFERCLEAR_FUNC_INDEX => "eth2_bufferClear",
+        LOADMODULE_FUNC_INDEX => "eth2_loadModule",
+        EXPOSE_FUNC_INDEX => "eth2_expose",
+        ARGUMENT_FUNC_INDEX => "eth2_argument",
+        RETURN_FUNC_INDEX => "eth2_return",
+        CALLMODULE_FUNC_INDEX => "eth2_callModule",
+        BUFFERCOUNT_FUNC_INDEX => "eth2_bufferCount",
+        BUFFERKEYS_FUNC_INDEX => "eth2_bufferKeys",
+        BUFFERDELETE_FUNC_INDEX => "eth2_bufferDelete",
+        BUFFERCOPY_FUNC_INDEX => "eth2_bufferCopy",
+        BUFFERMERGEMISSING_FUNC_INDEX => "eth2_bufferMergeMissing",
+        BUFFERMERGECHECKED_FUNC_INDEX => "eth2_bufferMergeChecked",
+        SHAREDMEMORYWRITE_FUNC_INDEX => "eth2_sharedMemoryWrite",
+        CALLMODULETYPED_FUNC_INDEX => "eth2_callModuleTyped",
+        ALLOWCALL_FUNC_INDEX => "eth2_allowCall",
+        LOADMODULEWITHCAPABILITIES_FUNC_INDEX => "eth2_loadModuleWithCapabilities",
+        LOADMODULEWITHLIMITS_FUNC_INDEX => "eth2_loadModuleWithLimits",
+        LIMITMODULES_FUNC_INDEX => "eth2_limitModules",
+        BLOCKDATACOPYV2_FUNC_INDEX => "eth2_v2.eth2_blockDataCopy",
+        PRINT_FUNC_INDEX => "print",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Whether the built-in host function at `index` has side effects, and so
+/// traps in view mode.
+pub fn has_side_effects(index: usize) -> bool {
+    matches!(
+        index,
+        SAVEPOSTSTATEROOT_FUNC_INDEX
+            | BUFFERSET_FUNC_INDEX
+            | BUFFERMERGE_FUNC_INDEX
+            | BUFFERMERGEMISSING_FUNC_INDEX
+            | BUFFERMERGECHECKED_FUNC_INDEX
+            | BUFFERCLEAR_FUNC_INDEX
+            | BUFFERDELETE_FUNC_INDEX
+            | BUFFERCOPY_FUNC_INDEX
+            | SHAREDMEMORYWRITE_FUNC_INDEX
+            | LOADMODULE_FUNC_INDEX
+            | LOADMODULEWITHCAPABILITIES_FUNC_INDEX
+            | LOADMODULEWITHLIMITS_FUNC_INDEX
+            | PRINT_FUNC_INDEX
+    )
+}
+
+/// Resolves the root's imports, limited to the enabled groups of host
+/// functions.
+pub struct RuntimeModuleImportResolver<'r, 'a> {
+    abi: Abi,
+    host_functions: u32,
+    registry: &'r Registry<'a>,
+}
 
-impl<'a> ModuleImportResolver for RuntimeModuleImportResolver {
+impl<'r, 'a> RuntimeModuleImportResolver<'r, 'a> {
+    pub fn new(abi: Abi, host_functions: u32, registry: &'r Registry<'a>) -> Self {
+        Self {
+            abi,
+            host_functions,
+            registry,
+        }
+    }
+}
+
+/// Returns the group of host functions `field_name` belongs to, using the bits
+/// of `capabilities`. Functions that are always available belong to no group.
+fn group(field_name: &str) -> u32 {
+    match field_name {
+        "eth2_loadPreStateRoot" | "eth2_blockDataSize" | "eth2_blockDataCopy" => {
+            capabilities::BLOCK_DATA
+        }
+        name if name.starts_with("eth2_buffer") => capabilities::BUFFER,
+        "print" => capabilities::LOGGING,
+        "eth2_sharedMemoryWrite" => capabilities::SHARED_MEMORY,
+        "eth2_expose" => capabilities::CALL_ROOT,
+        "eth2_loadModule"
+        | "eth2_loadModuleWithCapabilities"
+        | "eth2_loadModuleWithLimits"
+        | "eth2_limitModules"
+        | "eth2_callModule"
+        | "eth2_callModuleTyped"
+        | "eth2_allowCall" => capabilities::CALL_MODULE,
+        _ => 0,
+    }
+}
+
+impl<'r, 'a> ModuleImportResolver for RuntimeModuleImportResolver<'r, 'a> {
     fn resolve_func(
         &self,
         field_name: &str,
         _signature: &Signature,
     ) -> Result<FuncRef, InterpreterError> {
+        let required = group(field_name);
+
+        if self.host_functions & required != required {
+            return Err(InterpreterError::Instantiation(format!(
+                "host function {} isn't enabled",
+                field_name
+            )));
+        }
+
         let func_ref = match field_name {
             "eth2_loadPreStateRoot" => FuncInstance::alloc_host(
                 Signature::new(&[ValueType::I32][..], None),
@@ -40,7 +173,10 @@ impl<'a> ModuleImportResolver for RuntimeModuleImportResolver {
             ),
             "eth2_blockDataCopy" => FuncInstance::alloc_host(
                 Signature::new(&[ValueType::I32, ValueType::I32, ValueType::I32][..], None),
-                BLOCKDATACOPY_FUNC_INDEX,
+                match self.abi {
+                    Abi::V1 => BLOCKDATACOPY_FUNC_INDEX,
+                    Abi::V2 => BLOCKDATACOPYV2_FUNC_INDEX,
+                },
             ),
             "eth2_bufferGet" => FuncInstance::alloc_host(
                 Signature::new(
@@ -61,14 +197,72 @@ impl<'a> ModuleImportResolver for RuntimeModuleImportResolver {
                 Signature::new(&[ValueType::I32][..], None),
                 BUFFERCLEAR_FUNC_INDEX,
             ),
+            "eth2_bufferCount" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
+                BUFFERCOUNT_FUNC_INDEX,
+            ),
+            "eth2_bufferKeys" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 3][..], Some(ValueType::I32)),
+                BUFFERKEYS_FUNC_INDEX,
+            ),
+            "eth2_bufferDelete" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 2][..], Some(ValueType::I32)),
+                BUFFERDELETE_FUNC_INDEX,
+            ),
+            "eth2_bufferCopy" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 2][..], Some(ValueType::I32)),
+                BUFFERCOPY_FUNC_INDEX,
+            ),
+            "eth2_bufferMergeMissing" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 2][..], None),
+                BUFFERMERGEMISSING_FUNC_INDEX,
+            ),
+            "eth2_bufferMergeChecked" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 4][..], Some(ValueType::I32)),
+                BUFFERMERGECHECKED_FUNC_INDEX,
+            ),
+            "eth2_sharedMemoryWrite" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 3][..], None),
+                SHAREDMEMORYWRITE_FUNC_INDEX,
+            ),
             "eth2_loadModule" => FuncInstance::alloc_host(
                 Signature::new(&[ValueType::I32; 3][..], None),
                 LOADMODULE_FUNC_INDEX,
             ),
+            "eth2_loadModuleWithCapabilities" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 4][..], None),
+                LOADMODULEWITHCAPABILITIES_FUNC_INDEX,
+            ),
+            "eth2_loadModuleWithLimits" => FuncInstance::alloc_host(
+                Signature::new(
+                    &[
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I32,
+                        ValueType::I64,
+                    ][..],
+                    None,
+                ),
+                LOADMODULEWITHLIMITS_FUNC_INDEX,
+            ),
+            "eth2_limitModules" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32][..], None),
+                LIMITMODULES_FUNC_INDEX,
+            ),
             "eth2_callModule" => FuncInstance::alloc_host(
                 Signature::new(&[ValueType::I32; 7][..], Some(ValueType::I32)),
                 CALLMODULE_FUNC_INDEX,
             ),
+            "eth2_callModuleTyped" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 7][..], Some(ValueType::I32)),
+                CALLMODULETYPED_FUNC_INDEX,
+            ),
+            "eth2_allowCall" => FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32; 2][..], None),
+                ALLOWCALL_FUNC_INDEX,
+            ),
             "eth2_expose" => FuncInstance::alloc_host(
                 Signature::new(&[ValueType::I32, ValueType::I32][..], None),
                 EXPOSE_FUNC_INDEX,
@@ -85,12 +279,24 @@ impl<'a> ModuleImportResolver for RuntimeModuleImportResolver {
                 Signature::new(&[ValueType::I32; 2][..], None),
                 PRINT_FUNC_INDEX,
             ),
-            _ => {
-                return Err(InterpreterError::Function(format!(
-                    "host module doesn't export function with name {}",
-                    field_name
-                )))
-            }
+            _ => match self.registry.find(field_name) {
+                Some(_) if self.host_functions & capabilities::EXTENSIONS == 0 => {
+                    return Err(InterpreterError::Instantiation(format!(
+                        "host function {} isn't enabled",
+                        field_name
+                    )))
+                }
+                Some((index, function)) => FuncInstance::alloc_host(
+                    function.signature().clone(),
+                    EXTENSION_FUNC_INDEX + index,
+                ),
+                None => {
+                    return Err(InterpreterError::Function(format!(
+                        "host module doesn't export function with name {}",
+                        field_name
+                    )))
+                }
+            },
         };
         Ok(func_ref)
     }
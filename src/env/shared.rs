@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasmi::{Error as InterpreterError, MemoryRef};
+
+/// Upper bound on the size of the shared memory (16 MiB).
+pub const MAX_PAGES: u32 = 256;
+
+const MAX_BYTES: usize = MAX_PAGES as usize * 65536;
+
+/// A memory region that the root fills once, so that large inputs can be
+/// read by any number of child calls without being passed through
+/// `eth2_argument` each time.
+///
+/// This is shared, read-only and copy-on-read, not zero-copy: the root copies
+/// its input in, and each child copies out the bytes it reads. It saves
+/// copying an input once per call when many calls read it, or when a child
+/// only needs part of it. wasmi only gives a module a single linear memory,
+/// so a child can't map the region without giving up its own memory.
+///
+/// The runtime enforces the following rules:
+///
+/// * Only the root writes to the region, with `eth2_sharedMemoryWrite`.
+/// * Children granted the `SHARED_MEMORY` capability read from it with
+///   `eth2_sharedMemorySize` and `eth2_sharedMemoryCopy`. It is never part of
+///   a child's linear memory, so children can't change it, see each other's
+///   use of it, or overwrite it with their own data.
+/// * It can never grow beyond `MAX_PAGES`.
+///
+/// Writes made during a cross-module call are undone if the call fails, like
+/// every other change to the root's state.
+#[derive(Debug, Clone, Default)]
+pub struct SharedMemory(Rc<RefCell<Vec<u8>>>);
+
+/// The contents of the shared memory replaced by a write, used to undo it.
+#[derive(Debug, Clone)]
+pub struct Overwritten {
+    offset: usize,
+    data: Vec<u8>,
+    size: usize,
+}
+
+impl SharedMemory {
+    /// Returns the size of the shared memory in bytes.
+    pub fn size(&self) -> u32 {
+        self.0.borrow().len() as u32
+    }
+
+    /// Copies `length` bytes at `src_offset` in `src` into the shared memory
+    /// at `dest_offset`, growing the shared memory if required. Returns what
+    /// the write replaced.
+    pub fn write(
+        &self,
+        src: &MemoryRef,
+        src_offset: u32,
+        dest_offset: u32,
+        length: u32,
+    ) -> Result<Overwritten, InterpreterError> {
+        let offset = dest_offset as usize;
+        let end = offset + length as usize;
+
+        if end > MAX_BYTES {
+            return Err(InterpreterError::Memory(format!(
+                "shared memory can't exceed {} pages, {} bytes requested",
+                MAX_PAGES, end
+            )));
+        }
+
+        let data = src.get(src_offset, length as usize)?;
+
+        let mut memory = self.0.borrow_mut();
+        let size = memory.len();
+
+        let replaced = offset.min(size)..end.min(size);
+        let overwritten = Overwritten {
+            offset: replaced.start,
+            data: memory[replaced].to_vec(),
+            size,
+        };
+
+        if end > size {
+            memory.resize(end, 0);
+        }
+
+        memory[offset..end].copy_from_slice(&data);
+
+        Ok(overwritten)
+    }
+
+    /// Undoes a write, restoring the contents and size it replaced.
+    pub fn restore(&self, overwritten: Overwritten) {
+        let mut memory = self.0.borrow_mut();
+
+        let end = overwritten.offset + overwritten.data.len();
+        memory[overwritten.offset..end].copy_from_slice(&overwritten.data);
+        memory.truncate(overwritten.size);
+    }
+
+    /// Copies `length` bytes at `offset` in the shared memory into `dest` at
+    /// `dest_offset`. Fails if the range isn't within the shared memory.
+    pub fn read(
+        &self,
+        dest: &MemoryRef,
+        offset: u32,
+        dest_offset: u32,
+        length: u32,
+    ) -> Result<(), InterpreterError> {
+        let memory = self.0.borrow();
+
+        let start = offset as usize;
+        let end = start + length as usize;
+
+        match memory.get(start..end) {
+            Some(data) => dest.set(dest_offset, data),
+            None => Err(InterpreterError::Memory(format!(
+                "reading {} bytes at {} exceeds the shared memory size of {}",
+                length,
+                offset,
+                memory.len()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use wasmi::memory_units::Pages;
+    use wasmi::MemoryInstance;
+
+    #[test]
+    fn clones_share_contents() {
+        let shared = SharedMemory::default();
+        let src = MemoryInstance::alloc(Pages(1), None).unwrap();
+        src.set(0, &[42]).unwrap();
+
+        shared.clone().write(&src, 0, 0, 1).unwrap();
+
+        assert_eq!(shared.size(), 1);
+    }
+
+    #[test]
+    fn write_grows_memory() {
+        let shared = SharedMemory::default();
+
+        let src = MemoryInstance::alloc(Pages(1), None).unwrap();
+        src.set(10, &[1, 2, 3]).unwrap();
+
+        shared.write(&src, 10, 65536, 3).unwrap();
+
+        let dest = MemoryInstance::alloc(Pages(1), None).unwrap();
+        shared.read(&dest, 65536, 0, 3).unwrap();
+
+        assert_eq!(shared.size(), 65539);
+        assert_eq!(dest.get(0, 3).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn write_rejects_oversized() {
+        let shared = SharedMemory::default();
+        let src = MemoryInstance::alloc(Pages(1), None).unwrap();
+
+        let offset = MAX_PAGES * 65536;
+
+        assert!(shared.write(&src, 0, offset, 1).is_err());
+    }
+
+    #[test]
+    fn read_rejects_out_of_bounds() {
+        let shared = SharedMemory::default();
+        let memory = MemoryInstance::alloc(Pages(1), None).unwrap();
+
+        shared.write(&memory, 0, 0, 4).unwrap();
+
+        assert!(shared.read(&memory, 2, 0, 3).is_err());
+        assert!(shared.read(&memory, 2, 0, 2).is_ok());
+    }
+
+    #[test]
+    fn restore_undoes_write() {
+        let shared = SharedMemory::default();
+        let src = MemoryInstance::alloc(Pages(1), None).unwrap();
+        src.set(0, &[1, 1, 2, 2, 2, 2]).unwrap();
+
+        shared.write(&src, 0, 0, 2).unwrap();
+        let overwritten = shared.write(&src, 2, 1, 4).unwrap();
+        shared.restore(overwritten);
+
+        let dest = MemoryInstance::alloc(Pages(1), None).unwrap();
+        shared.read(&dest, 0, 0, 2).unwrap();
+
+        assert_eq!(shared.size(), 2);
+        assert_eq!(dest.get(0, 2).unwrap(), [1, 1]);
+    }
+}
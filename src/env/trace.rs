@@ -0,0 +1,359 @@
+//! Structured execution events for embedders debugging execution environments.
+
+use super::values::VALUE_SIZE;
+use super::{ExtResult, StackFrame};
+
+use std::io::Write;
+
+use wasmi::RuntimeValue;
+
+/// Receives an event for every host function invocation, cross-module call
+/// and module load. Install one with `RootRuntimeBuilder::tracer`.
+pub trait Tracer {
+    fn event(&mut self, event: &Event);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A range of a module's memory that a host function may read or write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub access: Access,
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// The buffers a cross-module call was given for its argument and return
+/// data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub argument_offset: u32,
+    pub argument_length: u32,
+    pub return_offset: u32,
+    pub return_length: u32,
+}
+
+impl<'f> From<&'f StackFrame> for Frame {
+    fn from(frame: &'f StackFrame) -> Self {
+        Self {
+            argument_offset: frame.argument_offset,
+            argument_length: frame.argument_length,
+            return_offset: frame.return_offset,
+            return_length: frame.return_length,
+        }
+    }
+}
+
+/// Where a function runs: the root, represented by `None`, or a child slot.
+pub type Module = Option<u32>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A host function returned, or trapped with the given message.
+    HostCall {
+        caller: Module,
+        name: String,
+        args: Vec<RuntimeValue>,
+        memory: Vec<MemoryRange>,
+        result: Result<Option<RuntimeValue>, String>,
+    },
+
+    /// A cross-module call is about to invoke `name`.
+    CallEnter {
+        callee: Module,
+        name: String,
+        args: Vec<RuntimeValue>,
+        frame: Frame,
+    },
+
+    /// A cross-module call finished.
+    CallExit {
+        callee: Module,
+        name: String,
+        result: Result<Option<RuntimeValue>, String>,
+    },
+
+    /// `eth2_loadModule` or one of its variants finished.
+    ModuleLoad {
+        slot: u32,
+        code_length: u32,
+        capabilities: u32,
+        result: Result<(), String>,
+    },
+}
+
+pub(crate) fn outcome(result: &ExtResult) -> Result<Option<RuntimeValue>, String> {
+    result
+        .as_ref()
+        .map(|value| *value)
+        .map_err(|trap| trap.to_string())
+}
+
+/// Returns the memory ranges the host function `name` accesses when called
+/// with `args`. Ranges are reported as requested by the caller, even if the
+/// function ends up accessing less.
+pub(crate) fn accesses(name: &str, args: &[RuntimeValue]) -> Vec<MemoryRange> {
+    let arg = |n: usize| -> u32 {
+        match args.get(n) {
+            Some(RuntimeValue::I32(value)) => *value as u32,
+            _ => 0,
+        }
+    };
+
+    let read = |offset: u32, length: u32| MemoryRange {
+        access: Access::Read,
+        offset,
+        length,
+    };
+
+    let write = |offset: u32, length: u32| MemoryRange {
+        access: Access::Write,
+        offset,
+        length,
+    };
+
+    let values = |count: u32| count.saturating_mul(VALUE_SIZE as u32);
+
+    match name {
+        "eth2_loadPreStateRoot" => vec![write(arg(0), 32)],
+        "eth2_savePostStateRoot" => vec![read(arg(0), 32)],
+        "eth2_blockDataCopy" => vec![write(arg(0), arg(2).saturating_sub(arg(1)))],
+        "eth2_v2.eth2_blockDataCopy" => vec![write(arg(0), arg(2))],
+        "eth2_bufferGet" => vec![read(arg(1), 32), write(arg(2), 32)],
+        "eth2_bufferSet" => vec![read(arg(1), 32), read(arg(2), 32)],
+        "eth2_bufferDelete" => vec![read(arg(1), 32)],
+        "eth2_bufferKeys" => vec![write(arg(1), arg(2))],
+        "eth2_bufferMergeChecked" => vec![write(arg(2), arg(3))],
+        "eth2_sharedMemoryWrite" => vec![read(arg(1), arg(2))],
+        "eth2_loadModule" | "eth2_loadModuleWithCapabilities" | "eth2_loadModuleWithLimits" => {
+            vec![read(arg(1), arg(2))]
+        }
+        "eth2_expose" | "eth2_return" | "print" => vec![read(arg(0), arg(1))],
+        "eth2_argument" => vec![write(arg(0), arg(1))],
+        "eth2_call" => vec![
+            read(arg(0), arg(1)),
+            read(arg(2), arg(3)),
+            write(arg(4), arg(5)),
+        ],
+        "eth2_callTyped" => vec![
+            read(arg(0), arg(1)),
+            read(arg(2), values(arg(3))),
+            write(arg(4), values(arg(5))),
+        ],
+        "eth2_callModule" => vec![
+            read(arg(1), arg(2)),
+            read(arg(3), arg(4)),
+            write(arg(5), arg(6)),
+        ],
+        "eth2_callModuleTyped" => vec![
+            read(arg(1), arg(2)),
+            read(arg(3), values(arg(4))),
+            write(arg(5), values(arg(6))),
+        ],
+        "debug_printMem" | "debug_printMemHex" | "debug_abort" => vec![read(arg(0), arg(1))],
+        _ => Vec::new(),
+    }
+}
+
+/// Writes every event as a JSON object on its own line.
+pub struct JsonTracer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn event(&mut self, event: &Event) {
+        writeln!(self.writer, "{}", json(event)).expect("trace to be written");
+    }
+}
+
+fn json(event: &Event) -> String {
+    match event {
+        Event::HostCall {
+            caller,
+            name,
+            args,
+            memory,
+            result,
+        } => format!(
+            r#"{{"event":"host_call","caller":{},"name":{},"args":{},"memory":[{}],"result":{}}}"#,
+            json_module(*caller),
+            json_string(name),
+            json_values(args),
+            memory
+                .iter()
+                .map(|range| format!(
+                    r#"{{"access":"{}","offset":{},"length":{}}}"#,
+                    match range.access {
+                        Access::Read => "read",
+                        Access::Write => "write",
+                    },
+                    range.offset,
+                    range.length
+                ))
+                .collect::<Vec<_>>()
+                .join(","),
+            json_result(result),
+        ),
+        Event::CallEnter {
+            callee,
+            name,
+            args,
+            frame,
+        } => format!(
+            r#"{{"event":"call_enter","callee":{},"name":{},"args":{},"frame":{{"argument_offset":{},"argument_length":{},"return_offset":{},"return_length":{}}}}}"#,
+            json_module(*callee),
+            json_string(name),
+            json_values(args),
+            frame.argument_offset,
+            frame.argument_length,
+            frame.return_offset,
+            frame.return_length,
+        ),
+        Event::CallExit {
+            callee,
+            name,
+            result,
+        } => format!(
+            r#"{{"event":"call_exit","callee":{},"name":{},"result":{}}}"#,
+            json_module(*callee),
+            json_string(name),
+            json_result(result),
+        ),
+        Event::ModuleLoad {
+            slot,
+            code_length,
+            capabilities,
+            result,
+        } => format!(
+            r#"{{"event":"module_load","slot":{},"code_length":{},"capabilities":{},"result":{}}}"#,
+            slot,
+            code_length,
+            capabilities,
+            match result {
+                Ok(()) => r#"{"ok":null}"#.to_string(),
+                Err(message) => format!(r#"{{"error":{}}}"#, json_string(message)),
+            },
+        ),
+    }
+}
+
+fn json_module(module: Module) -> String {
+    module.map_or_else(|| "null".to_string(), |slot| slot.to_string())
+}
+
+fn json_value(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::I32(v) => format!(r#"{{"i32":{}}}"#, v),
+        RuntimeValue::I64(v) => format!(r#"{{"i64":{}}}"#, v),
+        RuntimeValue::F32(v) => format!(r#"{{"f32":{}}}"#, json_string(&v.to_float().to_string())),
+        RuntimeValue::F64(v) => format!(r#"{{"f64":{}}}"#, json_string(&v.to_float().to_string())),
+    }
+}
+
+fn json_values(values: &[RuntimeValue]) -> String {
+    let values: Vec<_> = values.iter().map(json_value).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn json_result(result: &Result<Option<RuntimeValue>, String>) -> String {
+    match result {
+        Ok(Some(value)) => format!(r#"{{"ok":{}}}"#, json_value(value)),
+        Ok(None) => r#"{"ok":null}"#.to_string(),
+        Err(message) => format!(r#"{{"error":{}}}"#, json_string(message)),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn string_escaping() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+
+    #[test]
+    fn call_module_accesses() {
+        let args: Vec<RuntimeValue> = [0, 10, 4, 20, 8, 40, 16]
+            .iter()
+            .map(|v| RuntimeValue::I32(*v))
+            .collect();
+
+        let ranges = accesses("eth2_callModule", &args);
+
+        assert_eq!(
+            ranges,
+            vec![
+                MemoryRange {
+                    access: Access::Read,
+                    offset: 10,
+                    length: 4
+                },
+                MemoryRange {
+                    access: Access::Read,
+                    offset: 20,
+                    length: 8
+                },
+                MemoryRange {
+                    access: Access::Write,
+                    offset: 40,
+                    length: 16
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn host_call_json() {
+        let event = Event::HostCall {
+            caller: Some(2),
+            name: "print".to_string(),
+            args: vec![RuntimeValue::I32(1), RuntimeValue::I64(-2)],
+            memory: vec![MemoryRange {
+                access: Access::Read,
+                offset: 1,
+                length: 2,
+            }],
+            result: Ok(None),
+        };
+
+        assert_eq!(
+            json(&event),
+            r#"{"event":"host_call","caller":2,"name":"print","args":[{"i32":1},{"i64":-2}],"memory":[{"access":"read","offset":1,"length":2}],"result":{"ok":null}}"#
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Encoding of the packed value lists used by typed cross-module calls.
+//!
+//! Each value occupies `VALUE_SIZE` bytes: a one byte type tag using the Wasm
+//! binary encoding of the value type (`0x7f` for i32, `0x7e` for i64),
+//! followed by the value as a little endian i64. i32 values are sign extended.
+//!
+//! Results use the same encoding, so that callees returning several values
+//! need no new host functions once the interpreter supports multi-value
+//! returns. Until then a callee produces at most one result.
+
+use arrayref::array_ref;
+
+use wasmi::{MemoryRef, RuntimeValue, Signature, Trap, TrapKind, ValueType};
+
+pub const VALUE_SIZE: usize = 9;
+
+const I32_TAG: u8 = 0x7f;
+const I64_TAG: u8 = 0x7e;
+
+/// Decodes a packed value list. Returns `None` if the list is malformed or
+/// contains a type other than i32 or i64.
+pub fn decode(bytes: &[u8]) -> Option<Vec<RuntimeValue>> {
+    let chunks = bytes.chunks_exact(VALUE_SIZE);
+
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+
+    chunks
+        .map(|chunk| {
+            let value = i64::from_le_bytes(*array_ref![chunk, 1, 8]);
+
+            match chunk[0] {
+                I32_TAG => Some(RuntimeValue::I32(value as i32)),
+                I64_TAG => Some(RuntimeValue::I64(value)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Encodes a packed value list. Returns `None` if a value is neither an i32
+/// nor an i64.
+pub fn encode(values: &[RuntimeValue]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(values.len() * VALUE_SIZE);
+
+    for value in values {
+        let (tag, value) = match *value {
+            RuntimeValue::I32(v) => (I32_TAG, i64::from(v)),
+            RuntimeValue::I64(v) => (I64_TAG, v),
+            _ => return None,
+        };
+
+        bytes.push(tag);
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    Some(bytes)
+}
+
+/// Reads a packed list of `count` values from `memory`.
+pub fn read(memory: &MemoryRef, offset: u32, count: u32) -> Result<Vec<RuntimeValue>, Trap> {
+    let bytes = memory
+        .get(offset, count as usize * VALUE_SIZE)
+        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+
+    decode(&bytes).ok_or_else(|| Trap::new(TrapKind::UnexpectedSignature))
+}
+
+/// Writes at most `count` of `values` to `memory` as a packed list.
+pub fn write(
+    memory: &MemoryRef,
+    offset: u32,
+    count: u32,
+    values: &[RuntimeValue],
+) -> Result<(), Trap> {
+    let fits = std::cmp::min(count as usize, values.len());
+    let bytes = encode(&values[..fits]).ok_or_else(|| Trap::new(TrapKind::UnexpectedSignature))?;
+
+    memory
+        .set(offset, &bytes)
+        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+}
+
+/// Checks that a function called with `eth2_call` or `eth2_callModule` takes
+/// no parameters and returns an i32 return code.
+pub fn check_call_signature(signature: &Signature) -> Result<(), Trap> {
+    if signature.params().is_empty() && signature.return_type() == Some(ValueType::I32) {
+        Ok(())
+    } else {
+        Err(Trap::new(TrapKind::UnexpectedSignature))
+    }
+}
+
+/// Checks that `args` match the parameters of `signature`, and that the
+/// function only deals in i32 and i64 values.
+pub fn check_signature(signature: &Signature, args: &[RuntimeValue]) -> Result<(), Trap> {
+    let supported = |ty: &ValueType| *ty == ValueType::I32 || *ty == ValueType::I64;
+
+    let matches = signature.params().len() == args.len()
+        && signature
+            .params()
+            .iter()
+            .zip(args)
+            .all(|(ty, arg)| *ty == arg.value_type())
+        && signature.params().iter().all(supported)
+        && signature.return_type().iter().all(supported);
+
+    if matches {
+        Ok(())
+    } else {
+        Err(Trap::new(TrapKind::UnexpectedSignature))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values = vec![
+            RuntimeValue::I32(-7),
+            RuntimeValue::I64(1 << 40),
+            RuntimeValue::I32(3),
+        ];
+
+        let bytes = encode(&values).unwrap();
+
+        assert_eq!(bytes.len(), 3 * VALUE_SIZE);
+        assert_eq!(decode(&bytes).unwrap(), values);
+    }
+
+    #[test]
+    fn decode_rejects_malformed() {
+        assert_eq!(decode(&[I32_TAG, 0, 0]), None);
+        assert_eq!(decode(&[0x7d, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn encode_rejects_floats() {
+        assert_eq!(encode(&[RuntimeValue::F32(1.0.into())]), None);
+    }
+
+    #[test]
+    fn check_signature_matches_params() {
+        let signature = Signature::new(&[ValueType::I32, ValueType::I64][..], None);
+
+        let good = [RuntimeValue::I32(1), RuntimeValue::I64(2)];
+        let bad = [RuntimeValue::I64(1), RuntimeValue::I64(2)];
+
+        assert!(check_signature(&signature, &good).is_ok());
+        assert!(check_signature(&signature, &bad).is_err());
+        assert!(check_signature(&signature, &good[..1]).is_err());
+    }
+}
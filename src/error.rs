@@ -0,0 +1,140 @@
+use crate::backtrace::Backtrace;
+
+use std::fmt;
+
+use wasmi::HostError;
+
+/// Errors raised by the runtime while servicing a host function. They are
+/// converted into a `wasmi::Trap`, which carries the error as its payload.
+///
+/// `Trap` is returned by `Execute::execute` when the root traps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A child module called a slot it hasn't been permitted to call.
+    CallNotPermitted { caller: u32, callee: u32 },
+
+    /// A module called a slot that no module has been loaded into.
+    EmptySlot { slot: u32 },
+
+    /// The root couldn't be loaded, for example because it exceeds the memory
+    /// limit or imports a host function that isn't enabled.
+    InvalidRoot { reason: String },
+
+    /// A module passed a function name that isn't valid UTF-8.
+    InvalidName,
+
+    /// A module called a function that the module in `slot` doesn't export,
+    /// or that the root, represented by `None`, hasn't exposed.
+    UnknownFunction { slot: Option<u32>, name: String },
+
+    /// A child module couldn't be loaded, for example because it imports a
+    /// root function that hasn't been exposed.
+    Instantiation { slot: u32, reason: String },
+
+    /// A metered child module used up the gas it was allotted when loaded.
+    OutOfGas { slot: u32 },
+
+    /// A cross-module call would have exceeded the configured call depth.
+    CallDepthExceeded { limit: usize },
+
+    /// The root's memory couldn't be grown by the configured extra pages,
+    /// because they exceed the memory limit.
+    ExtraPages { pages: u32 },
+
+    /// A module called `debug_abort`.
+    Aborted { message: String },
+
+    /// WebAssembly text failed to compile, at the given line and column if
+    /// the compiler reported them.
+    Wat {
+        location: Option<(u32, u32)>,
+        message: String,
+    },
+
+    /// A replayed execution made a host call that differs from the one at
+    /// `index` in its recording.
+    Diverged { index: usize },
+
+    /// A module called a host function with side effects in view mode.
+    ViewViolation { function: String },
+
+    /// The root doesn't export a function `name` taking no parameters and
+    /// returning an i32, so the embedder can't invoke it.
+    UnknownExport { name: String },
+
+    /// A module trapped. The backtrace is empty unless the runtime was built
+    /// with backtraces enabled.
+    Trap {
+        message: String,
+        backtrace: Backtrace,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::CallNotPermitted { caller, callee } => write!(
+                f,
+                "module in slot {} is not permitted to call slot {}",
+                caller, callee
+            ),
+            Error::EmptySlot { slot } => write!(f, "no module is loaded in slot {}", slot),
+            Error::InvalidRoot { reason } => write!(f, "unable to load the root: {}", reason),
+            Error::InvalidName => write!(f, "function name isn't valid UTF-8"),
+            Error::UnknownFunction {
+                slot: Some(slot),
+                name,
+            } => write!(
+                f,
+                "module in slot {} doesn't export function `{}`",
+                slot, name
+            ),
+            Error::UnknownFunction { slot: None, name } => {
+                write!(f, "root doesn't expose function `{}`", name)
+            }
+            Error::Instantiation { slot, reason } => {
+                write!(f, "unable to load module into slot {}: {}", slot, reason)
+            }
+            Error::OutOfGas { slot } => write!(f, "module in slot {} ran out of gas", slot),
+            Error::CallDepthExceeded { limit } => {
+                write!(f, "call depth limit of {} exceeded", limit)
+            }
+            Error::ExtraPages { pages } => write!(
+                f,
+                "unable to grow the root's memory by {} extra pages",
+                pages
+            ),
+            Error::Aborted { message } => write!(f, "aborted: {}", message),
+            Error::Wat {
+                location: Some((line, column)),
+                message,
+            } => write!(f, "invalid WAT at {}:{}: {}", line, column, message),
+            Error::Wat {
+                location: None,
+                message,
+            } => write!(f, "invalid WAT: {}", message),
+            Error::Diverged { index } => {
+                write!(f, "replay diverged from recording at host call {}", index)
+            }
+            Error::ViewViolation { function } => {
+                write!(f, "{} is not permitted in view mode", function)
+            }
+            Error::UnknownExport { name } => {
+                write!(f, "no exported function `{}` of type () -> i32", name)
+            }
+            Error::Trap { message, backtrace } => {
+                write!(f, "{}", message)?;
+
+                if !backtrace.frames.is_empty() {
+                    write!(f, "\n{}", backtrace)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl HostError for Error {}
@@ -1,3 +1,7 @@
+use crate::error::Error;
+
 pub trait Execute {
-    fn execute(&mut self) -> [u8; 32];
+    /// Runs the module's `main` function, returning the post-state root. Fails
+    /// with `Error::Trap` if `main` traps.
+    fn execute(&mut self) -> Result<[u8; 32], Error>;
 }
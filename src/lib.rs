@@ -1,6 +1,21 @@
+mod backtrace;
 mod buffer;
 mod env;
+mod error;
 mod execute;
+mod memory;
+#[cfg(feature = "wat")]
+mod wat;
 
-pub use env::root::RootRuntime;
+pub use backtrace::{Backtrace, BacktraceFrame};
+pub use env::child::{capabilities, GasSchedule};
+pub use env::debugger::{Action, Breakpoint, CallFrame, Console, Debugger, Session, Stop};
+pub use env::registry::{HostBuffer, HostContext};
+pub use env::replay::{Divergence, RecordedCall, Recording};
+pub use env::root::{Execution, Invocation, RootRuntime, RootRuntimeBuilder};
+pub use env::trace::{Access, Event, Frame, JsonTracer, MemoryRange, Tracer};
+pub use error::Error;
 pub use execute::Execute;
+pub use memory::{MemoryConfig, MemoryUsage};
+#[cfg(feature = "wat")]
+pub use wat::wat2wasm;
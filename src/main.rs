@@ -0,0 +1,365 @@
+//! Executes an execution environment from files, for testing EEs without a
+//! Rust harness.
+
+use ewasm::{Console, Execute, MemoryConfig, RootRuntime};
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+const USAGE: &str = "\
+usage: ewasm [options] <ee.wasm | ee.wat>
+
+.wat files are only accepted if ewasm is built with the wat feature.
+
+options:
+  --data <file>            block data, as hex or binary
+  --pre-root <hex>         pre-state root, 32 bytes of hex (default zero)
+  --gas-limit <n>          gas budget for metered children (default unlimited)
+  --extra-pages <n>        pages added to the root's memory before execution
+  --max-pages <n>          memory limit of every module, in pages
+  --call-depth-limit <n>   maximum depth of nested cross-module calls
+  --debug                  serve the debug namespace
+  --debugger               step through execution with commands from stdin
+  --view                   trap on host functions with side effects
+  --profile <file>         write a folded instruction profile to <file>
+  --record <file>          write a recording of the execution to <file>
+  --replay <file>          replay a recording and report any divergence
+  -h, --help               show this message";
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    code: PathBuf,
+    data: Option<PathBuf>,
+    pre_root: [u8; 32],
+    gas_limit: Option<u64>,
+    memory: MemoryConfig,
+    call_depth_limit: Option<usize>,
+    debug: bool,
+    debugger: bool,
+    view: bool,
+    profile: Option<PathBuf>,
+    record: Option<PathBuf>,
+}
+
+enum Command {
+    Run(Options),
+    Replay(PathBuf),
+    Help,
+}
+
+fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut code = None;
+    let mut replay = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", arg))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--data" => options.data = Some(value()?.into()),
+            "--pre-root" => {
+                let bytes = decode_hex(&value()?).ok_or("--pre-root must be hex")?;
+                if bytes.len() != 32 {
+                    return Err("--pre-root must be 32 bytes".to_string());
+                }
+
+                options.pre_root.copy_from_slice(&bytes);
+            }
+            "--gas-limit" => options.gas_limit = Some(number(&arg, &value()?)?),
+            "--extra-pages" => options.memory.extra_pages = number(&arg, &value()?)?,
+            "--max-pages" => options.memory.max_pages = Some(number(&arg, &value()?)?),
+            "--call-depth-limit" => options.call_depth_limit = Some(number(&arg, &value()?)?),
+            "--debug" => options.debug = true,
+            "--debugger" => options.debugger = true,
+            "--view" => options.view = true,
+            "--profile" => options.profile = Some(value()?.into()),
+            "--record" => options.record = Some(value()?.into()),
+            "--replay" => replay = Some(value()?.into()),
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            path if code.is_none() => code = Some(path.into()),
+            path => return Err(format!("unexpected argument {}", path)),
+        }
+    }
+
+    match (code, replay) {
+        (Some(code), None) => {
+            options.code = code;
+            Ok(Command::Run(options))
+        }
+        (None, Some(recording)) => Ok(Command::Replay(recording)),
+        (Some(_), Some(_)) => Err("--replay doesn't take an EE".to_string()),
+        (None, None) => Err("missing EE".to_string()),
+    }
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} must be a number", option))
+}
+
+/// Decodes hex, with an optional `0x` prefix. Whitespace is ignored, so that
+/// files may wrap long lines.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+
+    if digits.len() % 2 == 1 {
+        return None;
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_code(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if path.extension().and_then(|extension| extension.to_str()) == Some("wat") {
+        compile_wat(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    } else {
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "wat")]
+fn compile_wat(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let wat = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+    ewasm::wat2wasm(wat).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "wat"))]
+fn compile_wat(_: &[u8]) -> Result<Vec<u8>, String> {
+    Err("ewasm was built without the wat feature".to_string())
+}
+
+/// Reads block data, which is taken as hex if the whole file decodes as hex.
+fn read_data(path: &Path) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let hex = std::str::from_utf8(&bytes).ok().and_then(decode_hex);
+    Ok(hex.unwrap_or(bytes))
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let code = read_code(&options.code)?;
+    let data = match &options.data {
+        Some(path) => read_data(path)?,
+        None => Vec::new(),
+    };
+
+    let logs = RefCell::new(Vec::new());
+
+    let mut builder = RootRuntime::builder(&code, &data, options.pre_root)
+        .memory(options.memory)
+        .debug(options.debug)
+        .view(options.view)
+        .profile(options.profile.is_some())
+        .backtraces(true)
+        .record(options.record.is_some())
+        .logger(|message| logs.borrow_mut().push(message.to_string()));
+
+    if let Some(gas) = options.gas_limit {
+        builder = builder.gas_limit(gas);
+    }
+
+    if let Some(depth) = options.call_depth_limit {
+        builder = builder.call_depth_limit(depth);
+    }
+
+    if options.debugger {
+        let stdin = io::stdin();
+        builder = builder.debugger(Console::new(BufReader::new(stdin), io::stdout()));
+    }
+
+    let mut runtime = builder.build().map_err(|error| error.to_string())?;
+
+    let start = Instant::now();
+    let result = runtime.execute();
+    let elapsed = start.elapsed();
+
+    for log in logs.borrow().iter() {
+        println!("log: {}", log);
+    }
+
+    let succeeded = match &result {
+        Ok(post_root) => {
+            println!("post-state root: {}", encode_hex(post_root));
+            true
+        }
+        Err(error) => {
+            println!("trap: {}", error);
+            false
+        }
+    };
+
+    let usage = runtime.memory_usage();
+    println!("time: {:?}", elapsed);
+    println!(
+        "memory: {} pages{}",
+        usage.pages,
+        usage
+            .max_pages
+            .map_or_else(String::new, |max| format!(" of {}", max))
+    );
+
+    if let Some(gas) = options.gas_limit {
+        println!("gas allotted: {} of {}", gas - runtime.gas_left(), gas);
+    }
+
+    if let Some(path) = &options.profile {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        runtime
+            .write_profile(BufWriter::new(file))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    if let Some(path) = &options.record {
+        let recording = runtime
+            .recording()
+            .ok_or_else(|| format!("{}: no execution was recorded", path.display()))?;
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        recording
+            .write(BufWriter::new(file))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    Ok(succeeded)
+}
+
+fn replay(path: &Path) -> Result<bool, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let recording = ewasm::Recording::read(BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    match recording.replay() {
+        Some(divergence) => {
+            println!("{}", divergence);
+            Ok(false)
+        }
+        None => {
+            println!("replay matches recording");
+            Ok(true)
+        }
+    }
+}
+
+fn main() {
+    let result = match parse(env::args().skip(1)) {
+        Ok(Command::Run(options)) => run(options),
+        Ok(Command::Replay(path)) => replay(&path),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("error: {}", message);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn options() {
+        let root = "0x".to_string() + &"ab".repeat(32);
+        let command = parse(args(&[
+            "--gas-limit",
+            "100",
+            "ee.wat",
+            "--max-pages",
+            "4",
+            "--pre-root",
+            &root,
+            "--debug",
+        ]));
+
+        match command {
+            Ok(Command::Run(options)) => assert_eq!(
+                options,
+                Options {
+                    code: "ee.wat".into(),
+                    pre_root: [0xab; 32],
+                    gas_limit: Some(100),
+                    memory: MemoryConfig {
+                        extra_pages: 0,
+                        max_pages: Some(4),
+                    },
+                    debug: true,
+                    ..Default::default()
+                }
+            ),
+            _ => panic!("expected options"),
+        }
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(parse(args(&[])).is_err());
+        assert!(parse(args(&["ee.wasm", "other.wasm"])).is_err());
+        assert!(parse(args(&["ee.wasm", "--gas-limit"])).is_err());
+        assert!(parse(args(&["ee.wasm", "--gas-limit", "lots"])).is_err());
+        assert!(parse(args(&["ee.wasm", "--pre-root", "abcd"])).is_err());
+        assert!(parse(args(&["ee.wasm", "--frobnicate"])).is_err());
+    }
+
+    #[test]
+    fn invalid_root() {
+        let path = env::temp_dir().join("ewasm-invalid-root.wasm");
+        fs::write(&path, b"not wasm").unwrap();
+
+        let result = run(Options {
+            code: path.clone(),
+            ..Default::default()
+        });
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(message) => assert!(message.starts_with("unable to load the root")),
+            Ok(_) => panic!("invalid root executed"),
+        }
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode_hex("0x0aff\n10 "), Some(vec![0x0a, 0xff, 0x10]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(encode_hex(&[0x0a, 0xff]), "0aff");
+    }
+}
@@ -0,0 +1,52 @@
+use typed_builder::TypedBuilder;
+
+/// Memory settings applied to every instance created by a `RootRuntime`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, TypedBuilder)]
+pub struct MemoryConfig {
+    /// Pages added to the root's memory before `main` is invoked.
+    #[builder(default)]
+    pub extra_pages: u32,
+
+    /// Hard limit on the memory of the root and of every child, in pages.
+    /// Modules declaring more initial memory fail to load, and `memory.grow`
+    /// beyond the limit returns -1.
+    #[builder(default)]
+    pub max_pages: Option<u32>,
+}
+
+impl MemoryConfig {
+    /// Returns the tighter of the configured limit and `pages`.
+    pub(crate) fn clamp(&self, pages: Option<u32>) -> Option<u32> {
+        match (self.max_pages, pages) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// The root's memory usage after execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Size of the root's memory, in pages.
+    pub pages: u32,
+
+    /// The limit the memory was subject to, if any.
+    pub max_pages: Option<u32>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clamp() {
+        let unlimited = MemoryConfig::default();
+        let limited = MemoryConfig::builder().max_pages(Some(4)).build();
+
+        assert_eq!(unlimited.clamp(None), None);
+        assert_eq!(unlimited.clamp(Some(8)), Some(8));
+        assert_eq!(limited.clamp(None), Some(4));
+        assert_eq!(limited.clamp(Some(2)), Some(2));
+        assert_eq!(limited.clamp(Some(8)), Some(4));
+    }
+}
@@ -0,0 +1,72 @@
+//! Compilation of the WebAssembly text format, for prototyping EEs without a
+//! separate build step.
+
+use crate::error::Error;
+
+/// Compiles WebAssembly text to a binary module. Errors point at the line and
+/// column of the WAT that failed to compile, when the compiler reports one.
+pub fn wat2wasm(wat: &str) -> Result<Vec<u8>, Error> {
+    wabt::wat2wasm(wat).map_err(|error| {
+        let error = error.to_string();
+
+        Error::Wat {
+            location: location(&error),
+            message: summary(&error),
+        }
+    })
+}
+
+/// Finds the first `:line:column` in a compiler error.
+fn location(error: &str) -> Option<(u32, u32)> {
+    let number = |s: &str| -> Option<(u32, usize)> {
+        let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+        Some((s[..digits].parse().ok()?, digits))
+    };
+
+    error.match_indices(':').find_map(|(colon, _)| {
+        let rest = &error[colon + 1..];
+        let (line, digits) = number(rest)?;
+
+        let rest = rest[digits..].strip_prefix(':')?;
+        let (column, _) = number(rest)?;
+
+        Some((line, column))
+    })
+}
+
+/// Returns the first line of a compiler error, without its location.
+fn summary(error: &str) -> String {
+    let line = error.lines().next().unwrap_or_default();
+
+    match line.find("error: ") {
+        Some(start) => line[start + "error: ".len()..].to_string(),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wabt_errors() {
+        let error = "test.wast:3:7: error: unexpected token \"i33.const\"\n  (i33.const 0)\n";
+
+        assert_eq!(location(error), Some((3, 7)));
+        assert_eq!(summary(error), "unexpected token \"i33.const\"");
+    }
+
+    #[test]
+    fn annotated_errors() {
+        let error =
+            "unknown operator or unexpected token\n     --> <anon>:12:5\n   |\n12 | i33.const";
+
+        assert_eq!(location(error), Some((12, 5)));
+        assert_eq!(summary(error), "unknown operator or unexpected token");
+    }
+
+    #[test]
+    fn no_location() {
+        assert_eq!(location("module: bad thing: 12"), None);
+    }
+}
@@ -1,9 +1,13 @@
 mod utils;
 
-use ewasm::{Execute, RootRuntime};
+use ewasm::{
+    Access, Action, BacktraceFrame, Breakpoint, Console, Debugger, Error, Event, Execute,
+    GasSchedule, MemoryRange, RootRuntime, Session, Stop, Tracer,
+};
 use std::{cell::RefCell, rc::Rc};
 use utils::escape;
 use wabt::wat2wasm;
+use wasmi::{Signature, ValueType};
 
 fn compile_wat(child_code: &str) -> Vec<u8> {
     let child_asm = wat2wasm(child_code).unwrap();
@@ -149,7 +153,7 @@ fn call() {
     let code = compile_wat(child_code);
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    runtime.execute();
+    runtime.execute().unwrap();
 }
 
 #[test]
@@ -188,7 +192,1800 @@ fn print() {
         *result.borrow_mut() = b.to_string();
     });
 
-    let _ = runtime.execute();
+    runtime.execute().unwrap();
 
     assert_eq!(*result.borrow(), "hello world");
 }
+
+fn compile_journal_wat(child_code: &str) -> Vec<u8> {
+    let child_asm = wat2wasm(child_code).unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_expose" (func $expose (param i32) (param i32)))
+            (import "env" "eth2_bufferSet" (func $buffer_set (param i32) (param i32) (param i32)))
+            (import "env" "eth2_bufferGet" (func $buffer_get (param i32) (param i32) (param i32) (result i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 0) "write")
+            (data (i32.const 10) "main")
+            (data (i32.const 300) "{}")
+
+            (; Writes buffer[0, 0x00..] = 1 ;)
+            (func $write (export "write") (result i32)
+                (i32.store (i32.const 32) (i32.const 1))
+                (call $buffer_set (i32.const 0) (i32.const 64) (i32.const 32))
+                (i32.const 0))
+
+            (func $main (export "main")
+                (call $expose (i32.const 0) (i32.const 5))
+                (call $load (i32.const 0) (i32.const 300) (i32.const {}))
+
+                (; Store the child's return code at mem[200] ;)
+                (i32.store
+                    (i32.const 200)
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 0)   (; Argument Offset ;)
+                        (i32.const 0)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (; Store whether the write survived at mem[204] (0 if it did) ;)
+                (i32.store
+                    (i32.const 204)
+                    (call $buffer_get (i32.const 0) (i32.const 64) (i32.const 96)))
+
+                (call $save_post_root (i32.const 200))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+    ))
+    .unwrap()
+}
+
+fn journal_child(tail: &str) -> String {
+    format!(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_call"
+                (func
+                    $eth2_call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "write")
+            (func $main (export "main") (result i32)
+                (drop
+                    (call
+                        $eth2_call
+                        (i32.const 0)
+                        (i32.const 5)
+                        (i32.const 0)
+                        (i32.const 0)
+                        (i32.const 0)
+                        (i32.const 0)))
+                {}
+            )
+        )
+        "#,
+        tail
+    )
+}
+
+fn journal_result(retcode: i32, get: i32) -> [u8; 32] {
+    let mut ret = [0u8; 32];
+    ret[..4].copy_from_slice(&retcode.to_le_bytes());
+    ret[4..8].copy_from_slice(&get.to_le_bytes());
+    ret
+}
+
+#[test]
+fn call_commits_on_success() {
+    let code = compile_journal_wat(&journal_child("(i32.const 3)"));
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    assert_eq!(post_root, journal_result(3, 0));
+}
+
+#[test]
+fn call_reverts_on_failure_code() {
+    let code = compile_journal_wat(&journal_child("(i32.const -5)"));
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    assert_eq!(post_root, journal_result(-5, 1));
+}
+
+#[test]
+fn call_reverts_on_trap() {
+    let code = compile_journal_wat(&journal_child("(unreachable)"));
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    assert_eq!(post_root, journal_result(i32::MIN, 1));
+}
+
+#[test]
+fn shared_memory() {
+    let child_asm = wat2wasm(
+        r#"
+        (module
+            (import "env" "eth2_argument" (func $argument (param i32) (param i32) (result i32)))
+            (import "env" "eth2_sharedMemoryCopy" (func $shared_copy (param i32) (param i32) (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32)
+                (; Read the location of the input in shared memory ;)
+                (drop (call $argument (i32.const 0) (i32.const 4)))
+
+                (; Copy only the input out of shared memory ;)
+                (call $shared_copy (i32.const 8) (i32.load (i32.const 0)) (i32.const 4))
+                (i32.load (i32.const 8))
+            )
+        )
+        "#,
+    )
+    .unwrap();
+
+    let code = wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "eth2_sharedMemoryWrite" (func $shared_write (param i32) (param i32) (param i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 10) "main")
+            (data (i32.const 100) "{}")
+
+            (func $main (export "main")
+                (; Write the input into shared memory at 5000 ;)
+                (i32.store (i32.const 20) (i32.const 77))
+                (call $shared_write (i32.const 5000) (i32.const 20) (i32.const 4))
+
+                (; Only the location is passed as the argument ;)
+                (i32.store (i32.const 14) (i32.const 5000))
+
+                (call $load (i32.const 0) (i32.const 100) (i32.const {}))
+
+                (i32.store
+                    (i32.const 1000)
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 14)  (; Argument Offset ;)
+                        (i32.const 4)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (call $save_post_root (i32.const 1000))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+    ))
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 77;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn call_typed() {
+    let child_asm = wat2wasm(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_callTyped"
+                (func
+                    $call_typed
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "double")
+            (func $add (export "add") (param $a i32) (param $b i64) (result i64)
+                (; Pack the sum as a single i64 argument ;)
+                (i32.store8 (i32.const 100) (i32.const 0x7e))
+                (i64.store
+                    (i32.const 101)
+                    (i64.add (i64.extend_s/i32 (get_local $a)) (get_local $b)))
+
+                (if
+                    (i32.ne
+                        (call
+                            $call_typed
+                            (i32.const 0)   (; Name Offset ;)
+                            (i32.const 6)   (; Name Length ;)
+                            (i32.const 100) (; Params Offset ;)
+                            (i32.const 1)   (; Params Count ;)
+                            (i32.const 200) (; Results Offset ;)
+                            (i32.const 1))  (; Results Count ;)
+                        (i32.const 1))
+                    (then (unreachable)))
+
+                (i64.load (i32.const 201))
+            )
+        )
+        "#,
+    )
+    .unwrap();
+
+    let code = wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_expose" (func $expose (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "eth2_callModuleTyped"
+                (func
+                    $call_typed
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 0) "double")
+            (data (i32.const 10) "add")
+            (data (i32.const 1000) "{}")
+
+            (func $double (export "double") (param $x i64) (result i64)
+                (i64.mul (get_local $x) (i64.const 2)))
+
+            (func $main (export "main")
+                (call $expose (i32.const 0) (i32.const 6))
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}))
+
+                (; add(5: i32, 7: i64) ;)
+                (i32.store8 (i32.const 100) (i32.const 0x7f))
+                (i64.store (i32.const 101) (i64.const 5))
+                (i32.store8 (i32.const 109) (i32.const 0x7e))
+                (i64.store (i32.const 110) (i64.const 7))
+
+                (if
+                    (i32.ne
+                        (call
+                            $call_typed
+                            (i32.const 0)   (; Slot ;)
+                            (i32.const 10)  (; Name Offset ;)
+                            (i32.const 3)   (; Name Length ;)
+                            (i32.const 100) (; Params Offset ;)
+                            (i32.const 2)   (; Params Count ;)
+                            (i32.const 200) (; Results Offset ;)
+                            (i32.const 1))  (; Results Count ;)
+                        (i32.const 1))
+                    (then (unreachable)))
+
+                (call $save_post_root (i32.const 200))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+    ))
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    // The result is packed as an i64 with the value (5 + 7) * 2 = 24
+    let mut expected = [0u8; 32];
+    expected[0] = 0x7e;
+    expected[1] = 24;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn call_typed_results_out_of_bounds() {
+    let child_asm = wat2wasm(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $one (export "one") (result i32) (i32.const 1))
+        )
+        "#,
+    )
+    .unwrap();
+
+    let code = wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import
+                "env"
+                "eth2_callModuleTyped"
+                (func
+                    $call_typed
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 10) "one")
+            (data (i32.const 1000) "{}")
+
+            (func $main (export "main")
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}))
+
+                (drop
+                    (call
+                        $call_typed
+                        (i32.const 0)     (; Slot ;)
+                        (i32.const 10)    (; Name Offset ;)
+                        (i32.const 3)     (; Name Length ;)
+                        (i32.const 0)     (; Params Offset ;)
+                        (i32.const 0)     (; Params Count ;)
+                        (i32.const 65536) (; Results Offset ;)
+                        (i32.const 1)))   (; Results Count ;)
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+    ))
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    // The caller traps, rather than seeing the callee as having trapped.
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(message, "wasm trap: MemoryAccessOutOfBounds"),
+        error => panic!("unexpected error {:?}", error),
+    }
+    assert!(runtime.last_trap().is_none());
+}
+
+fn compile_child_to_child_wat(allow: bool) -> Vec<u8> {
+    let library_asm = wat2wasm(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $get (export "get") (result i32) (i32.const 42))
+        )
+        "#,
+    )
+    .unwrap();
+
+    let user_asm = wat2wasm(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "get")
+            (func $main (export "main") (result i32)
+                (; Call into the library in slot 1 ;)
+                (call
+                    $call
+                    (i32.const 1)   (; Slot ;)
+                    (i32.const 0)   (; Name Offset ;)
+                    (i32.const 3)   (; Name Length ;)
+                    (i32.const 0)   (; Argument Offset ;)
+                    (i32.const 0)   (; Argument Length ;)
+                    (i32.const 0)   (; Return Offset ;)
+                    (i32.const 0)   (; Return Length ;)
+                )
+            )
+        )
+        "#,
+    )
+    .unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_allowCall" (func $allow (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 10) "main")
+            (data (i32.const 1000) "{}")
+            (data (i32.const 2000) "{}")
+
+            (func $main (export "main")
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}))
+                (call $load (i32.const 1) (i32.const 2000) (i32.const {}))
+
+                {}
+
+                (i32.store
+                    (i32.const 100)
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 0)   (; Argument Offset ;)
+                        (i32.const 0)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (call $save_post_root (i32.const 100))
+            )
+        )
+        "#,
+        escape(&user_asm),
+        escape(&library_asm),
+        user_asm.len(),
+        library_asm.len(),
+        if allow {
+            "(call $allow (i32.const 0) (i32.const 1))"
+        } else {
+            ""
+        },
+    ))
+    .unwrap()
+}
+
+#[test]
+fn child_to_child_call() {
+    let code = compile_child_to_child_wat(true);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 42;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn child_to_child_call_not_permitted() {
+    let code = compile_child_to_child_wat(false);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    // The unpermitted call traps, which the root observes as i32::MIN.
+    let mut expected = [0u8; 32];
+    expected[..4].copy_from_slice(&i32::MIN.to_le_bytes());
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn call_empty_slot() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "main")
+            (func $main (export "main")
+                (drop (call $call (i32.const 3) (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))))
+        )
+        "#,
+    )
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(message, "no module is loaded in slot 3"),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+fn load_module_occupied_slot() {
+    let child = wat2wasm(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32) (i32.const 0)))
+        "#,
+    )
+    .unwrap();
+
+    let code = wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{}")
+            (func $main (export "main")
+                (call $load (i32.const 0) (i32.const 0) (i32.const {}))
+                (call $load (i32.const 0) (i32.const 0) (i32.const {})))
+        )
+        "#,
+        escape(&child),
+        child.len(),
+        child.len(),
+    ))
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(
+            message,
+            "unable to load module into slot 0: the slot is already in use"
+        ),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+#[should_panic(expected = "modules with a start function aren't supported")]
+fn load_module_with_start() {
+    let code = compile_wat(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $init)
+            (start $init)
+            (func $main (export "main") (result i32) (i32.const 0)))
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+fn call_module_invalid_name() {
+    // (name offset, name length, message)
+    let cases = [
+        (65536, 4, "wasm trap: MemoryAccessOutOfBounds"),
+        (10, 2, "function name isn't valid UTF-8"),
+    ];
+
+    for import in &["eth2_callModule", "eth2_callModuleTyped"] {
+        for (offset, length, message) in cases.iter() {
+            let code = wat2wasm(format!(
+                r#"
+                (module
+                    (import
+                        "env"
+                        "{}"
+                        (func
+                            $call
+                            (param i32)
+                            (param i32)
+                            (param i32)
+                            (param i32)
+                            (param i32)
+                            (param i32)
+                            (param i32)
+                            (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 10) "\ff\fe")
+                    (func $main (export "main")
+                        (drop (call $call (i32.const 0) (i32.const {}) (i32.const {}) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))))
+                )
+                "#,
+                import, offset, length,
+            ))
+            .unwrap();
+
+            let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+            match runtime.execute().unwrap_err() {
+                Error::Trap {
+                    message: actual, ..
+                } => assert_eq!(actual, *message),
+                error => panic!("unexpected error {:?}", error),
+            }
+        }
+    }
+}
+
+/// Loads `child_code` into slot 0 and calls its function `name` with the
+/// `import` flavour of `eth2_callModule`. The post state root holds the
+/// return code.
+fn compile_call_module_wat(child_code: &str, import: &str, name: &str) -> Vec<u8> {
+    let child_asm = wat2wasm(child_code).unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "{}"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{}")
+            (data (i32.const 1000) "{}")
+            (func $main (export "main")
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}))
+                (i32.store
+                    (i32.const 300)
+                    (call $call (i32.const 0) (i32.const 0) (i32.const {}) (i32.const 0) (i32.const 0) (i32.const 200) (i32.const 1)))
+                (call $save_post_root (i32.const 300)))
+        )
+        "#,
+        import,
+        name,
+        escape(&child_asm),
+        child_asm.len(),
+        name.len(),
+    ))
+    .unwrap()
+}
+
+/// Executes `code` and checks that the call it makes traps with `message`.
+fn assert_call_trapped(code: &[u8], message: &str) {
+    let mut runtime = RootRuntime::new(code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    assert_eq!(post_root[..4], i32::MIN.to_le_bytes());
+    match runtime.last_trap() {
+        Some(Error::Trap {
+            message: actual, ..
+        }) => assert_eq!(actual, message),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+const EXPORTS_CHILD: &str = r#"
+    (module
+        (memory (export "memory") 1)
+        (func $nothing (export "nothing"))
+        (func $wide (export "wide") (result i64) (i64.const 1))
+    )
+"#;
+
+#[test]
+fn call_module_unknown_export() {
+    let code = compile_call_module_wat(EXPORTS_CHILD, "eth2_callModule", "missing");
+    assert_call_trapped(&code, "module in slot 0 doesn't export function `missing`");
+}
+
+#[test]
+fn call_module_typed_unknown_export() {
+    let code = compile_call_module_wat(EXPORTS_CHILD, "eth2_callModuleTyped", "missing");
+    assert_call_trapped(&code, "module in slot 0 doesn't export function `missing`");
+}
+
+#[test]
+fn call_module_non_i32_export() {
+    for name in &["nothing", "wide"] {
+        let code = compile_call_module_wat(EXPORTS_CHILD, "eth2_callModule", name);
+        assert_call_trapped(&code, "wasm trap: UnexpectedSignature");
+    }
+}
+
+#[test]
+fn call_root_not_exposed() {
+    // The child returns 1 if calling the root with `import` trapped.
+    let child = |import: &str| {
+        format!(
+            r#"
+            (module
+                (import
+                    "env"
+                    "{}"
+                    (func
+                        $call
+                        (param i32)
+                        (param i32)
+                        (param i32)
+                        (param i32)
+                        (param i32)
+                        (param i32)
+                        (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "main")
+                (func $main (export "main") (result i32)
+                    (i32.eq
+                        (call $call (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))
+                        (i32.const -2147483648)))
+            )
+            "#,
+            import
+        )
+    };
+
+    for import in &["eth2_call", "eth2_callTyped"] {
+        let code = compile_call_module_wat(&child(import), "eth2_callModule", "main");
+
+        let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+        let post_root = runtime.execute().unwrap();
+
+        assert_eq!(post_root[..4], 1i32.to_le_bytes());
+        match runtime.last_trap() {
+            Some(Error::Trap { message, .. }) => {
+                assert_eq!(message, "root doesn't expose function `main`")
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+}
+
+fn compile_linked_wat(child_code: &str) -> Vec<u8> {
+    let child_asm = wat2wasm(child_code).unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (import "env" "eth2_expose" (func $expose (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 0) "double")
+            (data (i32.const 10) "main")
+            (data (i32.const 1000) "{}")
+
+            (func $double (export "double") (param $x i64) (result i64)
+                (i64.mul (get_local $x) (i64.const 2)))
+
+            (func $main (export "main")
+                (call $expose (i32.const 0) (i32.const 6))
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}))
+
+                (i32.store
+                    (i32.const 100)
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 0)   (; Argument Offset ;)
+                        (i32.const 0)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (call $save_post_root (i32.const 100))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+    ))
+    .unwrap()
+}
+
+#[test]
+fn linked_root_function() {
+    let code = compile_linked_wat(
+        r#"
+        (module
+            (import "root" "double" (func $double (param i64) (result i64)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32)
+                (i32.wrap/i64 (call $double (i64.const 21)))
+            )
+        )
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 42;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+#[should_panic(expected = "root doesn't expose function with name triple")]
+fn linked_root_function_not_exposed() {
+    let code = compile_linked_wat(
+        r#"
+        (module
+            (import "root" "triple" (func $triple (param i64) (result i64)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32) (i32.const 0))
+        )
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "was imported as")]
+fn linked_root_function_signature_mismatch() {
+    let code = compile_linked_wat(
+        r#"
+        (module
+            (import "root" "double" (func $double (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32) (i32.const 0))
+        )
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+const BUFFER_CHILD: &str = r#"
+    (module
+        (import "env" "eth2_bufferSet" (func $buffer_set (param i32) (param i32) (param i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 32) "\2a")
+        (func $main (export "main") (result i32)
+            (call $buffer_set (i32.const 0) (i32.const 0) (i32.const 32))
+            (i32.const 0)
+        )
+    )
+"#;
+
+/// Loads `child_code` with `capabilities` and calls its `main`. The post state
+/// root is the value stored under the zero key in buffer frame 0, with the
+/// return code of `main` in the last four bytes.
+fn compile_capabilities_wat(child_code: &str, capabilities: u32) -> Vec<u8> {
+    let child_asm = wat2wasm(child_code).unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_loadModuleWithCapabilities"
+                (func $load (param i32) (param i32) (param i32) (param i32)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "eth2_bufferGet" (func $buffer_get (param i32) (param i32) (param i32) (result i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 10) "main")
+            (data (i32.const 1000) "{}")
+
+            (func $main (export "main")
+                (local $retcode i32)
+                (call $load (i32.const 0) (i32.const 1000) (i32.const {}) (i32.const {}))
+
+                (set_local
+                    $retcode
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 0)   (; Argument Offset ;)
+                        (i32.const 0)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (drop (call $buffer_get (i32.const 0) (i32.const 200) (i32.const 100)))
+                (i32.store (i32.const 128) (get_local $retcode))
+                (call $save_post_root (i32.const 100))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        child_asm.len(),
+        capabilities,
+    ))
+    .unwrap()
+}
+
+#[test]
+fn buffer_capability() {
+    // BUFFER
+    let code = compile_capabilities_wat(BUFFER_CHILD, 0x10);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 42;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn capabilities_out_of_bounds() {
+    let block_data_copy = r#"(import "env" "eth2_blockDataCopy" (func $f (param i32 i32 i32)))"#;
+    let pre_root = r#"(import "env" "eth2_loadPreStateRoot" (func $f (param i32)))"#;
+    let buffer_get =
+        r#"(import "env" "eth2_bufferGet" (func $f (param i32 i32 i32) (result i32)))"#;
+    let buffer_set = r#"(import "env" "eth2_bufferSet" (func $f (param i32 i32 i32)))"#;
+
+    // (capabilities, import, call), with two bytes of block data.
+    let cases = [
+        // v1 takes the end of the range, so the range is backwards.
+        (
+            0x20,
+            block_data_copy,
+            "(call $f (i32.const 0) (i32.const 2) (i32.const 1))",
+        ),
+        (
+            0x20,
+            block_data_copy,
+            "(call $f (i32.const 0) (i32.const 0) (i32.const 3))",
+        ),
+        (
+            0x20,
+            block_data_copy,
+            "(call $f (i32.const 65535) (i32.const 0) (i32.const 2))",
+        ),
+        (0x20, pre_root, "(call $f (i32.const 65535))"),
+        (
+            0x10,
+            buffer_get,
+            "(drop (call $f (i32.const 0) (i32.const 65535) (i32.const 0)))",
+        ),
+        (
+            0x10,
+            buffer_set,
+            "(call $f (i32.const 0) (i32.const 0) (i32.const 65535))",
+        ),
+    ];
+
+    for (capabilities, import, call) in cases.iter() {
+        let child = format!(
+            r#"
+            (module
+                {}
+                (memory (export "memory") 1)
+                (func $main (export "main") (result i32)
+                    {}
+                    (i32.const 0)))
+            "#,
+            import, call
+        );
+
+        let code = compile_capabilities_wat(&child, *capabilities);
+        let mut runtime = RootRuntime::new(&code, &[1, 2], [0u8; 32]);
+        let post_root = runtime.execute().unwrap();
+
+        assert_eq!(post_root[28..], i32::MIN.to_le_bytes(), "{}", call);
+        match runtime.last_trap() {
+            Some(Error::Trap { message, .. }) => {
+                assert_eq!(message, "wasm trap: MemoryAccessOutOfBounds")
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "module isn't permitted to import eth2_bufferSet")]
+fn missing_capability() {
+    // CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY
+    let code = compile_capabilities_wat(BUFFER_CHILD, 0x0f);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "unknown capabilities 0xf0")]
+fn unknown_capability() {
+    let code = compile_capabilities_wat(BUFFER_CHILD, 0xf0);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+fn view_mode_buffer_write() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+            (import "env" "eth2_bufferCount" (func $buffer_count (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 10) "main")
+            (func $query (export "query") (result i32)
+                (drop (call $call (i32.const 0) (i32.const 10) (i32.const 4) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+                (call $buffer_count (i32.const 0))))
+        "#,
+    )
+    .unwrap();
+    let child = wat2wasm(BUFFER_CHILD).unwrap();
+
+    let runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .view(true)
+        .build()
+        .unwrap();
+
+    // BUFFER
+    runtime.load_module(0, &child, 0x10).unwrap();
+
+    // The child's write aborts the whole invocation, rather than being
+    // reported to the root as a trap.
+    match runtime.invoke("query", &[], 0) {
+        Err(Error::Trap { message, .. }) => {
+            assert_eq!(message, "eth2_bufferSet is not permitted in view mode")
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+const BLOCK_DATA_CHILD: &str = r#"
+    (module
+        (import "env" "eth2_loadPreStateRoot" (func $load_pre_root (param i32)))
+        (import "env" "eth2_blockDataSize" (func $block_data_size (result i32)))
+        (import "env" "eth2_blockDataCopy" (func $block_data_copy (param i32) (param i32) (param i32)))
+        (memory (export "memory") 1)
+        (func $main (export "main") (result i32)
+            (call $load_pre_root (i32.const 0))
+            (call $block_data_copy (i32.const 32) (i32.const 0) (call $block_data_size))
+            (i32.add (i32.load8_u (i32.const 0)) (i32.load8_u (i32.const 33)))
+        )
+    )
+"#;
+
+#[test]
+fn block_data_capability() {
+    // BLOCK_DATA
+    let code = compile_capabilities_wat(BLOCK_DATA_CHILD, 0x20);
+
+    let mut pre_root = [0u8; 32];
+    pre_root[0] = 40;
+
+    let mut runtime = RootRuntime::new(&code, &[1, 2], pre_root);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[28] = 42;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+#[should_panic(expected = "module isn't permitted to import eth2_loadPreStateRoot")]
+fn block_data_not_granted_by_default() {
+    // CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY
+    let code = compile_capabilities_wat(BLOCK_DATA_CHILD, 0x0f);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+/// Loads `child_code` with the default capabilities and the given limits,
+/// then calls its `main`. The post state root holds the return code of
+/// `main`.
+fn compile_limits_wat(child_code: &str, modules: u32, max_pages: u32, gas: u64) -> Vec<u8> {
+    let child_asm = wat2wasm(child_code).unwrap();
+
+    wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_limitModules" (func $limit (param i32)))
+            (import
+                "env"
+                "eth2_loadModuleWithLimits"
+                (func $load (param i32) (param i32) (param i32) (param i32) (param i32) (param i64)))
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import
+                "env"
+                "eth2_callModule"
+                (func
+                    $call
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (param i32)
+                    (result i32)))
+
+            (memory (export "memory") 1)
+            (data (i32.const 10) "main")
+            (data (i32.const 1000) "{}")
+
+            (func $main (export "main")
+                (call $limit (i32.const {}))
+                (call
+                    $load
+                    (i32.const 0)
+                    (i32.const 1000)
+                    (i32.const {})
+                    (i32.const 0x0f)
+                    (i32.const {})
+                    (i64.const {}))
+
+                (i32.store
+                    (i32.const 100)
+                    (call
+                        $call
+                        (i32.const 0)   (; Slot ;)
+                        (i32.const 10)  (; Name Offset ;)
+                        (i32.const 4)   (; Name Length ;)
+                        (i32.const 0)   (; Argument Offset ;)
+                        (i32.const 0)   (; Argument Length ;)
+                        (i32.const 0)   (; Return Offset ;)
+                        (i32.const 0)   (; Return Length ;)
+                    ))
+
+                (call $save_post_root (i32.const 100))
+            )
+        )
+        "#,
+        escape(&child_asm),
+        modules,
+        child_asm.len(),
+        max_pages,
+        gas,
+    ))
+    .unwrap()
+}
+
+const LOOP_CHILD: &str = r#"
+    (module
+        (memory (export "memory") 1)
+        (func $main (export "main") (result i32)
+            (local $i i32)
+            (loop $continue
+                (set_local $i (i32.add (get_local $i) (i32.const 1)))
+                (br_if $continue (i32.lt_u (get_local $i) (i32.const 10)))
+            )
+            (get_local $i)
+        )
+    )
+"#;
+
+#[test]
+fn gas_allotment() {
+    let code = compile_limits_wat(LOOP_CHILD, 1, 1, 1000);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .gas_limit(5000)
+        .build()
+        .unwrap();
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 10;
+    assert_eq!(post_root, expected);
+    assert_eq!(runtime.gas_left(), 4000);
+}
+
+#[test]
+fn gas_exhausted() {
+    let code = compile_limits_wat(LOOP_CHILD, 1, 1, 20);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(message, "module in slot 0 ran out of gas"),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+#[should_panic(expected = "1000 gas requested, but only 999 is left")]
+fn gas_exceeds_budget() {
+    let code = compile_limits_wat(LOOP_CHILD, 1, 1, 1000);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .gas_limit(999)
+        .build()
+        .unwrap();
+    runtime.execute().unwrap();
+}
+
+#[test]
+fn memory_limit_caps_growth() {
+    let code = compile_limits_wat(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32)
+                (i32.add (grow_memory (i32.const 2)) (i32.const 10))
+            )
+        )
+        "#,
+        1,
+        2,
+        1000,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 9;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+#[should_panic(expected = "module requests 2 pages of memory, but is limited to 1")]
+fn memory_limit_exceeded() {
+    let code = compile_limits_wat(
+        r#"
+        (module
+            (memory (export "memory") 2)
+            (func $main (export "main") (result i32) (i32.const 0))
+        )
+        "#,
+        1,
+        1,
+        1000,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "no more than 0 modules can be loaded")]
+fn module_limit_reached() {
+    let code = compile_limits_wat(LOOP_CHILD, 0, 1, 1000);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+#[test]
+fn call_depth_limit() {
+    let code = compile_limits_wat(LOOP_CHILD, 1, 1, 1000);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .call_depth_limit(0)
+        .build()
+        .unwrap();
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(message, "call depth limit of 0 exceeded"),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+fn gas_schedule() {
+    let code = compile_limits_wat(LOOP_CHILD, 1, 1, 1000);
+
+    let schedule = GasSchedule {
+        instruction: 100,
+        memory_grow: 0,
+    };
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .gas_schedule(schedule)
+        .build()
+        .unwrap();
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { message, .. } => assert_eq!(message, "module in slot 0 ran out of gas"),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+#[should_panic(expected = "module isn't permitted to import gas")]
+fn gas_import_requires_metering() {
+    let child = r#"
+        (module
+            (import "env" "gas" (func $gas (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32)
+                (call $gas (i32.const 1))
+                (i32.const 0)
+            )
+        )
+    "#;
+
+    // CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY
+    let code = compile_capabilities_wat(child, 0x0f);
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime.execute().unwrap();
+}
+
+const EXTENSION_CHILD: &str = r#"
+    (module
+        (import "env" "whoami" (func $whoami (result i32)))
+        (memory (export "memory") 1)
+        (func $main (export "main") (result i32)
+            (call $whoami)
+        )
+    )
+"#;
+
+fn whoami_runtime(code: &[u8]) -> RootRuntime<'static> {
+    let signature = Signature::new(&[][..], Some(ValueType::I32));
+
+    RootRuntime::builder(code, &[], [0u8; 32])
+        .host_function("whoami", signature, |context, _| {
+            let slot = context.caller().expect("called from a child");
+            Ok(Some((slot + 42).into()))
+        })
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn registered_host_function() {
+    // EXTENSIONS
+    let code = compile_capabilities_wat(EXTENSION_CHILD, 0x40);
+
+    let mut runtime = whoami_runtime(&code);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[28] = 42;
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+#[should_panic(expected = "module isn't permitted to import whoami")]
+fn registered_host_function_not_granted() {
+    // CALL_ROOT | CALL_MODULE | LOGGING | SHARED_MEMORY
+    let code = compile_capabilities_wat(EXTENSION_CHILD, 0x0f);
+
+    let mut runtime = whoami_runtime(&code);
+    runtime.execute().unwrap();
+}
+
+struct Collector(Rc<RefCell<Vec<Event>>>);
+
+impl Tracer for Collector {
+    fn event(&mut self, event: &Event) {
+        self.0.borrow_mut().push(event.clone());
+    }
+}
+
+#[test]
+fn tracer() {
+    let child_code = r#"
+    (module
+        (import "env" "eth2_return" (func $return (param i32) (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\e1\10\00\00")
+        (func $main (export "main") (result i32)
+            (drop (call $return (i32.const 0) (i32.const 4)))
+            (i32.const 0)))
+    "#;
+
+    let code = compile_wat(child_code);
+    let events = Rc::new(RefCell::new(Vec::new()));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .tracer(Collector(events.clone()))
+        .build()
+        .unwrap();
+    runtime.execute().unwrap();
+
+    let summary: Vec<String> = events
+        .borrow()
+        .iter()
+        .map(|event| match event {
+            Event::HostCall { caller, name, .. } => format!("host {:?} {}", caller, name),
+            Event::CallEnter { callee, name, .. } => format!("enter {:?} {}", callee, name),
+            Event::CallExit { callee, name, .. } => format!("exit {:?} {}", callee, name),
+            Event::ModuleLoad { slot, .. } => format!("load {}", slot),
+        })
+        .collect();
+
+    assert_eq!(
+        summary,
+        vec![
+            "host None eth2_expose",
+            "load 0",
+            "host None eth2_loadModule",
+            "enter Some(0) main",
+            "host Some(0) eth2_return",
+            "exit Some(0) main",
+            "host None eth2_callModule",
+        ]
+    );
+
+    let events = events.borrow();
+
+    match &events[3] {
+        Event::CallEnter { frame, .. } => {
+            assert_eq!(frame.argument_offset, 14);
+            assert_eq!(frame.return_offset, 18);
+            assert_eq!(frame.return_length, 4);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    match &events[4] {
+        Event::HostCall { memory, result, .. } => {
+            assert_eq!(
+                memory,
+                &vec![MemoryRange {
+                    access: Access::Read,
+                    offset: 0,
+                    length: 4,
+                }]
+            );
+            assert_eq!(result, &Ok(Some(4.into())));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    match &events[5] {
+        Event::CallExit { result, .. } => assert_eq!(result, &Ok(Some(0.into()))),
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn profile() {
+    let child_code = r#"
+    (module
+        (import "env" "eth2_return" (func $return (param i32) (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\e1\10\00\00")
+        (func $helper (result i32)
+            (drop (call $return (i32.const 0) (i32.const 4)))
+            (return (i32.const 0)))
+        (func $main (export "main") (result i32)
+            (call $helper)))
+    "#;
+
+    let code = compile_wat(child_code);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .profile(true)
+        .build()
+        .unwrap();
+    runtime.execute().unwrap();
+
+    let mut folded = Vec::new();
+    runtime.write_profile(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+
+    let stacks: Vec<_> = folded
+        .lines()
+        .map(|line| {
+            let (stack, count) = line.split_at(line.rfind(' ').unwrap());
+            assert!(count.trim().parse::<u64>().unwrap() > 0);
+            stack
+        })
+        .collect();
+
+    assert_eq!(
+        stacks,
+        vec![
+            "root:main",
+            "root:main;child0:main",
+            "root:main;child0:main;child0:helper",
+        ]
+    );
+}
+
+#[test]
+fn backtrace() {
+    let child_code = r#"
+    (module
+        (memory (export "memory") 1)
+        (func $helper (export "helper")
+            (unreachable))
+        (func $main (export "main") (result i32)
+            (call $helper)
+            (i32.const 0)))
+    "#;
+
+    let code = compile_wat(child_code);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .backtraces(true)
+        .build()
+        .unwrap();
+
+    // The child traps, so the root finds no return value and traps as well.
+    let error = runtime.execute().unwrap_err();
+
+    let root_main = BacktraceFrame {
+        module: None,
+        index: 6,
+        name: "main".to_string(),
+    };
+
+    match error {
+        Error::Trap { backtrace, .. } => assert_eq!(backtrace.frames, vec![root_main.clone()]),
+        error => panic!("unexpected error {:?}", error),
+    }
+
+    match runtime.last_trap() {
+        Some(Error::Trap { message, backtrace }) => {
+            assert_eq!(message, "wasm trap: Unreachable");
+            assert_eq!(
+                backtrace.frames,
+                vec![
+                    BacktraceFrame {
+                        module: Some(0),
+                        index: 0,
+                        name: "helper".to_string(),
+                    },
+                    BacktraceFrame {
+                        module: Some(0),
+                        index: 1,
+                        name: "main".to_string(),
+                    },
+                    root_main,
+                ]
+            );
+        }
+        trap => panic!("unexpected trap {:?}", trap),
+    }
+}
+
+#[test]
+fn backtraces_disabled() {
+    let code = compile_wat(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $main (export "main") (result i32)
+                (unreachable)))
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    match runtime.execute().unwrap_err() {
+        Error::Trap { backtrace, .. } => assert!(backtrace.frames.is_empty()),
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+const DEBUGGEE: &str = r#"
+    (module
+        (import "env" "eth2_return" (func $return (param i32) (param i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "\e1\10\00\00")
+        (func $helper (result i32)
+            (drop (call $return (i32.const 0) (i32.const 4)))
+            (return (i32.const 0)))
+        (func $main (export "main") (result i32)
+            (call $helper)))
+    "#;
+
+#[derive(Default)]
+struct Script {
+    stops: Rc<RefCell<Vec<Stop>>>,
+}
+
+impl Debugger for Script {
+    fn stop(&mut self, session: &mut Session, stop: &Stop) -> Action {
+        self.stops.borrow_mut().push(stop.clone());
+
+        match stop {
+            Stop::FunctionEntry { module: None, .. } => {
+                session.add_breakpoint(Breakpoint::Function("helper".to_string()));
+                Action::Continue
+            }
+            Stop::FunctionEntry {
+                module: Some(0), ..
+            } => {
+                let calls = session.call_stack();
+                assert_eq!(calls.len(), 1);
+                assert_eq!(calls[0].callee, Some(0));
+                assert_eq!(calls[0].name, "main");
+                assert_eq!(calls[0].frame.argument_offset, 14);
+
+                let frames: Vec<_> = session
+                    .backtrace()
+                    .frames
+                    .into_iter()
+                    .map(|frame| frame.name)
+                    .collect();
+                assert_eq!(frames, vec!["helper", "main", "main"]);
+
+                assert_eq!(
+                    session.read_memory(Some(0), 0, 4),
+                    Some(vec![0xe1, 0x10, 0, 0])
+                );
+                assert_eq!(session.read_memory(Some(0), 65535, 2), None);
+
+                Action::Step
+            }
+            _ => Action::Continue,
+        }
+    }
+}
+
+#[test]
+fn debugger() {
+    let code = compile_wat(DEBUGGEE);
+
+    let debugger = Script::default();
+    let stops = debugger.stops.clone();
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .debugger(debugger)
+        .build()
+        .unwrap();
+    runtime.execute().unwrap();
+
+    assert_eq!(
+        *stops.borrow(),
+        vec![
+            Stop::FunctionEntry {
+                module: None,
+                index: 6,
+                name: "main".to_string(),
+            },
+            Stop::FunctionEntry {
+                module: Some(0),
+                index: 1,
+                name: "helper".to_string(),
+            },
+            Stop::HostCall {
+                caller: Some(0),
+                name: "eth2_return".to_string(),
+                args: vec![0.into(), 4.into()],
+            },
+        ]
+    );
+}
+
+#[test]
+fn debugger_console() {
+    let code = compile_wat(DEBUGGEE);
+    let input = "h eth2_return\nl\nc\ncalls\nmem 0 4 0\nc\n";
+    let mut output = Vec::new();
+
+    {
+        let console = Console::new(input.as_bytes(), &mut output);
+        let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+            .debugger(console)
+            .build()
+            .unwrap();
+        runtime.execute().unwrap();
+    }
+
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(
+        lines,
+        vec![
+            "stopped: entering main in root (function 6)",
+            "(ewasm) (ewasm) 0: host function eth2_return",
+            "(ewasm) stopped: child 0 calling eth2_return[I32(0), I32(4)]",
+            "(ewasm) main in child 0: argument 14+4, return 18+4",
+            "(ewasm) e1100000",
+            "(ewasm) ",
+        ]
+    );
+}
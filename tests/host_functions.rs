@@ -1,9 +1,13 @@
 mod utils;
 
-use ewasm::{Execute, RootRuntime};
+use ewasm::{
+    capabilities, Divergence, Error, Execute, Invocation, MemoryConfig, MemoryUsage, RecordedCall,
+    Recording, RootRuntime,
+};
 use std::{cell::RefCell, rc::Rc};
 use utils::escape;
 use wabt::wat2wasm;
+use wasmi::{Signature, ValueType};
 
 fn nop() -> Vec<u8> {
     wat2wasm(r#"(module (func $main (export "main") (nop)))"#).unwrap()
@@ -22,6 +26,10 @@ fn compile_wat(code: &str) -> Vec<u8> {
                 (import "env" "eth2_bufferSet" (func $buffer_set (param i32) (param i32) (param i32)))
                 (import "env" "eth2_bufferMerge" (func $buffer_merge (param i32) (param i32)))
                 (import "env" "eth2_bufferClear" (func $buffer_clear (param i32)))
+                (import "env" "eth2_bufferCount" (func $buffer_count (param i32) (result i32)))
+                (import "env" "eth2_bufferKeys" (func $buffer_keys (param i32) (param i32) (param i32) (result i32)))
+                (import "env" "eth2_bufferDelete" (func $buffer_delete (param i32) (param i32) (result i32)))
+                (import "env" "eth2_bufferCopy" (func $buffer_copy (param i32) (param i32) (result i32)))
                 (import "env" "print" (func $print (param i32) (param i32)))
                 (memory (export "memory") 1)
                 (data (i32.const 1000) "hello world")
@@ -74,7 +82,303 @@ fn module_load_and_call() {
     .unwrap();
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    runtime.execute();
+    runtime.execute().unwrap();
+}
+
+const CALL_SLOT_ZERO: &str = r#"
+    (module
+        (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+        (import
+            "env"
+            "eth2_callModule"
+            (func
+                $call
+                (param i32)
+                (param i32)
+                (param i32)
+                (param i32)
+                (param i32)
+                (param i32)
+                (param i32)
+                (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 100) "main")
+        (func $main (export "main")
+            (i32.store
+                (i32.const 0)
+                (call
+                    $call
+                    (i32.const 0)   (; Slot ;)
+                    (i32.const 100) (; Name Offset ;)
+                    (i32.const 4)   (; Name Length ;)
+                    (i32.const 0)   (; Argument Offset ;)
+                    (i32.const 0)   (; Argument Length ;)
+                    (i32.const 0)   (; Return Offset ;)
+                    (i32.const 0)   (; Return Length ;)))
+            (call $save_post_root (i32.const 0))))
+    "#;
+
+const RETURN_FIVE: &str = r#"
+    (module
+        (memory (export "memory") 1)
+        (func $main (export "main") (result i32) (i32.const 5)))
+    "#;
+
+#[test]
+fn embedder_load_module() {
+    let code = wat2wasm(CALL_SLOT_ZERO).unwrap();
+    let child = wat2wasm(RETURN_FIVE).unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    runtime
+        .load_module(0, &child, capabilities::DEFAULT)
+        .unwrap();
+
+    match runtime.load_module(1, b"not wasm", capabilities::DEFAULT) {
+        Err(Error::Instantiation { slot: 1, .. }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    match runtime.load_module(0, &child, capabilities::DEFAULT) {
+        Err(Error::Instantiation { slot: 0, reason }) => {
+            assert_eq!(reason, "the slot is already in use")
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    let start = wat2wasm(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $init)
+            (start $init)
+            (func $main (export "main") (result i32) (i32.const 0)))
+        "#,
+    )
+    .unwrap();
+    match runtime.load_module(2, &start, capabilities::DEFAULT) {
+        Err(Error::Instantiation { slot: 2, reason }) => {
+            assert_eq!(
+                reason,
+                "Instantiation: modules with a start function aren't supported"
+            )
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(5));
+}
+
+#[test]
+fn invoke_export() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "env" "eth2_argument" (func $argument (param i32) (param i32) (result i32)))
+            (import "env" "eth2_return" (func $return (param i32) (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main") (nop))
+            (func $exclaim (export "exclaim") (result i32)
+                (local $len i32)
+                (local.set $len (call $argument (i32.const 0) (i32.const 32)))
+                (i32.store8 (local.get $len) (i32.const 33))
+                (drop (call $return (i32.const 0) (i32.add (local.get $len) (i32.const 1))))
+                (i32.const 7))
+            (func $fail (export "fail") (result i32) (unreachable)))
+        "#,
+    )
+    .unwrap();
+
+    let runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+
+    let invocation = runtime.invoke("exclaim", b"abc", 16).unwrap();
+    assert_eq!(
+        invocation,
+        Invocation {
+            code: 7,
+            data: b"abc!".to_vec(),
+        }
+    );
+
+    assert_eq!(runtime.invoke("exclaim", b"abc", 2).unwrap().data, b"ab");
+
+    for name in &["main", "memory", "missing"] {
+        match runtime.invoke(name, &[], 0) {
+            Err(Error::UnknownExport { .. }) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    match runtime.invoke("fail", &[], 0) {
+        Err(Error::Trap { .. }) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn view_mode() {
+    let forbidden = [
+        (
+            "eth2_savePostStateRoot",
+            "(call $save_post_root (i32.const 0))",
+        ),
+        (
+            "eth2_bufferSet",
+            "(call $buffer_set (i32.const 0) (i32.const 0) (i32.const 0))",
+        ),
+        (
+            "eth2_bufferMerge",
+            "(call $buffer_merge (i32.const 0) (i32.const 1))",
+        ),
+        ("eth2_bufferClear", "(call $buffer_clear (i32.const 0))"),
+        (
+            "eth2_bufferDelete",
+            "(drop (call $buffer_delete (i32.const 0) (i32.const 0)))",
+        ),
+        (
+            "eth2_bufferCopy",
+            "(drop (call $buffer_copy (i32.const 0) (i32.const 1)))",
+        ),
+        ("print", "(call $print (i32.const 1000) (i32.const 11))"),
+    ];
+
+    for (function, code) in forbidden.iter() {
+        let code = compile_wat(code);
+        let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+            .view(true)
+            .build()
+            .unwrap();
+
+        match runtime.execute() {
+            Err(Error::Trap { message, .. }) => {
+                assert_eq!(
+                    message,
+                    format!("{} is not permitted in view mode", function)
+                )
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    // Reads are permitted.
+    let code = compile_wat(
+        r#"
+            (call $load_pre_root (i32.const 0))
+            (drop (call $block_data_size))
+            (drop (call $buffer_get (i32.const 0) (i32.const 0) (i32.const 32)))
+            (drop (call $buffer_count (i32.const 0)))
+        "#,
+    );
+    let mut runtime = RootRuntime::builder(&code, &[1, 2], build_root(42))
+        .view(true)
+        .build()
+        .unwrap();
+    assert_eq!(runtime.execute().unwrap(), [0u8; 32]);
+}
+
+#[test]
+fn view_mode_module_load() {
+    let child = nop();
+    let code = wat2wasm(format!(
+        r#"
+        (module
+            (import "env" "eth2_loadModule" (func $load (param i32) (param i32) (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{}")
+            (func $main (export "main")
+                (call $load (i32.const 0) (i32.const 0) (i32.const {}))))
+        "#,
+        escape(&child),
+        child.len(),
+    ))
+    .unwrap();
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .view(true)
+        .build()
+        .unwrap();
+
+    match runtime.execute() {
+        Err(Error::Trap { message, .. }) => {
+            assert_eq!(message, "eth2_loadModule is not permitted in view mode")
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn view_mode_debug_print() {
+    let code = compile_debug_wat("(call $print_i32 (i32.const 42))");
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .debug(true)
+        .view(true)
+        .build()
+        .unwrap();
+
+    match runtime.execute() {
+        Err(Error::Trap { message, .. }) => {
+            assert_eq!(message, "debug_printI32 is not permitted in view mode")
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn view_mode_registered_host_function() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "env" "random" (func $random (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+                (drop (call $random (i32.const 0)))))
+        "#,
+    )
+    .unwrap();
+
+    let signature = Signature::new(&[ValueType::I32][..], Some(ValueType::I32));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .host_function("random", signature, |_, _| Ok(Some(7.into())))
+        .view(true)
+        .build()
+        .unwrap();
+
+    match runtime.execute() {
+        Err(Error::Trap { message, .. }) => {
+            assert_eq!(message, "random is not permitted in view mode")
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[cfg(feature = "wat")]
+#[test]
+fn wat_input() {
+    let mut runtime = RootRuntime::from_wat(CALL_SLOT_ZERO, &[], [0u8; 32]).unwrap();
+    runtime
+        .load_module_wat(0, RETURN_FIVE, capabilities::DEFAULT)
+        .unwrap();
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(5));
+}
+
+#[cfg(feature = "wat")]
+#[test]
+fn wat_input_error() {
+    let wat = "(module\n  (func $main (export \"main\")\n    (i33.const 0)))";
+
+    match RootRuntime::from_wat(wat, &[], [0u8; 32]) {
+        Err(Error::Wat {
+            location: Some((line, _)),
+            ..
+        }) => assert_eq!(line, 3),
+        Err(error) => panic!("unexpected error {:?}", error),
+        Ok(_) => panic!("invalid WAT compiled"),
+    }
 }
 
 #[test]
@@ -87,7 +391,7 @@ fn save_post_root() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
     assert_eq!(post_root, build_root(42));
 }
 
@@ -101,7 +405,7 @@ fn load_pre_root() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[], build_root(42));
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
     assert_eq!(post_root, build_root(42));
 }
 
@@ -116,7 +420,7 @@ fn block_data_size() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[0u8; 42], build_root(42));
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
     assert_eq!(post_root, build_root(42));
 }
 
@@ -131,7 +435,7 @@ fn block_data_copy() {
 
     let block_data = build_root(42);
     let mut runtime = RootRuntime::new(&code, &block_data, [0u8; 32]);
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
     assert_eq!(post_root, build_root(42));
 }
 
@@ -148,7 +452,7 @@ fn buffer_get_and_set() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
     assert_eq!(post_root, build_root(42));
 }
 
@@ -197,7 +501,7 @@ fn buffer_merge() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
 
     // The post root should be 1 + 3 + 4 = 8
     assert_eq!(post_root, build_root(8));
@@ -235,12 +539,99 @@ fn buffer_clear() {
     );
 
     let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
-    let post_root = runtime.execute();
+    let post_root = runtime.execute().unwrap();
 
     // The post root should be 2 - 0 = 2
     assert_eq!(post_root, build_root(2));
 }
 
+#[test]
+fn buffer_large_frame() {
+    let code = compile_wat(
+        r#"
+            (i32.store (i32.const 32) (i32.const 1))
+            (i32.store (i32.const 64) (i32.const 2))
+
+            ;; Frame 256 must not alias frame 0.
+            (call $buffer_set (i32.const 0) (i32.const 0) (i32.const 32))
+            (call $buffer_set (i32.const 256) (i32.const 0) (i32.const 64))
+
+            (drop (call $buffer_get (i32.const 0) (i32.const 0) (i32.const 96)))
+            (call $save_post_root (i32.const 96))
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(1));
+}
+
+#[test]
+fn buffer_count_and_keys() {
+    let code = compile_wat(
+        r#"
+            (i32.store (i32.const 0) (i32.const 7))
+            (i32.store (i32.const 32) (i32.const 5))
+
+            (call $buffer_set (i32.const 1) (i32.const 0) (i32.const 64))
+            (call $buffer_set (i32.const 1) (i32.const 32) (i32.const 64))
+
+            ;; Copy the keys of frame 1 to mem[128..192], sorted ascending.
+            (drop (call $buffer_keys (i32.const 1) (i32.const 128) (i32.const 64)))
+
+            ;; Store count + first key + second key at mem[256]
+            (i32.store
+                (i32.const 256)
+                (i32.add
+                    (call $buffer_count (i32.const 1))
+                    (i32.add (i32.load (i32.const 128)) (i32.load (i32.const 160)))
+                )
+            )
+
+            (call $save_post_root (i32.const 256))
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    // The post root should be 2 + 5 + 7 = 14
+    assert_eq!(post_root, build_root(14));
+}
+
+#[test]
+fn buffer_copy_and_delete() {
+    let code = compile_wat(
+        r#"
+            (i32.store (i32.const 0) (i32.const 1))
+            (i32.store (i32.const 32) (i32.const 3))
+
+            (call $buffer_set (i32.const 0) (i32.const 0) (i32.const 32))
+
+            ;; Copy frame 0 into frame 1, then delete the key from frame 0.
+            (drop (call $buffer_copy (i32.const 0) (i32.const 1)))
+            (drop (call $buffer_delete (i32.const 0) (i32.const 0)))
+
+            (drop (call $buffer_get (i32.const 0) (i32.const 0) (i32.const 64)))
+            (drop (call $buffer_get (i32.const 1) (i32.const 0) (i32.const 96)))
+
+            ;; Store the result of buffer[0,1] + buffer[1,1] at mem[128]
+            (i32.store
+                (i32.const 128)
+                (i32.add (i32.load (i32.const 64)) (i32.load (i32.const 96)))
+            )
+
+            (call $save_post_root (i32.const 128))
+        "#,
+    );
+
+    let mut runtime = RootRuntime::new(&code, &[], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    // The post root should be 0 + 3 = 3
+    assert_eq!(post_root, build_root(3));
+}
+
 #[test]
 fn print() {
     let code = compile_wat(
@@ -256,7 +647,469 @@ fn print() {
         *result.borrow_mut() = b.to_string();
     });
 
-    let _ = runtime.execute();
+    runtime.execute().unwrap();
 
     assert_eq!(*result.borrow(), "hello world");
 }
+
+#[test]
+fn memory_extra_pages() {
+    let code = compile_wat(
+        r#"
+            (i32.store (i32.const 0) (current_memory))
+            (call $save_post_root (i32.const 0))
+        "#,
+    );
+
+    let memory = MemoryConfig::builder().extra_pages(3).build();
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .memory(memory)
+        .build()
+        .unwrap();
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(4));
+}
+
+#[test]
+fn memory_limit() {
+    let code = compile_wat(
+        r#"
+            (i32.store (i32.const 0) (grow_memory (i32.const 2)))
+            (i32.store (i32.const 1) (grow_memory (i32.const 1)))
+            (call $save_post_root (i32.const 0))
+        "#,
+    );
+
+    let memory = MemoryConfig::builder().max_pages(Some(2)).build();
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .memory(memory)
+        .build()
+        .unwrap();
+
+    let execution = runtime.run().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[0] = 0xff;
+    expected[1] = 1;
+    assert_eq!(execution.post_root[..4], expected[..4]);
+
+    let usage = MemoryUsage {
+        pages: 2,
+        max_pages: Some(2),
+    };
+    assert_eq!(execution.memory, usage);
+    assert_eq!(runtime.memory_usage(), usage);
+}
+
+#[test]
+fn extra_pages_beyond_limit() {
+    let code = compile_wat("");
+
+    let memory = MemoryConfig::builder()
+        .extra_pages(2)
+        .max_pages(Some(2))
+        .build();
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .memory(memory)
+        .build()
+        .unwrap();
+
+    assert_eq!(runtime.execute(), Err(Error::ExtraPages { pages: 2 }));
+}
+
+#[test]
+fn memory_limit_below_initial() {
+    let code = compile_wat("");
+
+    let memory = MemoryConfig::builder().max_pages(Some(0)).build();
+    let result = RootRuntime::builder(&code, &[], [0u8; 32])
+        .memory(memory)
+        .build();
+
+    match result {
+        Err(Error::InvalidRoot { reason }) => {
+            assert!(reason.contains("module requests 1 pages of memory, but is limited to 0"))
+        }
+        _ => panic!("root exceeding the memory limit was loaded"),
+    }
+}
+
+#[test]
+fn host_functions_disabled() {
+    let code = compile_wat("");
+
+    let result = RootRuntime::builder(&code, &[], [0u8; 32])
+        .host_functions(capabilities::ALL & !capabilities::BUFFER)
+        .build();
+
+    match result {
+        Err(Error::InvalidRoot { reason }) => {
+            assert!(reason.contains("host function eth2_bufferGet isn't enabled"))
+        }
+        _ => panic!("root importing a disabled host function was loaded"),
+    }
+}
+
+#[test]
+fn builder_logger() {
+    let code = compile_wat(
+        r#"
+            (call $print (i32.const 1000) (i32.const 11))
+        "#,
+    );
+
+    let result = Rc::new(RefCell::new(String::new()));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .logger(|b| {
+            *result.borrow_mut() = b.to_string();
+        })
+        .build()
+        .unwrap();
+
+    runtime.execute().unwrap();
+
+    assert_eq!(*result.borrow(), "hello world");
+}
+
+#[test]
+fn registered_host_function() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "sum" (func $sum (param i32) (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "\01\02\03\04")
+            (func $main (export "main")
+                (i32.store (i32.const 100) (call $sum (i32.const 0) (i32.const 4)))
+                (call $save_post_root (i32.const 100))))
+        "#,
+    )
+    .unwrap();
+
+    let signature = Signature::new(&[ValueType::I32; 2][..], Some(ValueType::I32));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .host_function("sum", signature, |context, args| {
+            assert_eq!(context.caller(), None);
+
+            let offset: u32 = args.nth(0);
+            let length: u32 = args.nth(1);
+            let bytes = context.memory().get(offset, length as usize).unwrap();
+            let sum: u32 = bytes.iter().map(|b| u32::from(*b)).sum();
+
+            Ok(Some(sum.into()))
+        })
+        .build()
+        .unwrap();
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(10));
+}
+
+fn record() -> Recording {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "eth2_blockDataSize" (func $block_data_size (result i32)))
+            (import "env" "random" (func $random (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+                (drop (call $random (i32.const 100)))
+                (i32.store8 (i32.const 100) (call $block_data_size))
+                (call $save_post_root (i32.const 100))))
+        "#,
+    )
+    .unwrap();
+
+    let signature = Signature::new(&[ValueType::I32][..], Some(ValueType::I32));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .host_function("random", signature, |context, args| {
+            let offset: u32 = args.nth(0);
+            context.memory().set(offset, &[4u8; 32]).unwrap();
+            Ok(Some(7.into()))
+        })
+        .record(true)
+        .build()
+        .unwrap();
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root[0], 0);
+    assert_eq!(post_root[1], 4);
+
+    runtime.recording().unwrap()
+}
+
+#[test]
+fn record_and_replay() {
+    let recording = record();
+
+    assert_eq!(
+        recording.calls[0],
+        RecordedCall {
+            caller: None,
+            name: "random".to_string(),
+            args: vec![100.into()],
+            writes: vec![(100, vec![4u8; 32])],
+            result: Ok(Some(7.into())),
+        }
+    );
+
+    let mut file = Vec::new();
+    recording.write(&mut file).unwrap();
+    let recording = Recording::read(&file[..]).unwrap();
+
+    assert_eq!(recording.replay(), None);
+}
+
+#[test]
+fn replay_host_call_divergence() {
+    let mut recording = record();
+    recording.data = vec![1, 2, 3];
+
+    match recording.replay() {
+        Some(Divergence::HostCall {
+            index,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(index, 1);
+            assert_eq!(expected.unwrap().result, Ok(Some(0.into())));
+            assert_eq!(actual.unwrap().result, Ok(Some(3.into())));
+        }
+        divergence => panic!("unexpected divergence {:?}", divergence),
+    }
+}
+
+#[test]
+fn replay_outcome_divergence() {
+    let mut recording = record();
+    let recorded = recording.outcome.clone();
+    recording.outcome = Err("unreachable".to_string());
+
+    assert_eq!(
+        recording.replay(),
+        Some(Divergence::Outcome {
+            expected: Err("unreachable".to_string()),
+            actual: recorded,
+        })
+    );
+}
+
+#[test]
+#[should_panic(expected = "host function print is provided by the runtime")]
+fn registered_host_function_builtin_name() {
+    let signature = Signature::new(&[ValueType::I32; 2][..], None);
+
+    RootRuntime::builder(&nop(), &[], [0u8; 32]).host_function("print", signature, |_, _| Ok(None));
+}
+
+#[test]
+#[should_panic(expected = "host function eth2_call is provided by the runtime")]
+fn registered_host_function_child_builtin_name() {
+    let signature = Signature::new(&[ValueType::I32; 6][..], Some(ValueType::I32));
+
+    RootRuntime::builder(&nop(), &[], [0u8; 32])
+        .host_function("eth2_call", signature, |_, _| Ok(None));
+}
+
+#[test]
+#[should_panic(expected = "host function gas is provided by the runtime")]
+fn registered_host_function_gas() {
+    let signature = Signature::new(&[ValueType::I32][..], None);
+
+    RootRuntime::builder(&nop(), &[], [0u8; 32]).host_function("gas", signature, |_, _| Ok(None));
+}
+
+#[test]
+fn registered_host_function_buffer() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "env" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "eth2_bufferGet" (func $buffer_get (param i32 i32 i32) (result i32)))
+            (import "env" "stash" (func $stash (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+                (call $stash (i32.const 7))
+                (drop (call $buffer_get (i32.const 3) (i32.const 0) (i32.const 100)))
+                (call $save_post_root (i32.const 100))))
+        "#,
+    )
+    .unwrap();
+
+    let signature = Signature::new(&[ValueType::I32][..], None);
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .host_function("stash", signature, |context, args| {
+            let value: u32 = args.nth(0);
+
+            let buffer = context.buffer();
+            buffer.set(3, [0u8; 32], build_root(value as u8));
+            assert_eq!(buffer.len(3), 1);
+
+            Ok(None)
+        })
+        .build()
+        .unwrap();
+
+    let post_root = runtime.execute().unwrap();
+    assert_eq!(post_root, build_root(7));
+}
+
+#[test]
+fn versioned_namespaces() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "eth2_v1" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (import "env" "eth2_blockDataCopy" (func $copy_v1 (param i32) (param i32) (param i32)))
+            (import "eth2_v2" "eth2_blockDataCopy" (func $copy_v2 (param i32) (param i32) (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+                (; v1 copies data[1..3] ;)
+                (call $copy_v1 (i32.const 0) (i32.const 1) (i32.const 3))
+                (; v2 copies data[1..4] ;)
+                (call $copy_v2 (i32.const 2) (i32.const 1) (i32.const 3))
+                (call $save_post_root (i32.const 0))))
+        "#,
+    )
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[1, 2, 3, 4], [0u8; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    let mut expected = [0u8; 32];
+    expected[..5].copy_from_slice(&[2, 3, 2, 3, 4]);
+    assert_eq!(post_root, expected);
+}
+
+#[test]
+fn block_data_copy_v2_out_of_bounds() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "eth2_v2" "eth2_blockDataCopy" (func $copy (param i32) (param i32) (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")
+                (call $copy (i32.const 0) (i32.const 2) (i32.const 3))))
+        "#,
+    )
+    .unwrap();
+
+    let mut runtime = RootRuntime::new(&code, &[1, 2, 3, 4], [0u8; 32]);
+    assert!(runtime.execute().is_err());
+}
+
+#[test]
+#[should_panic(expected = "Module eth2_v9 not found")]
+fn unknown_namespace() {
+    let code = wat2wasm(
+        r#"
+        (module
+            (import "eth2_v9" "eth2_savePostStateRoot" (func $save_post_root (param i32)))
+            (memory (export "memory") 1)
+            (func $main (export "main")))
+        "#,
+    )
+    .unwrap();
+
+    RootRuntime::new(&code, &[], [0u8; 32]);
+}
+
+fn compile_debug_wat(code: &str) -> Vec<u8> {
+    wat2wasm(
+        [
+            r#"
+            (module
+                (import "env" "eth2_bufferSet" (func $buffer_set (param i32) (param i32) (param i32)))
+                (import "debug" "debug_printMem" (func $print_mem (param i32) (param i32)))
+                (import "debug" "debug_printMemHex" (func $print_mem_hex (param i32) (param i32)))
+                (import "debug" "debug_printI32" (func $print_i32 (param i32)))
+                (import "debug" "debug_printI64" (func $print_i64 (param i64)))
+                (import "debug" "debug_printBuffer" (func $print_buffer (param i32)))
+                (import "debug" "debug_abort" (func $abort (param i32) (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 1000) "hello world")
+                (func $main (export "main")
+            "#,
+            code,
+            r#"))"#,
+        ]
+        .concat(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn debug_namespace() {
+    let code = compile_debug_wat(
+        r#"
+            (call $print_mem (i32.const 1000) (i32.const 5))
+            (call $print_mem_hex (i32.const 1000) (i32.const 2))
+            (call $print_i32 (i32.const -7))
+            (call $print_i64 (i64.const 1099511627776))
+            (i32.store8 (i32.const 63) (i32.const 1))
+            (call $buffer_set (i32.const 3) (i32.const 0) (i32.const 32))
+            (call $print_buffer (i32.const 3))
+        "#,
+    );
+
+    let messages = Rc::new(RefCell::new(Vec::new()));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .debug(true)
+        .logger(|message| messages.borrow_mut().push(message.to_string()))
+        .build()
+        .unwrap();
+
+    runtime.execute().unwrap();
+
+    let mut value = "00".repeat(31);
+    value.push_str("01");
+
+    let expected = vec![
+        "hello".to_string(),
+        "6865".to_string(),
+        "-7".to_string(),
+        "1099511627776".to_string(),
+        "buffer frame 3:".to_string(),
+        format!("  {}: {}", "00".repeat(32), value),
+    ];
+
+    assert_eq!(*messages.borrow(), expected);
+}
+
+#[test]
+fn debug_abort() {
+    let code = compile_debug_wat(
+        r#"
+            (call $abort (i32.const 1000) (i32.const 11))
+        "#,
+    );
+
+    let messages = Rc::new(RefCell::new(Vec::new()));
+
+    let mut runtime = RootRuntime::builder(&code, &[], [0u8; 32])
+        .debug(true)
+        .logger(|message| messages.borrow_mut().push(message.to_string()))
+        .build()
+        .unwrap();
+
+    let error = runtime.execute().unwrap_err();
+
+    assert_eq!(*messages.borrow(), vec!["abort: hello world".to_string()]);
+    assert!(error.to_string().contains("aborted: hello world"));
+}
+
+#[test]
+#[should_panic(expected = "Module debug not found")]
+fn debug_namespace_disabled() {
+    let code = compile_debug_wat("");
+
+    RootRuntime::new(&code, &[], [0u8; 32]);
+}
@@ -0,0 +1,72 @@
+//! Builds the example EEs of the guest SDK to wasm and runs them. The tests
+//! need the `wasm32-unknown-unknown` target, so they're ignored by default:
+//!
+//! ```text
+//! rustup target add wasm32-unknown-unknown
+//! cargo test --test sdk -- --ignored
+//! ```
+
+use ewasm::{Execute, RootRuntime};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+/// Returns the compiled example `name`, building all examples of the SDK the
+/// first time it's called.
+fn example(name: &str) -> Vec<u8> {
+    static BUILD: Once = Once::new();
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target: PathBuf = root.join("target").join("sdk");
+
+    BUILD.call_once(|| {
+        // The interpreter only supports the MVP feature set.
+        let status = Command::new(env!("CARGO"))
+            .current_dir(root)
+            .env("RUSTFLAGS", "-C target-cpu=mvp")
+            .args(["build", "--release", "--examples", "-p", "ewasm-sdk"])
+            .args(["--target", "wasm32-unknown-unknown", "--target-dir"])
+            .arg(&target)
+            .status()
+            .expect("cargo to run");
+
+        assert!(status.success(), "building the SDK examples failed");
+    });
+
+    let path = target
+        .join("wasm32-unknown-unknown/release/examples")
+        .join(name)
+        .with_extension("wasm");
+
+    fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[test]
+#[ignore]
+fn buffer_example() {
+    let code = example("buffer");
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&[2; 32]);
+    data.extend_from_slice(&[0xbb; 32]);
+    data.extend_from_slice(&[1; 32]);
+    data.extend_from_slice(&[0xaa; 32]);
+
+    let mut runtime = RootRuntime::new(&code, &data, [0x0f; 32]);
+    assert_eq!(runtime.execute().unwrap(), [0xa5; 32]);
+}
+
+#[test]
+#[ignore]
+fn modules_example() {
+    let code = example("modules");
+    let child = example("child");
+
+    let mut runtime = RootRuntime::new(&code, &child, [0; 32]);
+    let post_root = runtime.execute().unwrap();
+
+    assert_eq!(&post_root[..5], b"hello");
+    assert_eq!(post_root[31], 42);
+}